@@ -0,0 +1,75 @@
+use crate::{
+    db::advance_cycle,
+    error::{EngineError, EngineResult},
+    extange::Exchange,
+    production::{MachineReport, ProdInstance, company_ids},
+};
+use rusqlite::Connection;
+
+/// What moving the world on to a new cycle did.
+#[derive(Debug, Default)]
+pub struct CycleReport {
+    /// The cycle the world is now in.
+    pub cycle: u32,
+    /// Orders whose time in force ran out, cancelled and refunded.
+    pub expired: usize,
+    /// What each company with machines got out of them.
+    pub machines: Vec<(u32, MachineReport)>,
+    /// Workers whose employment ended, by company.
+    pub left: Vec<(u32, Vec<u32>)>,
+    /// Companies whose start of the cycle failed, left as they were.
+    pub failed: Vec<(u32, EngineError)>,
+}
+
+/// Moves the world on to the next cycle and does what is due as it starts:
+/// orders that have run out of time are taken off the book and refunded,
+/// then every company lets go of workers whose employment has ended and
+/// runs its machines.
+///
+/// Each company is loaded, updated and saved on its own, so one that fails
+/// is reported in [`CycleReport::failed`] without holding up the rest. Save
+/// any copies you hold first and reload them afterwards.
+pub fn next_cycle(conn: &Connection, exchange: &mut Exchange) -> EngineResult<CycleReport> {
+    let cycle = advance_cycle(conn)?;
+    let mut report = CycleReport {
+        cycle,
+        expired: exchange.sweep_expired(conn, cycle)?,
+        ..CycleReport::default()
+    };
+    for id in company_ids(conn)? {
+        match start_cycle(conn, id, cycle) {
+            Ok((left, machines)) => {
+                if !left.is_empty() {
+                    report.left.push((id, left));
+                }
+                if let Some(machines) = machines {
+                    report.machines.push((id, machines));
+                }
+            }
+            Err(e) => report.failed.push((id, e)),
+        }
+    }
+    Ok(report)
+}
+
+// Ends one company's finished contracts and runs its machines, saving it if
+// anything changed. Returns who left and, if it has machines, what they did.
+fn start_cycle(
+    conn: &Connection,
+    id: u32,
+    cycle: u32,
+) -> EngineResult<(Vec<u32>, Option<MachineReport>)> {
+    let Some(mut company) = ProdInstance::load(conn, id)? else {
+        return Ok((Vec::new(), None));
+    };
+    let left = company.end_contracts(cycle);
+    let machines = if company.machines.is_empty() {
+        None
+    } else {
+        Some(company.run_machines()?)
+    };
+    if !left.is_empty() || machines.is_some() {
+        company.save(conn)?;
+    }
+    Ok((left, machines))
+}
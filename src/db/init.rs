@@ -0,0 +1,25 @@
+use crate::{db::migrate, error::EngineResult};
+use rusqlite::Connection;
+use std::path::Path;
+
+/// Opens (or creates) the world stored at `path` and brings its schema up
+/// to date.
+pub fn init_db(path: impl AsRef<Path>) -> EngineResult<Connection> {
+    let conn = Connection::open(path)?;
+    let _ = conn.query_row("PRAGMA journal_mode = WAL;", [], |_row| Ok(()));
+    prepare(conn)
+}
+
+/// A fresh world that lives only as long as the connection, with the full
+/// schema applied. Every call gets its own isolated database, which is what
+/// tests and throwaway simulation runs want.
+pub fn init_memory_db() -> EngineResult<Connection> {
+    prepare(Connection::open_in_memory()?)
+}
+
+fn prepare(conn: Connection) -> EngineResult<Connection> {
+    // Enable foreign key support (very important in SQLite)
+    conn.execute("PRAGMA foreign_keys = ON;", [])?;
+    migrate(&conn)?;
+    Ok(conn)
+}
@@ -0,0 +1,349 @@
+use crate::{
+    db::atomic,
+    error::{EngineError, EngineResult},
+};
+use rusqlite::Connection;
+
+/// One step in the schema's history. Versions start at 1 and must be
+/// consecutive; the applied version is kept in `PRAGMA user_version`.
+pub struct Migration {
+    pub version: u32,
+    pub name: &'static str,
+    pub sql: &'static str,
+    /// Only run `sql` when this says the database needs it. The version is
+    /// recorded either way.
+    pub only_if: Option<fn(&Connection) -> rusqlite::Result<bool>>,
+}
+
+/// Every migration, oldest first. Never edit one that has shipped; add a new
+/// one instead.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial schema",
+        sql: include_str!("migrations/0001_initial.sql"),
+        only_if: None,
+    },
+    Migration {
+        version: 2,
+        name: "legacy exchange",
+        sql: include_str!("migrations/0002_legacy_exchange.sql"),
+        only_if: Some(is_legacy),
+    },
+    Migration {
+        version: 3,
+        name: "legacy companies",
+        sql: include_str!("migrations/0003_legacy_companies.sql"),
+        only_if: Some(has_company_blobs),
+    },
+];
+
+/// Whether the database was created by the engine from before migrations
+/// existed. Its order table never had a `seq` column, and amounts were
+/// stored in whole dollars rather than [`Money`](crate::money::Money)
+/// thousandths.
+pub fn is_legacy(conn: &Connection) -> rusqlite::Result<bool> {
+    let seq: u32 = conn.query_row(
+        "SELECT COUNT(*) FROM pragma_table_info('extchange') WHERE name = 'seq'",
+        [],
+        |row| row.get(0),
+    )?;
+    Ok(seq == 0)
+}
+
+/// Whether companies still keep their state in a JSON blob in
+/// `company.data`, as they did before migration 3.
+pub fn has_company_blobs(conn: &Connection) -> rusqlite::Result<bool> {
+    let data: u32 = conn.query_row(
+        "SELECT COUNT(*) FROM pragma_table_info('company') WHERE name = 'data'",
+        [],
+        |row| row.get(0),
+    )?;
+    Ok(data > 0)
+}
+
+/// The schema version this build of the engine expects.
+pub fn latest_version() -> u32 {
+    MIGRATIONS.last().map_or(0, |migration| migration.version)
+}
+
+pub fn schema_version(conn: &Connection) -> EngineResult<u32> {
+    Ok(conn.query_row("PRAGMA user_version", [], |row| row.get(0))?)
+}
+
+/// Brings the database up to [`latest_version`], applying each pending
+/// migration in its own transaction. Refuses to touch a database written by
+/// a newer engine. Returns the version the database is now at.
+///
+/// Foreign keys are off while migrating, so a migration can rebuild a table
+/// without the drop cascading into its children. Instead each migration is
+/// rolled back if it leaves more broken references than the database had
+/// before.
+pub fn migrate(conn: &Connection) -> EngineResult<u32> {
+    let version = schema_version(conn)?;
+    let latest = latest_version();
+    if version > latest {
+        return Err(EngineError::SchemaTooNew {
+            found: version,
+            supported: latest,
+        });
+    }
+    if version == latest {
+        return Ok(latest);
+    }
+
+    let enforced: bool = conn.query_row("PRAGMA foreign_keys", [], |row| row.get(0))?;
+    conn.pragma_update(None, "foreign_keys", false)?;
+    let applied = apply_pending(conn, version);
+    conn.pragma_update(None, "foreign_keys", enforced)?;
+    applied?;
+    Ok(latest)
+}
+
+fn apply_pending(conn: &Connection, version: u32) -> EngineResult<()> {
+    let broken = broken_references(conn)?;
+    for migration in MIGRATIONS.iter().filter(|m| m.version > version) {
+        atomic(conn, || -> EngineResult<()> {
+            if migration
+                .only_if
+                .map_or(Ok(true), |applies| applies(conn))?
+            {
+                conn.execute_batch(migration.sql)?;
+            }
+            if broken_references(conn)? > broken {
+                return Err(rusqlite::Error::SqliteFailure(
+                    rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_CONSTRAINT_FOREIGNKEY),
+                    Some("migration left rows pointing at nothing".to_string()),
+                )
+                .into());
+            }
+            conn.pragma_update(None, "user_version", migration.version)?;
+            Ok(())
+        })
+        .map_err(|e| match e {
+            EngineError::Persistence(e) => EngineError::Migration {
+                version: migration.version,
+                name: migration.name,
+                source: e,
+            },
+            e => e,
+        })?;
+    }
+    Ok(())
+}
+
+// Rows whose foreign key points at a row that doesn't exist.
+fn broken_references(conn: &Connection) -> rusqlite::Result<u64> {
+    conn.query_row("SELECT COUNT(*) FROM pragma_foreign_key_check", [], |row| {
+        row.get(0)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        extange::{Exchange, OfferType},
+        ledger::check_invariants,
+        materials::Material,
+        money::Money,
+        player::Player,
+        production::{Prod, ProdInstance},
+    };
+
+    // The tables and a few rows as the engine wrote them before migrations
+    // existed.
+    const BASELINE: &str = r#"
+        CREATE TABLE user (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            username TEXT UNIQUE NOT NULL,
+            password_hash TEXT NOT NULL,
+            energy INTEGER NOT NULL DEFAULT 50,
+            usd INTEGER NOT NULL DEFAULT 0,
+            data TEXT
+        );
+        CREATE TABLE company (
+            id INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            owner TEXT NOT NULL,
+            type TEXT NOT NULL,
+            data TEXT
+        );
+        CREATE TABLE extchange (
+            id INTEGER PRIMARY KEY,
+            item TEXT NOT NULL,
+            type BOOLEAN NOT NULL,
+            amount INTEGER NOT NULL,
+            unit_price FLOAT NOT NULL,
+            entity INTEGER NOT NULL
+        );
+        CREATE TABLE job_offers (
+            id INTEGER PRIMARY KEY,
+            entity_id INTEGER NOT NULL,
+            FOREIGN KEY (entity_id) REFERENCES company(id)
+        );
+        INSERT INTO user (username, password_hash, usd) VALUES ('ann', '', 25);
+        INSERT INTO company VALUES (1, 'Admin Production Facility', '1', 'Water Company',
+            '{"usd":100000,"human_prod_rate":500,"max_human_workers":10,"human_workers":[[1,true]],"owns":{"grain":0,"electricity":0,"water":400,"food":0},"creates":"Water","recipe":{"inputs":{}}}');
+        INSERT INTO company VALUES (2, 'Admin Production Facility', '1', 'Food Processing Plant',
+            '{"usd":100,"human_prod_rate":5,"max_human_workers":10,"human_workers":[[1,false]],"owns":{"grain":0,"electricity":0,"water":0,"food":0},"creates":"Food","recipe":{"inputs":{"Electricity":10,"Water":5,"Grain":5}}}');
+        INSERT INTO company VALUES (3, 'Admin Production Facility', '0', 'Grain Farm',
+            '{"usd":0,"human_prod_rate":100,"max_human_workers":10,"human_workers":[[0,true],[1,false]],"owns":{},"creates":"Grain","recipe":{"inputs":{}}}');
+        INSERT INTO extchange VALUES (1, 'Water', 0, 100, 0.1000000014901161194, 1);
+        INSERT INTO extchange VALUES (2, 'Grain', 1, 50, 0.25, 2);
+        INSERT INTO extchange VALUES (3, 'Grain', 0, 10, 1.5, 1);
+        INSERT INTO extchange VALUES (4, 'Grain', 1, 500, 0.25, 2);
+    "#;
+
+    fn m(key: &str) -> Material {
+        Material::lookup(key).unwrap()
+    }
+
+    fn baseline() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("PRAGMA foreign_keys = ON;").unwrap();
+        conn.execute_batch(BASELINE).unwrap();
+        conn
+    }
+
+    #[test]
+    fn fresh_databases_are_not_legacy() {
+        let conn = Connection::open_in_memory().unwrap();
+        assert_eq!(migrate(&conn).unwrap(), latest_version());
+        assert!(!is_legacy(&conn).unwrap());
+    }
+
+    #[test]
+    fn legacy_money_is_scaled_to_thousandths() {
+        let conn = baseline();
+        assert!(is_legacy(&conn).unwrap());
+        assert_eq!(migrate(&conn).unwrap(), latest_version());
+        assert_eq!(schema_version(&conn).unwrap(), latest_version());
+
+        let water = ProdInstance::load(&conn, 1).unwrap().unwrap();
+        assert_eq!(water.usd, Money::from_dollars(100_000));
+        let player_usd: Money = conn
+            .query_row("SELECT usd FROM user WHERE username = 'ann'", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(player_usd, Money::from_dollars(25));
+    }
+
+    #[test]
+    fn legacy_orders_are_rebuilt_and_escrowed() {
+        let conn = baseline();
+        migrate(&conn).unwrap();
+        assert!(!is_legacy(&conn).unwrap());
+
+        let exchange = Exchange::load(&conn).unwrap();
+        let mut orders: Vec<_> = exchange
+            .orders()
+            .map(|order| {
+                (
+                    order.id,
+                    order.offer_type,
+                    order.item,
+                    order.quantity,
+                    order.price,
+                )
+            })
+            .collect();
+        orders.sort_by_key(|order| order.0);
+        // The grain sell had nothing behind it, and the second grain buy
+        // only gets what is left of the cash after the first.
+        assert_eq!(
+            orders,
+            vec![
+                (1, OfferType::Sell, m("Water"), 100, Money::from_milli(100)),
+                (2, OfferType::Buy, m("Grain"), 50, Money::from_milli(250)),
+                (4, OfferType::Buy, m("Grain"), 350, Money::from_milli(250)),
+            ]
+        );
+
+        let water = ProdInstance::load(&conn, 1).unwrap().unwrap();
+        assert_eq!(water.available(m("Water")), 300);
+        assert_eq!(water.reserved(m("Water")), 100);
+        let food = ProdInstance::load(&conn, 2).unwrap().unwrap();
+        assert_eq!(food.available_usd(), Money::ZERO);
+        assert_eq!(food.reserved_usd(), Money::from_dollars(100));
+
+        assert_eq!(check_invariants(&conn).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn workers_must_be_players() {
+        let conn = baseline();
+        migrate(&conn).unwrap();
+
+        // Player 0 was never saved, so only ann is still hired.
+        let ann = Player::load_by_username(&conn, "ann").unwrap().unwrap();
+        let farm = ProdInstance::load(&conn, 3).unwrap().unwrap();
+        let hired: Vec<u32> = farm
+            .human_workers
+            .iter()
+            .map(|worker| worker.player)
+            .collect();
+        assert_eq!(hired, vec![ann.id]);
+
+        let e = conn
+            .execute(
+                "INSERT INTO company_workers (company_id, player_id) VALUES (3, 999)",
+                [],
+            )
+            .unwrap_err();
+        assert_eq!(
+            e.sqlite_error().map(|e| e.extended_code),
+            Some(rusqlite::ffi::SQLITE_CONSTRAINT_FOREIGNKEY)
+        );
+    }
+
+    #[test]
+    fn legacy_owners_become_player_ids() {
+        let conn = baseline();
+        migrate(&conn).unwrap();
+
+        let ann = Player::load_by_username(&conn, "ann").unwrap().unwrap();
+        assert_eq!(ProdInstance::load(&conn, 1).unwrap().unwrap().owner, ann.id);
+        // Nobody was saved as player 0, so a placeholder takes the company.
+        let placeholder = Player::load_by_username(&conn, "legacy-owner-0")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            ProdInstance::load(&conn, 3).unwrap().unwrap().owner,
+            placeholder.id
+        );
+
+        let kind: String = conn
+            .query_row(
+                "SELECT typeof(owner) FROM company WHERE id = 1",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(kind, "integer");
+        assert!(
+            conn.execute(
+                "INSERT INTO company (name, owner, type) VALUES ('Nobody''s', 999, 'grain_farm')",
+                [],
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn legacy_type_names_become_catalog_ids() {
+        let conn = baseline();
+        migrate(&conn).unwrap();
+
+        for (id, prod) in [
+            (1, "water_company"),
+            (2, "food_processing_plant"),
+            (3, "grain_farm"),
+        ] {
+            let company = ProdInstance::load(&conn, id).unwrap().unwrap();
+            assert_eq!(company.base_type, prod);
+            assert!(Prod::lookup(&company.base_type).is_ok());
+        }
+    }
+}
@@ -0,0 +1,159 @@
+-- The current schema. Tables use IF NOT EXISTS so databases created by the
+-- old `init_db` keep their data. Those keep their old `extchange` and
+-- `company` shapes until migrations 2 and 3 convert them.
+
+-- Money columns hold INTEGER thousandths of a dollar (see `Money`).
+
+CREATE TABLE IF NOT EXISTS user (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    username TEXT UNIQUE NOT NULL,
+    password_hash TEXT NOT NULL,
+    energy INTEGER NOT NULL DEFAULT 50,
+    usd INTEGER NOT NULL DEFAULT 0,
+    data TEXT
+);
+
+-- `session` table: only a hash of each token is stored.
+CREATE TABLE IF NOT EXISTS session (
+    token_hash TEXT PRIMARY KEY,
+    user_id INTEGER NOT NULL,
+    created_at INTEGER NOT NULL,
+    expires_at INTEGER NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES user(id) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS company (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL,
+    owner INTEGER NOT NULL REFERENCES user(id),
+    type TEXT NOT NULL,
+    usd INTEGER NOT NULL DEFAULT 0,
+    reserved_usd INTEGER NOT NULL DEFAULT 0,
+    max_human_workers INTEGER NOT NULL DEFAULT 10,
+    shift_energy INTEGER NOT NULL DEFAULT 4,
+    upkeep INTEGER NOT NULL DEFAULT 0,
+    version INTEGER NOT NULL DEFAULT 0
+);
+CREATE INDEX IF NOT EXISTS company_owner ON company (owner);
+
+CREATE TABLE IF NOT EXISTS company_inventory (
+    company_id INTEGER NOT NULL,
+    item TEXT NOT NULL,
+    owned INTEGER NOT NULL DEFAULT 0,
+    reserved INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (company_id, item),
+    FOREIGN KEY (company_id) REFERENCES company(id) ON DELETE CASCADE
+);
+
+-- Rows are kept in hiring order. `ends_at` is the cycle the contract ends,
+-- or NULL while it runs until someone gives notice.
+CREATE TABLE IF NOT EXISTS company_workers (
+    company_id INTEGER NOT NULL,
+    player_id INTEGER NOT NULL,
+    worked BOOLEAN NOT NULL DEFAULT 0,
+    wage_kind TEXT NOT NULL DEFAULT 'shift',
+    wage INTEGER NOT NULL DEFAULT 0,
+    duration INTEGER,
+    notice INTEGER NOT NULL DEFAULT 0,
+    hired_at INTEGER NOT NULL DEFAULT 0,
+    ends_at INTEGER,
+    PRIMARY KEY (company_id, player_id),
+    FOREIGN KEY (company_id) REFERENCES company(id) ON DELETE CASCADE,
+    FOREIGN KEY (player_id) REFERENCES user(id) ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS company_workers_player ON company_workers (player_id);
+
+-- Wages a company couldn't pay when they were earned. Kept after the
+-- worker leaves, until the company pays up.
+CREATE TABLE IF NOT EXISTS company_wages_owed (
+    company_id INTEGER NOT NULL,
+    player_id INTEGER NOT NULL,
+    owed INTEGER NOT NULL,
+    PRIMARY KEY (company_id, player_id),
+    FOREIGN KEY (company_id) REFERENCES company(id) ON DELETE CASCADE,
+    FOREIGN KEY (player_id) REFERENCES user(id) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS company_recipe_input (
+    company_id INTEGER NOT NULL,
+    item TEXT NOT NULL,
+    amount INTEGER NOT NULL,
+    PRIMARY KEY (company_id, item),
+    FOREIGN KEY (company_id) REFERENCES company(id) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS company_recipe_output (
+    company_id INTEGER NOT NULL,
+    item TEXT NOT NULL,
+    amount INTEGER NOT NULL,
+    PRIMARY KEY (company_id, item),
+    FOREIGN KEY (company_id) REFERENCES company(id) ON DELETE CASCADE
+);
+
+-- A company can run several machines of the same kind, each with its own
+-- age, so rows are kept in installation order rather than keyed by item.
+CREATE TABLE IF NOT EXISTS company_machines (
+    company_id INTEGER NOT NULL,
+    item TEXT NOT NULL,
+    age INTEGER NOT NULL DEFAULT 0,
+    FOREIGN KEY (company_id) REFERENCES company(id) ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS company_machines_company ON company_machines (company_id);
+
+CREATE TABLE IF NOT EXISTS extchange (
+    id INTEGER PRIMARY KEY,
+    item TEXT NOT NULL,
+    type BOOLEAN NOT NULL,
+    amount INTEGER NOT NULL,
+    unit_price INTEGER NOT NULL,
+    entity INTEGER NOT NULL,
+    expires_at INTEGER,
+    seq INTEGER NOT NULL,
+    created_at TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS stop_orders (
+    id INTEGER PRIMARY KEY,
+    item TEXT NOT NULL,
+    type BOOLEAN NOT NULL,
+    amount INTEGER NOT NULL,
+    trigger_price INTEGER NOT NULL,
+    limit_price INTEGER,
+    budget INTEGER,
+    entity INTEGER NOT NULL,
+    created_at TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS job_offers (
+    id INTEGER PRIMARY KEY,
+    entity_id INTEGER NOT NULL,
+    FOREIGN KEY (entity_id) REFERENCES company(id)
+);
+
+CREATE TABLE IF NOT EXISTS trades (
+    id INTEGER PRIMARY KEY,
+    buyer INTEGER NOT NULL,
+    seller INTEGER NOT NULL,
+    item TEXT NOT NULL,
+    amount INTEGER NOT NULL,
+    unit_price INTEGER NOT NULL,
+    cycle INTEGER NOT NULL,
+    created_at TEXT NOT NULL
+);
+
+-- `ledger` table: accounts are keys like `company:3:goods:Grain`
+-- (see `Account`), and each row moves `amount` from `credit` to `debit`.
+CREATE TABLE IF NOT EXISTS ledger (
+    id INTEGER PRIMARY KEY,
+    cycle INTEGER NOT NULL,
+    reason TEXT NOT NULL,
+    debit TEXT NOT NULL,
+    credit TEXT NOT NULL,
+    amount INTEGER NOT NULL,
+    created_at TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS game_state (
+    key TEXT PRIMARY KEY,
+    value INTEGER NOT NULL
+);
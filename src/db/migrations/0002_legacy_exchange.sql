@@ -0,0 +1,127 @@
+-- Converts a database from the original engine, from before migrations
+-- existed, into the current shape. Only runs on those databases; see
+-- `is_legacy`.
+--
+-- Money was stored as whole dollars: the company balance in its JSON blob,
+-- the player balance and the price of each order. Everything is thousandths
+-- of a dollar now.
+--
+-- The order table is rebuilt into the current shape, and each order's id
+-- doubles as its time priority. The old engine didn't hold anything back
+-- for resting orders, so their goods and cash are moved into escrow now,
+-- oldest order first. An order the company can't cover is cut down to what
+-- it can, or dropped.
+--
+-- The old engine kept no ledger either, so every balance gets an opening
+-- grant from the world for `check_invariants` to replay.
+
+ALTER TABLE company ADD COLUMN version INTEGER NOT NULL DEFAULT 0;
+
+UPDATE company SET data = json_set(data,
+    '$.usd', CAST(ROUND(COALESCE(json_extract(data, '$.usd'), 0) * 1000) AS INTEGER),
+    '$.reserved_usd', 0)
+WHERE json_valid(data);
+UPDATE user SET usd = usd * 1000;
+UPDATE extchange SET unit_price = ROUND(unit_price * 1000);
+
+CREATE TABLE extchange_new (
+    id INTEGER PRIMARY KEY,
+    item TEXT NOT NULL,
+    type BOOLEAN NOT NULL,
+    amount INTEGER NOT NULL,
+    unit_price INTEGER NOT NULL,
+    entity INTEGER NOT NULL,
+    expires_at INTEGER,
+    seq INTEGER NOT NULL,
+    created_at TEXT NOT NULL
+);
+
+INSERT INTO extchange_new (id, item, type, amount, unit_price, entity, seq, created_at)
+SELECT id, item, 0, MIN(amount, left), unit_price, entity, id,
+       strftime('%Y-%m-%dT%H:%M:%S+00:00', 'now')
+FROM (
+    SELECT e.id, e.item, e.amount, e.entity,
+           CAST(e.unit_price AS INTEGER) AS unit_price,
+           COALESCE(json_extract(c.data, '$.owns.' || lower(e.item)), 0)
+               - (SUM(e.amount) OVER (PARTITION BY e.entity, e.item ORDER BY e.id) - e.amount)
+               AS left
+    FROM extchange e
+    JOIN company c ON c.id = e.entity AND json_valid(c.data)
+    WHERE NOT e.type AND e.amount > 0
+)
+WHERE left > 0;
+
+INSERT INTO extchange_new (id, item, type, amount, unit_price, entity, seq, created_at)
+SELECT id, item, 1,
+       CASE WHEN unit_price = 0 THEN amount ELSE MIN(amount, left / unit_price) END,
+       unit_price, entity, id, strftime('%Y-%m-%dT%H:%M:%S+00:00', 'now')
+FROM (
+    SELECT e.id, e.item, e.amount, e.entity,
+           CAST(e.unit_price AS INTEGER) AS unit_price,
+           json_extract(c.data, '$.usd')
+               - (SUM(e.amount * CAST(e.unit_price AS INTEGER))
+                      OVER (PARTITION BY e.entity ORDER BY e.id)
+                  - e.amount * CAST(e.unit_price AS INTEGER)) AS left
+    FROM extchange e
+    JOIN company c ON c.id = e.entity AND json_valid(c.data)
+    WHERE e.type AND e.amount > 0 AND e.unit_price >= 0
+)
+WHERE unit_price = 0 OR left >= unit_price;
+
+DROP TABLE extchange;
+ALTER TABLE extchange_new RENAME TO extchange;
+
+UPDATE company SET data = json_patch(data, json_object(
+    'owns', json(held.owns),
+    'reserved', json(held.reserved)))
+FROM (
+    SELECT entity,
+           json_group_object(key, owned - amount) AS owns,
+           json_group_object(key, reserved + amount) AS reserved
+    FROM (
+        SELECT e.entity, lower(e.item) AS key, SUM(e.amount) AS amount,
+               COALESCE(json_extract(c.data, '$.owns.' || lower(e.item)), 0) AS owned,
+               COALESCE(json_extract(c.data, '$.reserved.' || lower(e.item)), 0) AS reserved
+        FROM extchange e
+        JOIN company c ON c.id = e.entity
+        WHERE NOT e.type
+        GROUP BY e.entity, e.item
+    )
+    GROUP BY entity
+) AS held
+WHERE held.entity = company.id;
+
+UPDATE company SET data = json_set(data,
+    '$.usd', json_extract(data, '$.usd') - held.cost,
+    '$.reserved_usd', held.cost)
+FROM (
+    SELECT entity, SUM(amount * unit_price) AS cost
+    FROM extchange WHERE type GROUP BY entity
+) AS held
+WHERE held.entity = company.id;
+
+INSERT INTO ledger (cycle, reason, debit, credit, amount, created_at)
+SELECT 0, 'grant', debit, credit, amount, strftime('%Y-%m-%dT%H:%M:%S+00:00', 'now')
+FROM (
+    SELECT 'company:' || id || ':cash' AS debit, 'world:cash' AS credit,
+           json_extract(data, '$.usd') AS amount
+    FROM company WHERE json_valid(data)
+    UNION ALL
+    SELECT 'company:' || id || ':escrow', 'world:cash', json_extract(data, '$.reserved_usd')
+    FROM company WHERE json_valid(data)
+    UNION ALL
+    SELECT 'company:' || company.id || ':goods:' || upper(substr(owns.key, 1, 1)) || substr(owns.key, 2),
+           'world:goods:' || upper(substr(owns.key, 1, 1)) || substr(owns.key, 2),
+           owns.value
+    FROM company, json_each(company.data, '$.owns') AS owns
+    WHERE json_valid(company.data)
+    UNION ALL
+    SELECT 'company:' || company.id || ':escrow:' || upper(substr(held.key, 1, 1)) || substr(held.key, 2),
+           'world:goods:' || upper(substr(held.key, 1, 1)) || substr(held.key, 2),
+           held.value
+    FROM company, json_each(company.data, '$.reserved') AS held
+    WHERE json_valid(company.data)
+    UNION ALL
+    SELECT 'player:' || id || ':cash', 'world:cash', usd FROM user
+)
+WHERE amount > 0;
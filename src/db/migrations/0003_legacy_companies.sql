@@ -0,0 +1,95 @@
+-- Companies used to keep their state in a `data` JSON blob. It moves into
+-- columns and child tables so it can be queried. Inventory keys in the blob
+-- were lowercase field names (`grain`); material keys are capitalised
+-- (`Grain`). The single good a company created becomes its recipe's output.
+--
+-- Types were the building's display name, like 'Water Company'. They become
+-- catalog ids, like newly founded companies use. Companies from before the
+-- catalog keep the old fixed shift cost and pay no upkeep.
+--
+-- Owners were kept as text. They become player ids the database checks.
+-- Companies whose owner never had a saved player, like the old demo's admin
+-- with id 0, are handed to a placeholder player named after the old owner.
+-- Placeholders have no password, so nobody can log in as them. Workers who
+-- were never saved as players are dropped: there is nobody to employ.
+-- Those who remain keep working for nothing, with no end date.
+
+UPDATE company SET data = '{}' WHERE data IS NULL OR NOT json_valid(data);
+
+INSERT OR IGNORE INTO user (username, password_hash)
+SELECT DISTINCT 'legacy-owner-' || owner, ''
+FROM company
+WHERE NOT EXISTS (SELECT 1 FROM user WHERE user.id = company.owner);
+
+CREATE TABLE company_new (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL,
+    owner INTEGER NOT NULL REFERENCES user(id),
+    type TEXT NOT NULL,
+    usd INTEGER NOT NULL DEFAULT 0,
+    reserved_usd INTEGER NOT NULL DEFAULT 0,
+    max_human_workers INTEGER NOT NULL DEFAULT 10,
+    shift_energy INTEGER NOT NULL DEFAULT 4,
+    upkeep INTEGER NOT NULL DEFAULT 0,
+    version INTEGER NOT NULL DEFAULT 0
+);
+
+INSERT INTO company_new
+    (id, name, owner, type, usd, reserved_usd, max_human_workers, version)
+SELECT id, name,
+       COALESCE(
+           (SELECT user.id FROM user WHERE user.id = company.owner),
+           (SELECT user.id FROM user WHERE user.username = 'legacy-owner-' || company.owner)
+       ),
+       CASE type
+           WHEN 'Water Company' THEN 'water_company'
+           WHEN 'Power Plant' THEN 'power_plant'
+           WHEN 'Grain Farm' THEN 'grain_farm'
+           WHEN 'Food Processing Plant' THEN 'food_processing_plant'
+           ELSE type
+       END,
+       COALESCE(json_extract(data, '$.usd'), 0),
+       COALESCE(json_extract(data, '$.reserved_usd'), 0),
+       COALESCE(json_extract(data, '$.max_human_workers'), 10),
+       version
+FROM company;
+
+INSERT INTO company_inventory (company_id, item, owned, reserved)
+SELECT company_id, item, SUM(owned), SUM(reserved)
+FROM (
+    SELECT company.id AS company_id,
+           upper(substr(owns.key, 1, 1)) || substr(owns.key, 2) AS item,
+           owns.value AS owned,
+           0 AS reserved
+    FROM company, json_each(company.data, '$.owns') AS owns
+    UNION ALL
+    SELECT company.id,
+           upper(substr(reserved.key, 1, 1)) || substr(reserved.key, 2),
+           0,
+           reserved.value
+    FROM company, json_each(company.data, '$.reserved') AS reserved
+)
+GROUP BY company_id, item
+HAVING SUM(owned) > 0 OR SUM(reserved) > 0;
+
+INSERT OR IGNORE INTO company_workers (company_id, player_id, worked)
+SELECT company.id,
+       json_extract(worker.value, '$[0]'),
+       COALESCE(json_extract(worker.value, '$[1]'), 0)
+FROM company, json_each(company.data, '$.human_workers') AS worker
+WHERE json_extract(worker.value, '$[0]') IN (SELECT id FROM user)
+ORDER BY company.id, worker.key;
+
+INSERT INTO company_recipe_input (company_id, item, amount)
+SELECT company.id, input.key, input.value
+FROM company, json_each(company.data, '$.recipe.inputs') AS input
+WHERE input.value > 0;
+
+INSERT INTO company_recipe_output (company_id, item, amount)
+SELECT id, json_extract(data, '$.creates'), json_extract(data, '$.human_prod_rate')
+FROM company
+WHERE json_extract(data, '$.creates') != '' AND json_extract(data, '$.human_prod_rate') > 0;
+
+DROP TABLE company;
+ALTER TABLE company_new RENAME TO company;
+CREATE INDEX company_owner ON company (owner);
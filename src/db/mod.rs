@@ -0,0 +1,3 @@
+use crate::flatten_modules;
+
+flatten_modules!(init, migrations, state);
@@ -0,0 +1,49 @@
+use rusqlite::{Connection, OptionalExtension, Result};
+
+/// The current game cycle. A fresh world starts at cycle 0.
+pub fn current_cycle(conn: &Connection) -> Result<u32> {
+    let cycle: Option<u32> = conn
+        .query_row(
+            "SELECT value FROM game_state WHERE key = 'cycle'",
+            [],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(cycle.unwrap_or(0))
+}
+
+/// Moves the world on to the next cycle and returns its number.
+pub fn advance_cycle(conn: &Connection) -> Result<u32> {
+    bump_counter(conn, "cycle").map(|cycle| cycle as u32)
+}
+
+fn bump_counter(conn: &Connection, key: &str) -> Result<i64> {
+    conn.query_row(
+        "INSERT INTO game_state (key, value) VALUES (?1, 1)
+         ON CONFLICT(key) DO UPDATE SET value = value + 1
+         RETURNING value",
+        [key],
+        |row| row.get(0),
+    )
+}
+
+/// Runs `f` inside a SAVEPOINT so every write it makes commits or rolls back
+/// as one unit. Savepoints nest, so this is safe to call from code that is
+/// already running inside another `atomic` block.
+pub fn atomic<T, E>(conn: &Connection, f: impl FnOnce() -> Result<T, E>) -> Result<T, E>
+where
+    E: From<rusqlite::Error>,
+{
+    conn.execute_batch("SAVEPOINT atomic;")?;
+    match f() {
+        Ok(value) => {
+            conn.execute_batch("RELEASE atomic;")?;
+            Ok(value)
+        }
+        Err(e) => {
+            // Keep the original error even if the rollback itself fails.
+            let _ = conn.execute_batch("ROLLBACK TO atomic; RELEASE atomic;");
+            Err(e)
+        }
+    }
+}
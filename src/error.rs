@@ -0,0 +1,208 @@
+use crate::{materials::Material, money::Money};
+use std::fmt;
+
+/// Everything a game operation can fail with. The game server matches on
+/// these to pick a message for the player.
+#[derive(Debug)]
+pub enum EngineError {
+    InsufficientFunds {
+        needed: Money,
+        available: Money,
+    },
+    InsufficientMaterial {
+        item: Material,
+        needed: u32,
+        available: u32,
+    },
+    NotHired {
+        player: u32,
+    },
+    AlreadyHired {
+        player: u32,
+    },
+    /// The company already employs as many workers as it has room for.
+    NoVacancy {
+        max: u32,
+    },
+    AlreadyWorked {
+        player: u32,
+    },
+    NoEnergy {
+        player: u32,
+        needed: u8,
+        available: u8,
+    },
+    /// A material key that isn't in the material list.
+    UnknownMaterial(String),
+    CompanyNotFound(u32),
+    /// No live order on the book has this id.
+    OrderNotFound(i64),
+    /// The company was saved from another copy after this one was loaded,
+    /// or its row is gone.
+    StaleCompany(u32),
+    /// The building's tier may not use this good as an input.
+    ForbiddenInput {
+        item: Material,
+        prod: String,
+    },
+    /// A production type id that isn't in the catalog.
+    UnknownProd(String),
+    /// The material isn't a machine that fits this type of building.
+    UnsuitableMachine {
+        item: Material,
+        prod: String,
+    },
+    PlayerNotFound(u32),
+    /// Another player already has this username.
+    UsernameTaken(String),
+    /// Wrong username or password. Deliberately doesn't say which.
+    InvalidCredentials,
+    /// The password hasher itself failed.
+    PasswordHashing(String),
+    /// The company has never been saved, so it has no id yet.
+    UnsavedCompany,
+    /// A share change that would leave a negative holding.
+    InvalidShares {
+        company: u32,
+        held: i16,
+        change: i16,
+    },
+    /// An order that can never be placed, whatever the balances.
+    InvalidOrder(String),
+    /// The offer went through, leaving `resting_id` on the book if anything,
+    /// but stop orders it triggered failed to run. They stay parked.
+    StopsFailed {
+        resting_id: Option<i64>,
+        failed: Vec<(i64, EngineError)>,
+    },
+    /// A stored row that can't be turned back into a value.
+    CorruptData(String),
+    /// A game data file (materials, buildings) that doesn't describe a
+    /// valid catalog.
+    InvalidDefinition(String),
+    /// A money amount that doesn't fit in [`Money`].
+    MoneyOverflow,
+    /// More of a material than one inventory can count.
+    MaterialOverflow(Material),
+    /// The database was written by a newer version of the engine.
+    SchemaTooNew {
+        found: u32,
+        supported: u32,
+    },
+    /// A schema migration failed and was rolled back.
+    Migration {
+        version: u32,
+        name: &'static str,
+        source: rusqlite::Error,
+    },
+    Persistence(rusqlite::Error),
+}
+
+pub type EngineResult<T> = Result<T, EngineError>;
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineError::InsufficientFunds { needed, available } => {
+                write!(f, "Needs {} but only {} is available", needed, available)
+            }
+            EngineError::InsufficientMaterial {
+                item,
+                needed,
+                available,
+            } => write!(
+                f,
+                "Needs {} {:?} but only {} is available",
+                needed, item, available
+            ),
+            EngineError::NotHired { player } => write!(f, "Player {} is not hired here.", player),
+            EngineError::AlreadyHired { player } => {
+                write!(f, "Player {} is already hired here!", player)
+            }
+            EngineError::NoVacancy { max } => {
+                write!(f, "All {} worker places are taken.", max)
+            }
+            EngineError::AlreadyWorked { player } => {
+                write!(f, "Player {} has already worked this cycle.", player)
+            }
+            EngineError::NoEnergy {
+                player,
+                needed,
+                available,
+            } => write!(
+                f,
+                "Player {} needs {} energy but only has {}.",
+                player, needed, available
+            ),
+            EngineError::UnknownMaterial(key) => write!(f, "Unknown material key: {}", key),
+            EngineError::CompanyNotFound(id) => write!(f, "Company {} does not exist.", id),
+            EngineError::OrderNotFound(id) => write!(f, "Order {} does not exist.", id),
+            EngineError::StaleCompany(id) => {
+                write!(f, "Company {} was changed elsewhere; reload it.", id)
+            }
+            EngineError::ForbiddenInput { item, prod } => {
+                write!(f, "{} may not use {:?} as an input.", prod, item)
+            }
+            EngineError::UnknownProd(id) => write!(f, "Unknown production type: {}", id),
+            EngineError::UnsuitableMachine { item, prod } => {
+                write!(f, "{:?} is not a machine for {}.", item, prod)
+            }
+            EngineError::PlayerNotFound(id) => write!(f, "Player {} does not exist.", id),
+            EngineError::UsernameTaken(name) => {
+                write!(f, "Username {} is already taken.", name)
+            }
+            EngineError::InvalidCredentials => write!(f, "Invalid username or password."),
+            EngineError::PasswordHashing(reason) => {
+                write!(f, "Password hashing failed: {}", reason)
+            }
+            EngineError::UnsavedCompany => write!(f, "Company has not been saved yet."),
+            EngineError::InvalidShares {
+                company,
+                held,
+                change,
+            } => write!(
+                f,
+                "Cannot change {} shares of company {} by {}",
+                held, company, change
+            ),
+            EngineError::InvalidOrder(reason) => write!(f, "Invalid order: {}", reason),
+            EngineError::StopsFailed { failed, .. } => {
+                write!(f, "{} triggered stop order(s) failed:", failed.len())?;
+                for (id, e) in failed {
+                    write!(f, " stop {}: {};", id, e)?;
+                }
+                Ok(())
+            }
+            EngineError::CorruptData(reason) => write!(f, "Corrupt data: {}", reason),
+            EngineError::InvalidDefinition(reason) => write!(f, "Invalid definition: {}", reason),
+            EngineError::MoneyOverflow => write!(f, "Money amount out of range."),
+            EngineError::MaterialOverflow(item) => write!(f, "Too much {:?} to hold.", item),
+            EngineError::SchemaTooNew { found, supported } => write!(
+                f,
+                "Database schema version {} is newer than the supported version {}.",
+                found, supported
+            ),
+            EngineError::Migration {
+                version,
+                name,
+                source,
+            } => write!(f, "Migration {} ({}) failed: {}", version, name, source),
+            EngineError::Persistence(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for EngineError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            EngineError::Persistence(e) | EngineError::Migration { source: e, .. } => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<rusqlite::Error> for EngineError {
+    fn from(e: rusqlite::Error) -> Self {
+        EngineError::Persistence(e)
+    }
+}
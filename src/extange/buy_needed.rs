@@ -1,57 +1,372 @@
-use crate::{materials::*, production::ProdInstance};
-use rusqlite::{Connection, OptionalExtension, params};
+use super::*;
+use crate::{
+    db::current_cycle, error::EngineResult, materials::*, money::Money, production::ProdInstance,
+};
+use rusqlite::Connection;
+use std::collections::HashMap;
+
+/// Caps on what [`ProdInstance::buy_needed`] may pay for one material.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ProcurementLimit {
+    /// Most cash to spend on the material in total.
+    pub max_spend: Option<Money>,
+    /// Highest ask worth buying from.
+    pub max_unit_price: Option<Money>,
+}
+
+/// Why a material could not be bought in full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shortfall {
+    /// The ask side ran out.
+    NoSellers,
+    /// The remaining asks are above the maximum unit price.
+    PriceLimit,
+    /// The next ask would go over the spend limit or the available cash.
+    Budget,
+    /// The next ask belongs to the buyer's own owner.
+    OwnOrders,
+}
+
+/// What happened to one recipe input.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProcuredMaterial {
+    pub item: Material,
+    /// Units missing from inventory before buying.
+    pub needed: u32,
+    pub bought: u32,
+    pub spent: Money,
+    pub shortfall: Option<Shortfall>,
+}
+
+/// Outcome of a [`ProdInstance::buy_needed`] run, one entry per recipe
+/// input.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ProcurementReport {
+    pub materials: Vec<ProcuredMaterial>,
+}
+
+impl ProcurementReport {
+    /// Whether every input was bought in full.
+    pub fn is_complete(&self) -> bool {
+        self.materials.iter().all(|line| line.bought >= line.needed)
+    }
+
+    pub fn total_spent(&self) -> EngineResult<Money> {
+        self.materials
+            .iter()
+            .try_fold(Money::ZERO, |total, line| total.try_add(line.spent))
+    }
+}
+
+/// Units to buy at each price level, cheapest first.
+type PurchasePlan = Vec<(Money, u32)>;
 
 impl ProdInstance {
-    pub fn buy_needed(&mut self, conn: &Connection, units_worth_of: u32) {
-        // Calculate total needed quantities for inputs
-        for (mat, amt_per_unit) in self.recipe.clone().inputs.iter() {
-            let needed_total = amt_per_unit.checked_mul(units_worth_of).unwrap_or(u32::MAX);
-
-            // How much do we currently have?
-            let current_owned = match mat {
-                Material::Electricity => self.owns.electricity,
-                Material::Water => self.owns.water,
-                Material::Grain => self.owns.grain,
-                Material::Food => self.owns.food,
-            };
+    /// Buys whatever is missing to produce `units_worth_of` units, walking
+    /// the ask side of each input from the cheapest seller up. Materials
+    /// without an entry in `limits` are bought with no price or spend cap
+    /// beyond the cash on hand.
+    ///
+    /// Without a price cap the input is bought with one immediate-or-cancel
+    /// market order. With one, each planned price level is bought with an
+    /// immediate-or-cancel limit at that price, so nothing can fill above
+    /// the cap and no more cash is locked than the level costs.
+    pub fn buy_needed(
+        &mut self,
+        conn: &Connection,
+        exchange: &mut Exchange,
+        units_worth_of: u32,
+        limits: &HashMap<Material, ProcurementLimit>,
+    ) -> EngineResult<ProcurementReport> {
+        let cycle = current_cycle(conn)?;
+        let mut report = ProcurementReport::default();
 
-            // Calculate how much we still need to buy
-            if needed_total <= current_owned {
-                // We have enough, skip this material
+        for &(item, amt_per_unit) in self.recipe.clone().inputs.iter() {
+            let needed_total = amt_per_unit.saturating_mul(units_worth_of);
+            let needed = needed_total.saturating_sub(self.available(item));
+            if needed == 0 {
                 continue;
             }
 
-            let need_to_buy = needed_total - current_owned;
+            let limit = limits.get(&item).copied().unwrap_or_default();
+            let budget = limit
+                .max_spend
+                .map_or(self.available_usd(), |max| max.min(self.available_usd()));
+            let (levels, mut shortfall) =
+                self.plan_purchase(exchange, item, needed, budget, &limit, cycle)?;
+            let quantity: u32 = levels.iter().map(|&(_, take)| take).sum();
 
-            // Find minimum price for this material on the market
-            let mut stmt = match conn.prepare(
-                "SELECT unit_price FROM extchange WHERE item = ?1 AND type = 1 ORDER BY unit_price ASC LIMIT 1"
-            ) {
-                Ok(s) => s,
-                Err(e) => {
-                    eprintln!("DB prepare failed for min price lookup: {}", e);
-                    continue;
-                }
+            let mut line = ProcuredMaterial {
+                item,
+                needed,
+                bought: 0,
+                spent: Money::ZERO,
+                shortfall,
             };
-
-            let min_price_res: rusqlite::Result<Option<f32>> = stmt
-                .query_row(params![mat.to_string_key()], |row| row.get(0))
-                .optional();
-
-            let min_price = match min_price_res {
-                Ok(Some(p)) => p,
-                Ok(None) => {
-                    eprintln!("No buy offers found for {:?}, skipping", mat);
-                    continue;
+            if quantity > 0 {
+                let (owned_before, usd_before) = (self.available(item), self.available_usd());
+                let orders = match limit.max_unit_price {
+                    None => vec![(
+                        OrderKind::Market {
+                            budget: Some(budget),
+                        },
+                        quantity,
+                    )],
+                    Some(_) => levels
+                        .iter()
+                        .map(|&(price, take)| (OrderKind::Limit(price), take))
+                        .collect(),
+                };
+                for (kind, quantity) in orders {
+                    let mut offer = Offer {
+                        entity: EntityRef::Borrowed(self),
+                        conn,
+                        item,
+                        quantity,
+                        kind,
+                        offer_type: OfferType::Buy,
+                        time_in_force: TimeInForce::ImmediateOrCancel,
+                        self_trade: SelfTradePrevention::default(),
+                    };
+                    offer.execute(exchange)?;
                 }
-                Err(e) => {
-                    eprintln!("Failed to query min price: {}", e);
-                    continue;
+
+                line.bought = self.available(item).saturating_sub(owned_before);
+                line.spent = usd_before.try_sub(self.available_usd())?;
+                if line.bought < needed && shortfall.is_none() {
+                    shortfall = Some(Shortfall::NoSellers);
                 }
-            };
+                line.shortfall = shortfall;
+            }
+            report.materials.push(line);
+        }
+        Ok(report)
+    }
 
-            // Place a quick buy order for the needed amount at the min price
-            self.quick_buy(conn, *mat, min_price, need_to_buy);
+    /// Walks the asks for `item` in price-time order and works out how many
+    /// of the `needed` units can be bought within `budget` and the limit's
+    /// price cap, and what stopped it short.
+    fn plan_purchase(
+        &self,
+        exchange: &Exchange,
+        item: Material,
+        needed: u32,
+        budget: Money,
+        limit: &ProcurementLimit,
+        cycle: u32,
+    ) -> EngineResult<(PurchasePlan, Option<Shortfall>)> {
+        let Some(book) = exchange.book(item) else {
+            return Ok((Vec::new(), Some(Shortfall::NoSellers)));
+        };
+
+        let mut levels = PurchasePlan::new();
+        let mut quantity = 0;
+        let mut budget_left = budget;
+        let mut shortfall = Some(Shortfall::NoSellers);
+        for ask in book.crossing(OfferType::Buy, Money::MAX) {
+            if quantity == needed {
+                shortfall = None;
+                break;
+            }
+            if !ask.is_live(cycle) {
+                continue;
+            }
+            if limit.max_unit_price.is_some_and(|max| ask.price > max) {
+                shortfall = Some(Shortfall::PriceLimit);
+                break;
+            }
+            if Some(ask.entity) == self.id || ask.owner == self.owner {
+                shortfall = Some(Shortfall::OwnOrders);
+                break;
+            }
+            let take = (needed - quantity)
+                .min(ask.quantity)
+                .min(budget_left.units_at(ask.price));
+            if take > 0 {
+                match levels.last_mut() {
+                    Some((price, taken)) if *price == ask.price => *taken += take,
+                    _ => levels.push((ask.price, take)),
+                }
+            }
+            quantity += take;
+            budget_left = budget_left.try_sub(ask.price.try_mul(take)?)?;
+            if take < ask.quantity && quantity < needed {
+                shortfall = Some(Shortfall::Budget);
+                break;
+            }
+        }
+        if quantity == needed {
+            shortfall = None;
         }
+        Ok((levels, shortfall))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::init_memory_db,
+        ledger::check_invariants,
+        player::Player,
+        production::{Prod, ProdInstance},
+    };
+
+    // Rests an ask for `quantity` of `item` at `dollars` from a new company
+    // owned by `owner`.
+    fn ask(
+        conn: &Connection,
+        exchange: &mut Exchange,
+        owner: &mut Player,
+        item: Material,
+        dollars: u32,
+        quantity: u32,
+    ) -> i64 {
+        let base = Prod::lookup("grain_farm").unwrap();
+        let mut farm = ProdInstance::new(conn, base, "Farm".to_string(), owner).unwrap();
+        farm.add_material(item, quantity).unwrap();
+        farm.save(conn).unwrap();
+        farm.quick_sell(conn, exchange, item, Money::from_dollars(dollars), quantity)
+            .unwrap()
+            .unwrap()
+    }
+
+    fn plant(conn: &Connection, owner: &mut Player, cash: Money) -> ProdInstance {
+        let base = Prod::lookup("food_processing_plant").unwrap();
+        let mut plant = ProdInstance::new(conn, base, "Plant".to_string(), owner).unwrap();
+        plant.earn(cash).unwrap();
+        plant.save(conn).unwrap();
+        plant
+    }
+
+    fn m(key: &str) -> Material {
+        Material::lookup(key).unwrap()
+    }
+
+    #[test]
+    fn each_input_reports_what_stopped_it() {
+        let conn = init_memory_db().unwrap();
+        let mut exchange = Exchange::load(&conn).unwrap();
+        let mut seller = Player::create(&conn, "seller").unwrap();
+        let mut buyer = Player::create(&conn, "buyer").unwrap();
+        seller.earn(Money::from_dollars(10_000)).unwrap();
+        buyer.earn(Money::from_dollars(10_000)).unwrap();
+
+        ask(&conn, &mut exchange, &mut seller, m("Electricity"), 1, 30);
+        ask(&conn, &mut exchange, &mut seller, m("Water"), 1, 4);
+        ask(&conn, &mut exchange, &mut seller, m("Grain"), 1, 6);
+        let dear = ask(&conn, &mut exchange, &mut seller, m("Grain"), 3, 10);
+
+        let mut plant = plant(&conn, &mut buyer, Money::from_dollars(100));
+        let limits = HashMap::from([
+            (
+                m("Electricity"),
+                ProcurementLimit {
+                    max_spend: Some(Money::from_dollars(5)),
+                    max_unit_price: None,
+                },
+            ),
+            (
+                m("Grain"),
+                ProcurementLimit {
+                    max_spend: None,
+                    max_unit_price: Some(Money::from_dollars(2)),
+                },
+            ),
+        ]);
+        // Two batches need 20 electricity, 10 water and 10 grain.
+        let report = plant.buy_needed(&conn, &mut exchange, 2, &limits).unwrap();
+
+        let line = |item, needed, bought, dollars, shortfall| ProcuredMaterial {
+            item,
+            needed,
+            bought,
+            spent: Money::from_dollars(dollars),
+            shortfall: Some(shortfall),
+        };
+        assert_eq!(
+            report.materials,
+            vec![
+                line(m("Electricity"), 20, 5, 5, Shortfall::Budget),
+                line(m("Water"), 10, 4, 4, Shortfall::NoSellers),
+                line(m("Grain"), 10, 6, 6, Shortfall::PriceLimit),
+            ]
+        );
+        assert!(!report.is_complete());
+        assert_eq!(report.total_spent().unwrap(), Money::from_dollars(15));
+        assert_eq!(plant.available_usd(), Money::from_dollars(85));
+        assert_eq!(plant.reserved_usd(), Money::ZERO);
+        assert_eq!(plant.available(m("Grain")), 6);
+        assert_eq!(exchange.order(dear).unwrap().quantity, 10);
+        assert_eq!(check_invariants(&conn).unwrap(), vec![]);
+
+        // What is already there isn't bought again.
+        let report = plant.buy_needed(&conn, &mut exchange, 1, &limits).unwrap();
+        let needed: Vec<_> = report
+            .materials
+            .iter()
+            .map(|line| (line.item, line.needed))
+            .collect();
+        assert_eq!(needed, vec![(m("Electricity"), 5), (m("Water"), 1)]);
+    }
+
+    #[test]
+    fn own_asks_stop_the_walk_and_capped_levels_lock_only_what_they_cost() {
+        let conn = init_memory_db().unwrap();
+        let mut exchange = Exchange::load(&conn).unwrap();
+        let mut seller = Player::create(&conn, "seller").unwrap();
+        let mut buyer = Player::create(&conn, "buyer").unwrap();
+        seller.earn(Money::from_dollars(10_000)).unwrap();
+        buyer.earn(Money::from_dollars(10_000)).unwrap();
+
+        ask(&conn, &mut exchange, &mut seller, m("Grain"), 1, 3);
+        ask(&conn, &mut exchange, &mut seller, m("Grain"), 2, 2);
+        let own = ask(&conn, &mut exchange, &mut buyer, m("Grain"), 3, 5);
+        let beyond = ask(&conn, &mut exchange, &mut seller, m("Grain"), 4, 10);
+
+        // Exactly enough cash for the two cheap levels. A single limit at the
+        // cap would have to lock $25 for the five units.
+        let mut plant = plant(&conn, &mut buyer, Money::from_dollars(7));
+        plant.add_material(m("Electricity"), 10).unwrap();
+        plant.add_material(m("Water"), 5).unwrap();
+        let limits = HashMap::from([(
+            m("Grain"),
+            ProcurementLimit {
+                max_spend: None,
+                max_unit_price: Some(Money::from_dollars(5)),
+            },
+        )]);
+        let report = plant.buy_needed(&conn, &mut exchange, 2, &limits).unwrap();
+
+        assert_eq!(
+            report.materials,
+            vec![
+                ProcuredMaterial {
+                    item: m("Electricity"),
+                    needed: 10,
+                    bought: 0,
+                    spent: Money::ZERO,
+                    shortfall: Some(Shortfall::NoSellers),
+                },
+                ProcuredMaterial {
+                    item: m("Water"),
+                    needed: 5,
+                    bought: 0,
+                    spent: Money::ZERO,
+                    shortfall: Some(Shortfall::NoSellers),
+                },
+                ProcuredMaterial {
+                    item: m("Grain"),
+                    needed: 10,
+                    bought: 5,
+                    spent: Money::from_dollars(7),
+                    shortfall: Some(Shortfall::OwnOrders),
+                },
+            ]
+        );
+        assert_eq!(plant.available_usd(), Money::ZERO);
+        assert_eq!(exchange.order(own).unwrap().quantity, 5);
+        assert_eq!(exchange.order(beyond).unwrap().quantity, 10);
+        assert_eq!(check_invariants(&conn).unwrap(), vec![]);
     }
 }
@@ -2,7 +2,7 @@ use crate::production::ProdInstance;
 
 #[derive(Debug)]
 pub enum EntityRef<'a> {
-    Owned(ProdInstance),
+    Owned(Box<ProdInstance>),
     Borrowed(&'a mut ProdInstance),
 }
 
@@ -17,13 +17,13 @@ impl<'a> EntityRef<'a> {
     pub fn as_mut(&mut self) -> &mut ProdInstance {
         match self {
             EntityRef::Owned(inst) => inst,
-            EntityRef::Borrowed(inst) => *inst,
+            EntityRef::Borrowed(inst) => inst,
         }
     }
 
     pub fn into_owned(self) -> ProdInstance {
         match self {
-            EntityRef::Owned(inst) => inst,
+            EntityRef::Owned(inst) => *inst,
             EntityRef::Borrowed(inst) => inst.clone(), // requires Clone
         }
     }
@@ -0,0 +1,538 @@
+use super::*;
+use crate::{db::atomic, error::EngineResult, materials::Material, money::Money};
+use rusqlite::{Connection, OptionalExtension, params};
+use std::{
+    collections::{BTreeMap, HashMap},
+    ops::{Deref, DerefMut},
+};
+
+/// A change to the book or the parked stops. It is applied in memory once
+/// the trade that caused it has settled, and written to the database either
+/// right away or on the next flush when a batch is open.
+#[derive(Debug, Clone)]
+pub enum BookChange {
+    Insert(RestingOrder),
+    Fill { id: i64, quantity: u32 },
+    Remove(i64),
+    InsertStop(StopOrder),
+    RemoveStop(i64),
+}
+
+/// The in-memory order books for every material, plus the stop orders
+/// waiting to trigger.
+///
+/// Matching never touches `extchange` or `stop_orders`: they are only read
+/// once by [`Exchange::load`] and then kept up to date as a write-behind
+/// copy.
+pub struct Exchange {
+    books: HashMap<Material, OrderBook>,
+    index: HashMap<i64, (Material, OfferType, Money)>,
+    pub(super) stops: BTreeMap<i64, StopOrder>,
+    next_seq: i64,
+    pending: Vec<BookChange>,
+    batching: bool,
+}
+
+impl Exchange {
+    /// Rebuilds the books from `extchange` and the stops from `stop_orders`.
+    pub fn load(conn: &Connection) -> EngineResult<Self> {
+        let mut exchange = Exchange {
+            books: HashMap::new(),
+            index: HashMap::new(),
+            stops: BTreeMap::new(),
+            next_seq: 1,
+            pending: Vec::new(),
+            batching: false,
+        };
+
+        let orders: Vec<RestingOrder> = {
+            let mut stmt = conn.prepare(
+                "SELECT e.id, e.entity, c.owner, e.type, e.item, e.amount,
+                        e.unit_price, e.seq, e.expires_at
+                 FROM extchange e
+                 JOIN company c ON c.id = e.entity
+                 ORDER BY e.seq ASC",
+            )?;
+            stmt.query_map([], |row| {
+                let item_str: String = row.get(4)?;
+                let item = Material::from_str(&item_str).ok_or_else(|| {
+                    rusqlite::Error::FromSqlConversionFailure(
+                        4,
+                        rusqlite::types::Type::Text,
+                        format!("Invalid material name in DB: {}", item_str).into(),
+                    )
+                })?;
+                Ok(RestingOrder {
+                    id: row.get(0)?,
+                    entity: row.get(1)?,
+                    owner: row.get(2)?,
+                    offer_type: OfferType::from(row.get::<_, bool>(3)?),
+                    item,
+                    quantity: row.get(5)?,
+                    price: row.get(6)?,
+                    seq: row.get(7)?,
+                    expires_at: row.get(8)?,
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?
+        };
+
+        let stops: Vec<StopOrder> = {
+            let mut stmt = conn.prepare(
+                "SELECT id, entity, type, item, amount, trigger_price, limit_price, budget
+                 FROM stop_orders
+                 ORDER BY id ASC",
+            )?;
+            stmt.query_map([], |row| {
+                let item_str: String = row.get(3)?;
+                let item = Material::from_str(&item_str).ok_or_else(|| {
+                    rusqlite::Error::FromSqlConversionFailure(
+                        3,
+                        rusqlite::types::Type::Text,
+                        format!("Invalid material name in DB: {}", item_str).into(),
+                    )
+                })?;
+                let trigger: Money = row.get(5)?;
+                let kind = match row.get::<_, Option<Money>>(6)? {
+                    Some(limit) => OrderKind::StopLimit { trigger, limit },
+                    None => OrderKind::Stop {
+                        trigger,
+                        budget: row.get(7)?,
+                    },
+                };
+                Ok(StopOrder {
+                    id: row.get(0)?,
+                    entity: row.get(1)?,
+                    offer_type: OfferType::from(row.get::<_, bool>(2)?),
+                    item,
+                    quantity: row.get(4)?,
+                    kind,
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?
+        };
+
+        let stored_seq: Option<i64> = conn
+            .query_row(
+                "SELECT value FROM game_state WHERE key = 'order_seq'",
+                [],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let max_seq = orders
+            .iter()
+            .map(|order| order.seq)
+            .chain(stops.iter().map(|stop| stop.id))
+            .max();
+        exchange.next_seq = stored_seq.max(max_seq).unwrap_or(0) + 1;
+
+        for order in orders {
+            exchange.apply_in_memory(&BookChange::Insert(order));
+        }
+        for stop in stops {
+            exchange.apply_in_memory(&BookChange::InsertStop(stop));
+        }
+        Ok(exchange)
+    }
+
+    pub fn book(&self, item: Material) -> Option<&OrderBook> {
+        self.books.get(&item)
+    }
+
+    pub fn order(&self, id: i64) -> Option<&RestingOrder> {
+        let (item, offer_type, price) = self.index.get(&id)?;
+        self.books.get(item)?.get(*offer_type, *price, id)
+    }
+
+    pub fn orders(&self) -> impl Iterator<Item = &RestingOrder> {
+        self.books.values().flat_map(|book| book.orders())
+    }
+
+    /// Hands out the id for a new resting or stop order. Ids double as the
+    /// order's time priority.
+    pub(super) fn next_order_id(&mut self) -> i64 {
+        let id = self.next_seq;
+        self.next_seq += 1;
+        id
+    }
+
+    /// Starts a batch: book changes are kept in memory and everything the
+    /// exchange writes stays in one open savepoint until
+    /// [`ExchangeBatch::flush`]. Dropping the batch without flushing it
+    /// rolls all of that back.
+    pub fn begin_batch<'a>(&'a mut self, conn: &'a Connection) -> EngineResult<ExchangeBatch<'a>> {
+        if !self.batching {
+            conn.execute_batch("SAVEPOINT exchange_batch;")?;
+            self.batching = true;
+        }
+        Ok(ExchangeBatch {
+            exchange: self,
+            conn,
+        })
+    }
+
+    /// Writes the pending book changes and commits the open batch. If that
+    /// fails the whole batch is rolled back and the books are reloaded.
+    fn flush(&mut self, conn: &Connection) -> EngineResult<()> {
+        if !self.batching {
+            return Ok(());
+        }
+
+        let pending = std::mem::take(&mut self.pending);
+        let result = write_changes(conn, &pending)
+            .and_then(|_| Ok(conn.execute_batch("RELEASE exchange_batch;")?));
+        self.batching = false;
+
+        if let Err(e) = result {
+            let _ = conn.execute_batch("ROLLBACK TO exchange_batch; RELEASE exchange_batch;");
+            *self = Exchange::load(conn)?;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Rolls back everything written since the batch opened and reloads the
+    /// books, dropping the pending changes.
+    fn abort_batch(&mut self, conn: &Connection) -> EngineResult<()> {
+        if !self.batching {
+            return Ok(());
+        }
+        self.pending.clear();
+        self.batching = false;
+        conn.execute_batch("ROLLBACK TO exchange_batch; RELEASE exchange_batch;")?;
+        *self = Exchange::load(conn)?;
+        Ok(())
+    }
+
+    /// Persists `changes` unless a batch is open, in which case they wait
+    /// for the flush. Call from inside the savepoint that settles them.
+    pub(super) fn stage(&self, conn: &Connection, changes: &[BookChange]) -> EngineResult<()> {
+        if self.batching {
+            Ok(())
+        } else {
+            write_changes(conn, changes)
+        }
+    }
+
+    /// Applies settled changes to the in-memory books.
+    pub(super) fn commit(&mut self, changes: Vec<BookChange>) {
+        for change in &changes {
+            self.apply_in_memory(change);
+        }
+        if self.batching {
+            self.pending.extend(changes);
+        }
+    }
+
+    /// Queues a change that is already applied in memory for the next flush,
+    /// if a batch is open.
+    pub(super) fn defer(&mut self, change: BookChange) {
+        if self.batching {
+            self.pending.push(change);
+        }
+    }
+
+    pub(super) fn apply_in_memory(&mut self, change: &BookChange) {
+        match change {
+            BookChange::Insert(order) => {
+                self.index
+                    .insert(order.id, (order.item, order.offer_type, order.price));
+                self.books
+                    .entry(order.item)
+                    .or_default()
+                    .insert(order.clone());
+            }
+            BookChange::Fill { id, quantity } => {
+                let Some((item, offer_type, price)) = self.index.get(id).copied() else {
+                    return;
+                };
+                let book = self.books.entry(item).or_default();
+                book.fill(offer_type, price, *id, *quantity);
+                if book.get(offer_type, price, *id).is_none() {
+                    self.index.remove(id);
+                }
+            }
+            BookChange::Remove(id) => {
+                if let Some((item, offer_type, price)) = self.index.remove(id) {
+                    self.books
+                        .entry(item)
+                        .or_default()
+                        .remove(offer_type, price, *id);
+                }
+            }
+            BookChange::InsertStop(stop) => {
+                self.stops.insert(stop.id, stop.clone());
+            }
+            BookChange::RemoveStop(id) => {
+                self.stops.remove(id);
+            }
+        }
+    }
+}
+
+/// A batch open on an [`Exchange`], from [`Exchange::begin_batch`]. Offers
+/// run against it like against the exchange itself. Nothing is committed
+/// until [`ExchangeBatch::flush`]; if the batch is dropped first, say
+/// because an error was returned with `?`, it is rolled back.
+pub struct ExchangeBatch<'a> {
+    exchange: &'a mut Exchange,
+    conn: &'a Connection,
+}
+
+impl ExchangeBatch<'_> {
+    /// Writes the pending book changes and commits the batch. If that fails
+    /// the whole batch is rolled back and the books are reloaded.
+    pub fn flush(self) -> EngineResult<()> {
+        self.exchange.flush(self.conn)
+    }
+
+    /// Rolls the batch back straight away, reporting if the books couldn't
+    /// be reloaded afterwards.
+    pub fn abort(self) -> EngineResult<()> {
+        self.exchange.abort_batch(self.conn)
+    }
+}
+
+impl Deref for ExchangeBatch<'_> {
+    type Target = Exchange;
+
+    fn deref(&self) -> &Exchange {
+        self.exchange
+    }
+}
+
+impl DerefMut for ExchangeBatch<'_> {
+    fn deref_mut(&mut self) -> &mut Exchange {
+        self.exchange
+    }
+}
+
+impl Drop for ExchangeBatch<'_> {
+    fn drop(&mut self) {
+        // Does nothing once the batch was flushed or aborted. Otherwise
+        // there is nobody left to report a failed rollback to.
+        let _ = self.exchange.abort_batch(self.conn);
+    }
+}
+
+fn write_changes(conn: &Connection, changes: &[BookChange]) -> EngineResult<()> {
+    if changes.is_empty() {
+        return Ok(());
+    }
+
+    atomic(conn, || {
+        let mut max_seq = None;
+        for change in changes {
+            match change {
+                BookChange::Insert(order) => {
+                    conn.execute(
+                        "INSERT INTO extchange (id, item, type, amount, unit_price, entity, expires_at, seq, created_at)
+                         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                        params![
+                            order.id,
+                            order.item.to_string_key(),
+                            bool::from(order.offer_type),
+                            order.quantity,
+                            order.price,
+                            order.entity,
+                            order.expires_at,
+                            order.seq,
+                            chrono::Utc::now().to_rfc3339(),
+                        ],
+                    )?;
+                    max_seq = max_seq.max(Some(order.seq));
+                }
+                BookChange::Fill { id, quantity } => {
+                    conn.execute(
+                        "UPDATE extchange SET amount = amount - ?1 WHERE id = ?2",
+                        params![quantity, id],
+                    )?;
+                    conn.execute(
+                        "DELETE FROM extchange WHERE id = ?1 AND amount <= 0",
+                        params![id],
+                    )?;
+                }
+                BookChange::Remove(id) => {
+                    conn.execute("DELETE FROM extchange WHERE id = ?1", params![id])?;
+                }
+                BookChange::InsertStop(stop) => {
+                    let limit = match stop.kind {
+                        OrderKind::StopLimit { limit, .. } => Some(limit),
+                        _ => None,
+                    };
+                    conn.execute(
+                        "INSERT INTO stop_orders (id, item, type, amount, trigger_price, limit_price, budget, entity, created_at)
+                         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                        params![
+                            stop.id,
+                            stop.item.to_string_key(),
+                            bool::from(stop.offer_type),
+                            stop.quantity,
+                            stop.kind.trigger(),
+                            limit,
+                            stop.kind.budget(),
+                            stop.entity,
+                            chrono::Utc::now().to_rfc3339(),
+                        ],
+                    )?;
+                    max_seq = max_seq.max(Some(stop.id));
+                }
+                BookChange::RemoveStop(id) => {
+                    conn.execute("DELETE FROM stop_orders WHERE id = ?1", params![id])?;
+                }
+            }
+        }
+
+        // Remember the highest id handed out so ids are never reused, even
+        // after the newest orders leave the book.
+        if let Some(seq) = max_seq {
+            conn.execute(
+                "INSERT INTO game_state (key, value) VALUES ('order_seq', ?1)
+                 ON CONFLICT(key) DO UPDATE SET value = MAX(value, excluded.value)",
+                params![seq],
+            )?;
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::init_memory_db,
+        error::EngineError,
+        ledger::check_invariants,
+        player::Player,
+        production::{Prod, ProdInstance},
+    };
+
+    struct World {
+        conn: Connection,
+        grain: Material,
+        seller: ProdInstance,
+    }
+
+    fn world() -> World {
+        let conn = init_memory_db().unwrap();
+        let grain = Material::lookup("Grain").unwrap();
+        let mut owner = Player::create(&conn, "owner").unwrap();
+        owner.earn(Money::from_dollars(10_000)).unwrap();
+        let base = Prod::lookup("grain_farm").unwrap();
+        let mut seller = ProdInstance::new(&conn, base, "Farm".to_string(), &mut owner).unwrap();
+        seller.add_material(grain, 100).unwrap();
+        seller.save(&conn).unwrap();
+        World {
+            conn,
+            grain,
+            seller,
+        }
+    }
+
+    fn stored(conn: &Connection) -> Vec<(i64, u32)> {
+        let mut book: Vec<_> = Exchange::load(conn)
+            .unwrap()
+            .orders()
+            .map(|order| (order.id, order.quantity))
+            .collect();
+        book.sort();
+        book
+    }
+
+    fn reserved(conn: &Connection, company: &ProdInstance, grain: Material) -> u32 {
+        ProdInstance::load(conn, company.id.unwrap())
+            .unwrap()
+            .unwrap()
+            .reserved(grain)
+    }
+
+    #[test]
+    fn a_flushed_batch_is_written_in_one_go() {
+        let World {
+            conn,
+            grain,
+            mut seller,
+        } = world();
+        let mut exchange = Exchange::load(&conn).unwrap();
+        let price = Money::from_dollars(1);
+
+        let mut batch = exchange.begin_batch(&conn).unwrap();
+        let first = seller
+            .quick_sell(&conn, &mut batch, grain, price, 10)
+            .unwrap()
+            .unwrap();
+        let second = seller
+            .quick_sell(&conn, &mut batch, grain, price, 20)
+            .unwrap()
+            .unwrap();
+        // The book changes wait for the flush.
+        assert_eq!(stored(&conn), vec![]);
+        batch.flush().unwrap();
+
+        assert_eq!(stored(&conn), vec![(first, 10), (second, 20)]);
+        assert_eq!(exchange.orders().count(), 2);
+        assert_eq!(reserved(&conn, &seller, grain), 30);
+        assert_eq!(check_invariants(&conn).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn a_dropped_batch_is_rolled_back() {
+        let World {
+            conn,
+            grain,
+            mut seller,
+        } = world();
+        let mut exchange = Exchange::load(&conn).unwrap();
+        let price = Money::from_dollars(1);
+        let kept = seller
+            .quick_sell(&conn, &mut exchange, grain, price, 5)
+            .unwrap()
+            .unwrap();
+
+        let run = |exchange: &mut Exchange, seller: &mut ProdInstance| -> EngineResult<()> {
+            let mut batch = exchange.begin_batch(&conn)?;
+            seller.quick_sell(&conn, &mut batch, grain, price, 10)?;
+            seller.quick_sell(&conn, &mut batch, grain, price, 1_000)?;
+            batch.flush()
+        };
+        assert!(matches!(
+            run(&mut exchange, &mut seller),
+            Err(EngineError::InsufficientMaterial { .. })
+        ));
+
+        let book: Vec<_> = exchange
+            .orders()
+            .map(|order| (order.id, order.quantity))
+            .collect();
+        assert_eq!(book, vec![(kept, 5)]);
+        assert_eq!(stored(&conn), vec![(kept, 5)]);
+        assert_eq!(reserved(&conn, &seller, grain), 5);
+        assert_eq!(check_invariants(&conn).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn a_failed_flush_rolls_the_batch_back() {
+        let World {
+            conn,
+            grain,
+            mut seller,
+        } = world();
+        let mut exchange = Exchange::load(&conn).unwrap();
+        conn.execute_batch(
+            "CREATE TEMP TRIGGER no_orders BEFORE INSERT ON extchange
+             BEGIN SELECT RAISE(ABORT, 'disk full'); END;",
+        )
+        .unwrap();
+
+        let mut batch = exchange.begin_batch(&conn).unwrap();
+        seller
+            .quick_sell(&conn, &mut batch, grain, Money::from_dollars(1), 10)
+            .unwrap();
+        assert!(matches!(batch.flush(), Err(EngineError::Persistence(_))));
+
+        assert_eq!(exchange.orders().count(), 0);
+        assert_eq!(stored(&conn), vec![]);
+        assert_eq!(reserved(&conn, &seller, grain), 0);
+        assert_eq!(check_invariants(&conn).unwrap(), vec![]);
+    }
+}
@@ -0,0 +1,294 @@
+use super::*;
+use crate::{error::EngineResult, materials::Material, money::Money};
+use rusqlite::{Connection, OptionalExtension, params};
+
+/// Aggregated resting quantity at one price.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PriceLevel {
+    pub price: Money,
+    pub quantity: u32,
+    pub orders: usize,
+}
+
+/// The best `n` price levels on each side of a book.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Depth {
+    pub bids: Vec<PriceLevel>,
+    pub asks: Vec<PriceLevel>,
+}
+
+/// Open, high, low, close and volume for one material over one cycle.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Candle {
+    pub cycle: u32,
+    pub open: Money,
+    pub high: Money,
+    pub low: Money,
+    pub close: Money,
+    pub volume: u32,
+}
+
+impl Exchange {
+    /// Highest buy price still live in `cycle`.
+    pub fn best_bid(&self, item: Material, cycle: u32) -> Option<PriceLevel> {
+        self.depth(item, 1, cycle).bids.pop()
+    }
+
+    /// Lowest sell price still live in `cycle`.
+    pub fn best_ask(&self, item: Material, cycle: u32) -> Option<PriceLevel> {
+        self.depth(item, 1, cycle).asks.pop()
+    }
+
+    /// Snapshot of the best `levels` price levels on each side. Orders that
+    /// have expired by `cycle` but not been swept yet are left out.
+    pub fn depth(&self, item: Material, levels: usize, cycle: u32) -> Depth {
+        let Some(book) = self.book(item) else {
+            return Depth::default();
+        };
+
+        let side = |offer_type| {
+            book.levels(offer_type)
+                .filter_map(|(price, queue)| {
+                    let live = queue.iter().filter(|order| order.is_live(cycle));
+                    let (quantity, orders) = live.fold((0, 0), |(quantity, orders), order| {
+                        (quantity + order.quantity, orders + 1)
+                    });
+                    (orders > 0).then_some(PriceLevel {
+                        price,
+                        quantity,
+                        orders,
+                    })
+                })
+                .take(levels)
+                .collect()
+        };
+
+        Depth {
+            bids: side(OfferType::Buy),
+            asks: side(OfferType::Sell),
+        }
+    }
+}
+
+/// Price of the most recent trade in `item`.
+pub fn last_trade_price(conn: &Connection, item: Material) -> EngineResult<Option<Money>> {
+    let price = conn
+        .query_row(
+            "SELECT unit_price FROM trades WHERE item = ?1 ORDER BY id DESC LIMIT 1",
+            params![item.to_string_key()],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(price)
+}
+
+/// Volume-weighted average price of `item` over trades from `since_cycle`
+/// onwards.
+pub fn vwap(conn: &Connection, item: Material, since_cycle: u32) -> EngineResult<Option<Money>> {
+    let price = conn.query_row(
+        "SELECT CAST(ROUND(1.0 * SUM(amount * unit_price) / SUM(amount)) AS INTEGER)
+         FROM trades
+         WHERE item = ?1 AND cycle >= ?2",
+        params![item.to_string_key(), since_cycle],
+        |row| row.get(0),
+    )?;
+    Ok(price)
+}
+
+/// One candle per cycle in `from_cycle..=to_cycle` that saw trades in `item`.
+pub fn ohlcv(
+    conn: &Connection,
+    item: Material,
+    from_cycle: u32,
+    to_cycle: u32,
+) -> EngineResult<Vec<Candle>> {
+    let mut stmt = conn.prepare(
+        "SELECT cycle, unit_price, amount
+         FROM trades
+         WHERE item = ?1 AND cycle BETWEEN ?2 AND ?3
+         ORDER BY cycle ASC, id ASC",
+    )?;
+    let rows = stmt.query_map(params![item.to_string_key(), from_cycle, to_cycle], |row| {
+        Ok((
+            row.get::<_, u32>(0)?,
+            row.get::<_, Money>(1)?,
+            row.get::<_, u32>(2)?,
+        ))
+    })?;
+
+    let mut candles: Vec<Candle> = Vec::new();
+    for row in rows {
+        let (cycle, price, amount) = row?;
+        match candles.last_mut() {
+            Some(candle) if candle.cycle == cycle => {
+                candle.high = candle.high.max(price);
+                candle.low = candle.low.min(price);
+                candle.close = price;
+                candle.volume += amount;
+            }
+            _ => candles.push(Candle {
+                cycle,
+                open: price,
+                high: price,
+                low: price,
+                close: price,
+                volume: amount,
+            }),
+        }
+    }
+    Ok(candles)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::{advance_cycle, init_memory_db},
+        player::Player,
+        production::{Prod, ProdInstance},
+    };
+
+    fn farm(conn: &Connection, owner: &mut Player, grain: Material) -> ProdInstance {
+        let base = Prod::lookup("grain_farm").unwrap();
+        let mut farm = ProdInstance::new(conn, base, "Farm".to_string(), owner).unwrap();
+        farm.earn(Money::from_dollars(100)).unwrap();
+        farm.add_material(grain, 100).unwrap();
+        farm.save(conn).unwrap();
+        farm
+    }
+
+    fn level(dollars: u32, quantity: u32, orders: usize) -> PriceLevel {
+        PriceLevel {
+            price: Money::from_dollars(dollars),
+            quantity,
+            orders,
+        }
+    }
+
+    #[test]
+    fn depth_adds_up_live_orders_per_level() {
+        let conn = init_memory_db().unwrap();
+        let mut exchange = Exchange::load(&conn).unwrap();
+        let grain = Material::lookup("Grain").unwrap();
+        let mut seller = Player::create(&conn, "seller").unwrap();
+        let mut buyer = Player::create(&conn, "buyer").unwrap();
+        seller.earn(Money::from_dollars(10_000)).unwrap();
+        buyer.earn(Money::from_dollars(10_000)).unwrap();
+
+        // The only ask at $2 expires as cycle 1 starts.
+        let mut short_lived = farm(&conn, &mut seller, grain);
+        let mut offer = Offer {
+            entity: EntityRef::Borrowed(&mut short_lived),
+            conn: &conn,
+            item: grain,
+            quantity: 10,
+            kind: OrderKind::Limit(Money::from_dollars(2)),
+            offer_type: OfferType::Sell,
+            time_in_force: TimeInForce::GoodForCycles(1),
+            self_trade: SelfTradePrevention::default(),
+        };
+        offer.execute(&mut exchange).unwrap();
+        for quantity in [5, 7] {
+            farm(&conn, &mut seller, grain)
+                .quick_sell(
+                    &conn,
+                    &mut exchange,
+                    grain,
+                    Money::from_dollars(3),
+                    quantity,
+                )
+                .unwrap();
+        }
+        farm(&conn, &mut buyer, grain)
+            .quick_buy(&conn, &mut exchange, grain, Money::from_dollars(1), 4)
+            .unwrap();
+
+        assert_eq!(
+            exchange.depth(grain, 5, 0),
+            Depth {
+                bids: vec![level(1, 4, 1)],
+                asks: vec![level(2, 10, 1), level(3, 12, 2)],
+            }
+        );
+        assert_eq!(exchange.depth(grain, 1, 0).asks, vec![level(2, 10, 1)]);
+        assert_eq!(exchange.best_ask(grain, 0), Some(level(2, 10, 1)));
+        assert_eq!(exchange.best_bid(grain, 0), Some(level(1, 4, 1)));
+
+        // Until it is swept the expired ask is still on the book, but it is
+        // no longer quoted.
+        advance_cycle(&conn).unwrap();
+        assert_eq!(exchange.orders().count(), 4);
+        assert_eq!(exchange.best_ask(grain, 1), Some(level(3, 12, 2)));
+        assert_eq!(exchange.depth(grain, 5, 1).asks, vec![level(3, 12, 2)]);
+
+        let water = Material::lookup("Water").unwrap();
+        assert_eq!(exchange.depth(water, 5, 1), Depth::default());
+        assert_eq!(exchange.best_bid(water, 1), None);
+    }
+
+    #[test]
+    fn vwap_and_candles_follow_the_trade_log() {
+        let conn = init_memory_db().unwrap();
+        let mut exchange = Exchange::load(&conn).unwrap();
+        let grain = Material::lookup("Grain").unwrap();
+        let mut seller = Player::create(&conn, "seller").unwrap();
+        let mut buyer = Player::create(&conn, "buyer").unwrap();
+        seller.earn(Money::from_dollars(10_000)).unwrap();
+        buyer.earn(Money::from_dollars(10_000)).unwrap();
+
+        assert_eq!(last_trade_price(&conn, grain).unwrap(), None);
+        assert_eq!(vwap(&conn, grain, 0).unwrap(), None);
+
+        // Cycle 0: 10 at $1, then 5 at $2.
+        for price in [1, 2] {
+            farm(&conn, &mut seller, grain)
+                .quick_sell(&conn, &mut exchange, grain, Money::from_dollars(price), 10)
+                .unwrap();
+        }
+        farm(&conn, &mut buyer, grain)
+            .quick_buy(&conn, &mut exchange, grain, Money::from_dollars(2), 15)
+            .unwrap();
+
+        // Cycle 1: the other 5 at $2, then 5 at $3.
+        advance_cycle(&conn).unwrap();
+        farm(&conn, &mut seller, grain)
+            .quick_sell(&conn, &mut exchange, grain, Money::from_dollars(3), 10)
+            .unwrap();
+        farm(&conn, &mut buyer, grain)
+            .quick_buy(&conn, &mut exchange, grain, Money::from_dollars(3), 10)
+            .unwrap();
+
+        assert_eq!(
+            last_trade_price(&conn, grain).unwrap(),
+            Some(Money::from_dollars(3))
+        );
+        // 45 dollars over 25 units, then 25 dollars over 10 units.
+        assert_eq!(
+            vwap(&conn, grain, 0).unwrap(),
+            Some(Money::from_milli(1_800))
+        );
+        assert_eq!(
+            vwap(&conn, grain, 1).unwrap(),
+            Some(Money::from_milli(2_500))
+        );
+        assert_eq!(vwap(&conn, grain, 2).unwrap(), None);
+
+        let candle = |cycle, open, high, low, close, volume| Candle {
+            cycle,
+            open: Money::from_dollars(open),
+            high: Money::from_dollars(high),
+            low: Money::from_dollars(low),
+            close: Money::from_dollars(close),
+            volume,
+        };
+        assert_eq!(
+            ohlcv(&conn, grain, 0, 5).unwrap(),
+            vec![candle(0, 1, 2, 1, 2, 15), candle(1, 2, 3, 2, 3, 10)]
+        );
+        assert_eq!(
+            ohlcv(&conn, grain, 1, 1).unwrap(),
+            vec![candle(1, 2, 3, 2, 3, 10)]
+        );
+        assert_eq!(ohlcv(&conn, grain, 2, 5).unwrap(), vec![]);
+    }
+}
@@ -3,11 +3,20 @@ use crate::flatten_modules;
 flatten_modules!(
     offer,
     offer_type,
-    offer_save,
+    order_kind,
+    time_in_force,
+    self_trade,
+    offer_cancel,
+    offer_amend,
     entity_ref,
     offer_exec,
     run_offer,
     buy_needed,
     offer_exec_helpers,
-    sell_all
+    sell_all,
+    trade,
+    order_book,
+    exchange,
+    market_data,
+    stop_order
 );
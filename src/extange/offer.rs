@@ -1,6 +1,9 @@
 use crate::{
-    extange::{EntityRef, OfferType},
+    error::{EngineError, EngineResult},
+    extange::{EntityRef, OfferType, OrderKind, SelfTradePrevention, TimeInForce},
     materials::Material,
+    money::Money,
+    production::ProdInstance,
 };
 use rusqlite::Connection;
 pub struct Offer<'a, 'b> {
@@ -8,15 +11,77 @@ pub struct Offer<'a, 'b> {
     pub conn: &'b Connection,
     pub item: Material,
     pub quantity: u32,
-    pub price: f32,
+    pub kind: OrderKind,
     pub offer_type: OfferType,
+    pub time_in_force: TimeInForce,
+    pub self_trade: SelfTradePrevention,
 }
 
 impl<'a, 'b> Offer<'a, 'b> {
     pub fn valid(&self) -> bool {
-        match self.offer_type {
-            OfferType::Buy => self.entity.as_ref().usd >= self.quantity as f32 * self.price,
-            OfferType::Sell => true, // Inventory check logic can be added later
+        self.validate().is_ok()
+    }
+
+    /// Checks the entity can back the offer, without locking anything.
+    pub fn validate(&self) -> EngineResult<()> {
+        let entity = self.entity.as_ref();
+        if entity.id.is_none() {
+            return Err(EngineError::UnsavedCompany);
+        }
+        let needed_usd = match (self.offer_type, self.kind) {
+            (OfferType::Sell, _) => {
+                let available = entity.available(self.item);
+                if available < self.quantity {
+                    return Err(EngineError::InsufficientMaterial {
+                        item: self.item,
+                        needed: self.quantity,
+                        available,
+                    });
+                }
+                return Ok(());
+            }
+            (OfferType::Buy, OrderKind::Limit(price))
+            | (OfferType::Buy, OrderKind::StopLimit { limit: price, .. }) => {
+                price.try_mul(self.quantity)?
+            }
+            (OfferType::Buy, OrderKind::Market { budget }) => budget.unwrap_or(Money::ZERO),
+            (OfferType::Buy, OrderKind::Stop { budget, .. }) => budget.ok_or_else(|| {
+                EngineError::InvalidOrder("a stop buy needs a budget to escrow".to_string())
+            })?,
+        };
+        if entity.available_usd() < needed_usd {
+            return Err(EngineError::InsufficientFunds {
+                needed: needed_usd,
+                available: entity.available_usd(),
+            });
         }
+        Ok(())
+    }
+
+    /// Worst price this offer accepts when matching.
+    pub fn limit_price(&self) -> Money {
+        self.kind.limit_price(self.offer_type)
+    }
+
+    /// Whether an unfilled remainder goes on the book. Only limit orders
+    /// with a resting time in force do.
+    pub fn rests(&self) -> bool {
+        matches!(self.kind, OrderKind::Limit(_)) && self.time_in_force.rests()
+    }
+}
+
+/// Returns `quantity` units' worth of an order's escrow to `entity`: cash at
+/// the order's price for buys, the goods themselves for sells.
+pub(super) fn release_order_escrow(
+    entity: &mut ProdInstance,
+    offer_type: OfferType,
+    item: Material,
+    price: Money,
+    quantity: u32,
+) -> EngineResult<()> {
+    match offer_type {
+        OfferType::Buy => entity.release_usd(price.try_mul(quantity)?)?,
+        OfferType::Sell => entity.release_material(item, quantity)?,
     }
+    Ok(())
 }
@@ -0,0 +1,312 @@
+use super::*;
+use crate::{
+    db::{atomic, current_cycle},
+    error::{EngineError, EngineResult},
+    money::Money,
+    production::ProdInstance,
+};
+use rusqlite::Connection;
+
+impl Exchange {
+    /// Shrinks a resting order to `new_quantity`, refunding the escrow for
+    /// the units taken off. Reducing to zero cancels the order. Keeps the
+    /// order's place in the queue.
+    ///
+    /// Returns `false` if the order doesn't exist or `new_quantity` isn't a
+    /// reduction.
+    pub fn reduce(
+        &mut self,
+        conn: &Connection,
+        offer_id: i64,
+        new_quantity: u32,
+    ) -> EngineResult<bool> {
+        if new_quantity == 0 {
+            return self.cancel(conn, offer_id);
+        }
+        let Some(order) = self.order(offer_id).cloned() else {
+            return Ok(false);
+        };
+        if new_quantity >= order.quantity {
+            return Ok(false);
+        }
+
+        let changes = vec![BookChange::Fill {
+            id: offer_id,
+            quantity: order.quantity - new_quantity,
+        }];
+        atomic(conn, || {
+            let mut entity = ProdInstance::load(conn, order.entity)?
+                .ok_or(EngineError::CompanyNotFound(order.entity))?;
+            release_order_escrow(
+                &mut entity,
+                order.offer_type,
+                order.item,
+                order.price,
+                order.quantity - new_quantity,
+            )?;
+            entity.save(conn)?;
+            self.stage(conn, &changes)
+        })?;
+        self.commit(changes);
+        Ok(true)
+    }
+
+    /// Moves a resting order to a new price. The order loses its time
+    /// priority: it is taken off the book and submitted again, so it may
+    /// trade straight away if the new price crosses the spread.
+    ///
+    /// Returns the id of the new resting order, or `None` if it filled
+    /// completely at the new price. Fails with [`EngineError::OrderNotFound`]
+    /// if there is no live order with that id, and with
+    /// [`EngineError::InsufficientFunds`] if a buy can't afford the new
+    /// price; the old order then stays where it was.
+    pub fn reprice(
+        &mut self,
+        conn: &Connection,
+        offer_id: i64,
+        new_price: Money,
+    ) -> EngineResult<Option<i64>> {
+        let cycle = current_cycle(conn)?;
+        let Some(order) = self
+            .order(offer_id)
+            .filter(|order| order.is_live(cycle))
+            .cloned()
+        else {
+            return Err(EngineError::OrderNotFound(offer_id));
+        };
+
+        let mut entity = ProdInstance::load(conn, order.entity)?
+            .ok_or(EngineError::CompanyNotFound(order.entity))?;
+        release_order_escrow(
+            &mut entity,
+            order.offer_type,
+            order.item,
+            order.price,
+            order.quantity,
+        )?;
+
+        let mut offer = Offer {
+            entity: EntityRef::Owned(Box::new(entity)),
+            conn,
+            item: order.item,
+            quantity: order.quantity,
+            kind: OrderKind::Limit(new_price),
+            offer_type: order.offer_type,
+            time_in_force: match order.expires_at {
+                Some(expires_at) => TimeInForce::GoodForCycles(expires_at.saturating_sub(cycle)),
+                None => TimeInForce::GoodTillCancelled,
+            },
+            self_trade: SelfTradePrevention::default(),
+        };
+        offer.validate()?;
+
+        // Take the old order off the book first so the resubmitted one can
+        // never match against it, and put it back if anything fails.
+        let removal = BookChange::Remove(offer_id);
+        self.apply_in_memory(&removal);
+        let result = atomic(conn, || {
+            self.stage(conn, std::slice::from_ref(&removal))?;
+            offer.execute(self)
+        });
+        match result {
+            Ok(resting_id) => {
+                self.defer(removal);
+                Ok(resting_id)
+            }
+            Err(e) => {
+                self.apply_in_memory(&BookChange::Insert(order));
+                Err(e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::init_memory_db, ledger::check_invariants, materials::Material, player::Player,
+        production::Prod,
+    };
+
+    fn farm(conn: &Connection, owner: &mut Player, grain: Material) -> ProdInstance {
+        let base = Prod::lookup("grain_farm").unwrap();
+        let mut farm = ProdInstance::new(conn, base, "Farm".to_string(), owner).unwrap();
+        farm.earn(Money::from_dollars(100)).unwrap();
+        farm.add_material(grain, 100).unwrap();
+        farm.save(conn).unwrap();
+        farm
+    }
+
+    fn reload(conn: &Connection, company: &ProdInstance) -> ProdInstance {
+        ProdInstance::load(conn, company.id.unwrap())
+            .unwrap()
+            .unwrap()
+    }
+
+    struct Market {
+        conn: Connection,
+        exchange: Exchange,
+        grain: Material,
+        seller: ProdInstance,
+        buyer: ProdInstance,
+    }
+
+    fn market() -> Market {
+        let conn = init_memory_db().unwrap();
+        let exchange = Exchange::load(&conn).unwrap();
+        let grain = Material::lookup("Grain").unwrap();
+        let mut owner = Player::create(&conn, "seller").unwrap();
+        let mut other = Player::create(&conn, "buyer").unwrap();
+        owner.earn(Money::from_dollars(10_000)).unwrap();
+        other.earn(Money::from_dollars(10_000)).unwrap();
+        let seller = farm(&conn, &mut owner, grain);
+        let buyer = farm(&conn, &mut other, grain);
+        Market {
+            conn,
+            exchange,
+            grain,
+            seller,
+            buyer,
+        }
+    }
+
+    #[test]
+    fn reducing_keeps_the_queue_position_and_refunds_the_rest() {
+        let Market {
+            conn,
+            mut exchange,
+            grain,
+            mut seller,
+            mut buyer,
+        } = market();
+        let price = Money::from_dollars(1);
+        let first = seller
+            .quick_sell(&conn, &mut exchange, grain, price, 40)
+            .unwrap()
+            .unwrap();
+        let second = seller
+            .quick_sell(&conn, &mut exchange, grain, price, 40)
+            .unwrap()
+            .unwrap();
+
+        assert!(!exchange.reduce(&conn, first, 50).unwrap());
+        assert!(!exchange.reduce(&conn, 99, 1).unwrap());
+        assert!(exchange.reduce(&conn, first, 10).unwrap());
+        assert_eq!(reload(&conn, &seller).reserved(grain), 50);
+
+        // The reduced order still fills first.
+        buyer
+            .quick_buy(&conn, &mut exchange, grain, price, 15)
+            .unwrap();
+        assert!(exchange.order(first).is_none());
+        assert_eq!(exchange.order(second).unwrap().quantity, 35);
+
+        assert!(exchange.reduce(&conn, second, 0).unwrap());
+        assert!(exchange.order(second).is_none());
+        let seller = reload(&conn, &seller);
+        assert_eq!(seller.reserved(grain), 0);
+        assert_eq!(seller.available(grain), 85);
+        assert_eq!(check_invariants(&conn).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn repricing_moves_the_order_to_the_back_of_the_queue() {
+        let Market {
+            conn,
+            mut exchange,
+            grain,
+            mut seller,
+            mut buyer,
+        } = market();
+        let first = seller
+            .quick_sell(&conn, &mut exchange, grain, Money::from_dollars(2), 10)
+            .unwrap()
+            .unwrap();
+        let second = seller
+            .quick_sell(&conn, &mut exchange, grain, Money::from_dollars(1), 10)
+            .unwrap()
+            .unwrap();
+
+        let moved = exchange
+            .reprice(&conn, first, Money::from_dollars(1))
+            .unwrap()
+            .unwrap();
+        assert_ne!(moved, first);
+        assert!(exchange.order(first).is_none());
+        assert_eq!(exchange.order(moved).unwrap().price, Money::from_dollars(1));
+
+        buyer
+            .quick_buy(&conn, &mut exchange, grain, Money::from_dollars(1), 10)
+            .unwrap();
+        assert!(exchange.order(second).is_none());
+        assert_eq!(exchange.order(moved).unwrap().quantity, 10);
+        assert_eq!(check_invariants(&conn).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn repricing_into_a_resting_order_fills_it() {
+        let Market {
+            conn,
+            mut exchange,
+            grain,
+            mut seller,
+            mut buyer,
+        } = market();
+        seller
+            .quick_sell(&conn, &mut exchange, grain, Money::from_dollars(2), 10)
+            .unwrap();
+        let bid = buyer
+            .quick_buy(&conn, &mut exchange, grain, Money::from_dollars(1), 10)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(
+            exchange
+                .reprice(&conn, bid, Money::from_dollars(2))
+                .unwrap(),
+            None
+        );
+        assert_eq!(exchange.orders().count(), 0);
+        let buyer = reload(&conn, &buyer);
+        assert_eq!(buyer.available(grain), 110);
+        assert_eq!(buyer.reserved_usd(), Money::ZERO);
+        assert_eq!(buyer.available_usd(), Money::from_dollars(80));
+        assert_eq!(check_invariants(&conn).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn repricing_reports_why_it_failed() {
+        let Market {
+            conn,
+            mut exchange,
+            grain,
+            seller: _,
+            mut buyer,
+        } = market();
+        let bid = buyer
+            .quick_buy(&conn, &mut exchange, grain, Money::from_dollars(1), 10)
+            .unwrap()
+            .unwrap();
+
+        assert!(matches!(
+            exchange.reprice(&conn, 99, Money::from_dollars(1)),
+            Err(EngineError::OrderNotFound(99))
+        ));
+        assert!(matches!(
+            exchange.reprice(&conn, bid, Money::from_dollars(20)),
+            Err(EngineError::InsufficientFunds { .. })
+        ));
+
+        // The old order is untouched.
+        let order = exchange.order(bid).unwrap();
+        assert_eq!((order.quantity, order.price), (10, Money::from_dollars(1)));
+        let buyer = reload(&conn, &buyer);
+        assert_eq!(buyer.reserved_usd(), Money::from_dollars(10));
+        assert_eq!(
+            Exchange::load(&conn).unwrap().order(bid).unwrap().quantity,
+            10
+        );
+        assert_eq!(check_invariants(&conn).unwrap(), vec![]);
+    }
+}
@@ -0,0 +1,185 @@
+use super::*;
+use crate::{
+    db::atomic,
+    error::{EngineError, EngineResult},
+    production::ProdInstance,
+};
+use rusqlite::Connection;
+
+impl Exchange {
+    /// Withdraws a resting or parked stop order and refunds whatever it
+    /// still has in escrow.
+    ///
+    /// Returns `false` if no order with that id exists.
+    pub fn cancel(&mut self, conn: &Connection, offer_id: i64) -> EngineResult<bool> {
+        if let Some(stop) = self.stop_order(offer_id).cloned() {
+            return self.cancel_stop(conn, &stop).map(|_| true);
+        }
+        let Some(order) = self.order(offer_id).cloned() else {
+            return Ok(false);
+        };
+
+        let changes = vec![BookChange::Remove(offer_id)];
+        atomic(conn, || {
+            let mut entity = ProdInstance::load(conn, order.entity)?
+                .ok_or(EngineError::CompanyNotFound(order.entity))?;
+            release_order_escrow(
+                &mut entity,
+                order.offer_type,
+                order.item,
+                order.price,
+                order.quantity,
+            )?;
+            entity.save(conn)?;
+            self.stage(conn, &changes)
+        })?;
+        self.commit(changes);
+        Ok(true)
+    }
+
+    fn cancel_stop(&mut self, conn: &Connection, stop: &StopOrder) -> EngineResult<()> {
+        let changes = vec![BookChange::RemoveStop(stop.id)];
+        atomic(conn, || {
+            let mut entity = ProdInstance::load(conn, stop.entity)?
+                .ok_or(EngineError::CompanyNotFound(stop.entity))?;
+            stop.release_escrow(&mut entity)?;
+            entity.save(conn)?;
+            self.stage(conn, &changes)
+        })?;
+        self.commit(changes);
+        Ok(())
+    }
+
+    /// Cancels every order whose time in force ran out by `cycle`, returning
+    /// their escrow. Returns how many orders were swept.
+    pub fn sweep_expired(&mut self, conn: &Connection, cycle: u32) -> EngineResult<usize> {
+        let expired: Vec<i64> = self
+            .orders()
+            .filter(|order| !order.is_live(cycle))
+            .map(|order| order.id)
+            .collect();
+
+        let mut swept = 0;
+        for offer_id in expired {
+            if self.cancel(conn, offer_id)? {
+                swept += 1;
+            }
+        }
+        Ok(swept)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cycle::next_cycle, db::init_memory_db, ledger::check_invariants, materials::Material,
+        money::Money, player::Player, production::Prod,
+    };
+
+    fn farm(conn: &Connection, owner: &mut Player, grain: Material) -> ProdInstance {
+        let base = Prod::lookup("grain_farm").unwrap();
+        let mut farm = ProdInstance::new(conn, base, "Farm".to_string(), owner).unwrap();
+        farm.earn(Money::from_dollars(100)).unwrap();
+        farm.add_material(grain, 100).unwrap();
+        farm.save(conn).unwrap();
+        farm
+    }
+
+    // The resting orders as (id, quantity), after checking the stored book
+    // agrees with the one in memory.
+    fn book(conn: &Connection, exchange: &Exchange) -> Vec<(i64, u32)> {
+        let sorted = |exchange: &Exchange| {
+            let mut book: Vec<_> = exchange
+                .orders()
+                .map(|order| (order.id, order.quantity))
+                .collect();
+            book.sort();
+            book
+        };
+        let book = sorted(exchange);
+        assert_eq!(sorted(&Exchange::load(conn).unwrap()), book);
+        book
+    }
+
+    fn reload(conn: &Connection, company: &ProdInstance) -> ProdInstance {
+        ProdInstance::load(conn, company.id.unwrap())
+            .unwrap()
+            .unwrap()
+    }
+
+    #[test]
+    fn cancelling_refunds_the_escrow() {
+        let conn = init_memory_db().unwrap();
+        let mut exchange = Exchange::load(&conn).unwrap();
+        let grain = Material::lookup("Grain").unwrap();
+        let mut owner = Player::create(&conn, "owner").unwrap();
+        owner.earn(Money::from_dollars(10_000)).unwrap();
+
+        let mut company = farm(&conn, &mut owner, grain);
+        let bid = company
+            .quick_buy(&conn, &mut exchange, grain, Money::from_dollars(1), 30)
+            .unwrap()
+            .unwrap();
+        let ask = company
+            .quick_sell(&conn, &mut exchange, grain, Money::from_dollars(5), 40)
+            .unwrap()
+            .unwrap();
+        let company = reload(&conn, &company);
+        assert_eq!(company.reserved_usd(), Money::from_dollars(30));
+        assert_eq!(company.reserved(grain), 40);
+
+        assert!(exchange.cancel(&conn, bid).unwrap());
+        assert!(exchange.cancel(&conn, ask).unwrap());
+        assert_eq!(book(&conn, &exchange), vec![]);
+        let company = reload(&conn, &company);
+        assert_eq!(company.reserved_usd(), Money::ZERO);
+        assert_eq!(company.available_usd(), Money::from_dollars(100));
+        assert_eq!(company.reserved(grain), 0);
+        assert_eq!(company.available(grain), 100);
+
+        assert!(!exchange.cancel(&conn, bid).unwrap());
+        assert_eq!(check_invariants(&conn).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn expired_orders_are_swept_when_the_cycle_advances() {
+        let conn = init_memory_db().unwrap();
+        let mut exchange = Exchange::load(&conn).unwrap();
+        let grain = Material::lookup("Grain").unwrap();
+        let mut owner = Player::create(&conn, "owner").unwrap();
+        owner.earn(Money::from_dollars(10_000)).unwrap();
+
+        let mut company = farm(&conn, &mut owner, grain);
+        let mut place = |time_in_force| {
+            let mut offer = Offer {
+                entity: EntityRef::Borrowed(&mut company),
+                conn: &conn,
+                item: grain,
+                quantity: 10,
+                kind: OrderKind::Limit(Money::from_dollars(2)),
+                offer_type: OfferType::Sell,
+                time_in_force,
+                self_trade: SelfTradePrevention::default(),
+            };
+            offer.execute(&mut exchange).unwrap().unwrap()
+        };
+        let short = place(TimeInForce::GoodForCycles(1));
+        let long = place(TimeInForce::GoodForCycles(2));
+        let open = place(TimeInForce::GoodTillCancelled);
+
+        let report = next_cycle(&conn, &mut exchange).unwrap();
+        assert_eq!((report.cycle, report.expired), (1, 1));
+        assert_eq!(book(&conn, &exchange), vec![(long, 10), (open, 10)]);
+        assert!(exchange.order(short).is_none());
+        assert_eq!(reload(&conn, &company).reserved(grain), 20);
+
+        let report = next_cycle(&conn, &mut exchange).unwrap();
+        assert_eq!((report.cycle, report.expired), (2, 1));
+        assert_eq!(book(&conn, &exchange), vec![(open, 10)]);
+        let company = reload(&conn, &company);
+        assert_eq!(company.reserved(grain), 10);
+        assert_eq!(company.available(grain), 90);
+        assert_eq!(check_invariants(&conn).unwrap(), vec![]);
+    }
+}
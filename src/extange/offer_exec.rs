@@ -1,71 +1,403 @@
 use super::*;
-use rusqlite::params;
+use crate::{
+    db::{atomic, current_cycle},
+    error::{EngineError, EngineResult},
+    money::Money,
+    production::ProdInstance,
+};
+use std::collections::HashMap;
 
 impl<'a, 'b> Offer<'a, 'b> {
-    pub fn execute(&mut self) -> rusqlite::Result<()> {
-        if !self.valid() {
-            println!("❌ Offer is not valid.");
-            return Ok(());
+    /// Matches the offer against the in-memory book and settles every fill.
+    ///
+    /// Returns the id of the order left on the book: the resting remainder
+    /// of a limit order, or a stop that has not triggered yet. All writes
+    /// (both entities, the trade log and the book rows) happen inside one
+    /// savepoint. If anything fails the database is rolled back, the book is
+    /// left untouched and the in-memory entity is restored to its prior
+    /// state.
+    ///
+    /// Any stops the fills trigger run straight after, each in its own
+    /// savepoint, and the entity is reloaded if one of them fired. If any of
+    /// them fail this returns [`EngineError::StopsFailed`], even though the
+    /// offer itself went through.
+    ///
+    /// Fails without touching anything if the entity can't back the offer.
+    pub fn execute(&mut self, exchange: &mut Exchange) -> EngineResult<Option<i64>> {
+        self.validate()?;
+
+        if self.kind.trigger().is_some() {
+            let last_price = last_trade_price(self.conn, self.item)?;
+            if !last_price.is_some_and(|price| self.kind.is_triggered(self.offer_type, price)) {
+                return self.park_stop(exchange).map(Some);
+            }
+            println!("⚡ Stop already triggered. Executing immediately.");
+            self.kind = self.kind.triggered();
         }
 
-        let (target_offer_type, price_operator) = match self.offer_type {
-            OfferType::Buy => (OfferType::Sell, "<="),
-            OfferType::Sell => (OfferType::Buy, ">="),
-        };
+        let resting_id = self.execute_now(exchange)?;
 
-        let sql = build_sql_query(self.offer_type, price_operator);
+        // Triggered stops may have traded with this entity through a fresh
+        // copy loaded from the database, so pick up what they wrote.
+        let stops = exchange.fire_stops(self.conn, self.item)?;
+        if !stops.fired.is_empty()
+            && let Some(id) = self.entity.as_ref().id
+            && let Some(entity) = ProdInstance::load(self.conn, id)?
+        {
+            *self.entity.as_mut() = entity;
+        }
+        if !stops.failed.is_empty() {
+            return Err(EngineError::StopsFailed {
+                resting_id,
+                failed: stops.failed,
+            });
+        }
+        Ok(resting_id)
+    }
 
-        let mut stmt = self.conn.prepare(&sql)?;
+    /// Runs the matching itself, without looking at stops.
+    pub(super) fn execute_now(&mut self, exchange: &mut Exchange) -> EngineResult<Option<i64>> {
+        let conn = self.conn;
+        let entity_snapshot = self.entity.as_ref().clone();
+        let quantity_snapshot = self.quantity;
 
-        let mut rows = stmt.query(params![
-            self.item.to_string_key(),
-            bool::from(target_offer_type),
-            self.price,
-        ])?;
+        match atomic(conn, || self.match_and_settle(exchange)) {
+            Ok((resting_id, changes)) => {
+                exchange.commit(changes);
+                Ok(resting_id)
+            }
+            Err(e) => {
+                *self.entity.as_mut() = entity_snapshot;
+                self.quantity = quantity_snapshot;
+                Err(e)
+            }
+        }
+    }
 
-        let mut remaining_qty = self.quantity;
-        let mut match_found = false;
+    fn match_and_settle(
+        &mut self,
+        exchange: &mut Exchange,
+    ) -> EngineResult<(Option<i64>, Vec<BookChange>)> {
+        let cycle = current_cycle(self.conn)?;
+        let candidates = collect_candidates(exchange, self, cycle);
 
-        while let Some(row) = rows.next()? {
-            match_found = true;
-            println!("✅ Match found!");
+        if self.time_in_force == TimeInForce::FillOrKill
+            && self.fillable(&candidates)? < self.quantity as u64
+        {
+            println!("🛑 Fill-or-kill offer cannot be filled completely. Killed.");
+            return Ok((None, Vec::new()));
+        }
+
+        // Lock everything the offer could need up front. Whatever isn't
+        // spent or kept for a resting remainder is handed back at the end.
+        let offered = self.quantity;
+        let reserved_usd = self.cash_to_reserve()?;
+        let reserved = match self.offer_type {
+            OfferType::Buy => self.entity.as_mut().reserve_usd(reserved_usd),
+            OfferType::Sell => self.entity.as_mut().reserve_material(self.item, offered),
+        };
+        reserved?;
+
+        if candidates.is_empty() {
+            println!("🚫 No matching offers found.");
+        }
 
-            if remaining_qty == 0 {
+        let mut makers: HashMap<u32, ProdInstance> = HashMap::new();
+        let mut changes = Vec::new();
+        let mut spent = Money::ZERO;
+        let mut delivered: u32 = 0;
+
+        for candidate in candidates {
+            if self.quantity == 0 {
                 println!("✅ No remaining quantity to match. Exiting loop.");
                 break;
             }
 
-            let matched_id: i64 = row.get(0)?;
-            let matched_price: f32 = row.get(2)?;
+            if self.is_self_trade(&candidate) {
+                if self.prevent_self_trade(&candidate, &mut makers, &mut changes)? {
+                    continue;
+                }
+                break;
+            }
 
-            let mut matched_offer =
-                Offer::load_from_id(self.conn, matched_id)?.expect("Failed to load matched offer");
+            let mut trade_qty = self.quantity.min(candidate.quantity);
+            if self.is_market_buy() {
+                let budget_left = reserved_usd.try_sub(spent)?;
+                trade_qty = trade_qty.min(budget_left.units_at(candidate.price));
+            }
+            if trade_qty == 0 {
+                println!("💸 Budget exhausted. Exiting loop.");
+                break;
+            }
+            println!("✅ Match found!");
 
-            let trade_qty = remaining_qty.min(matched_offer.quantity);
-            println!("🔁 Trading {} units @ {}", trade_qty, matched_price);
+            let maker = maker_entity(self.conn, &mut makers, candidate.entity)?;
+            println!("🔁 Trading {} units @ {}", trade_qty, candidate.price);
 
-            process_trade(self, &mut matched_offer, trade_qty, matched_price)?;
+            process_trade(self, maker, trade_qty, candidate.price)?;
 
-            remaining_qty -= trade_qty;
-            matched_offer.quantity -= trade_qty;
+            self.quantity -= trade_qty;
+            spent = spent.try_add(candidate.price.try_mul(trade_qty)?)?;
+            delivered += trade_qty;
+            changes.push(BookChange::Fill {
+                id: candidate.id,
+                quantity: trade_qty,
+            });
+        }
 
-            update_db_after_trade(self.conn, matched_id, &mut matched_offer)?;
+        let mut resting_id = None;
+        let mut kept = 0;
+        if self.quantity == 0 {
+            println!("✅ Offer fully executed and removed.");
+        } else if self.rests() {
+            println!("📬 Offer partially (or not) filled. Resting remainder on the book.");
+            let id = exchange.next_order_id();
+            changes.push(BookChange::Insert(self.resting_order(id, cycle)));
+            resting_id = Some(id);
+            kept = self.quantity;
+        } else {
+            println!("↩️ Offer partially (or not) filled. Returning remainder.");
+        }
 
-            self.quantity = remaining_qty;
+        let entity = self.entity.as_mut();
+        match self.offer_type {
+            OfferType::Buy => {
+                // Market orders never keep anything, and their limit is
+                // the top of the range, so don't multiply it out.
+                let kept_usd = if kept > 0 {
+                    self.kind.limit_price(self.offer_type).try_mul(kept)?
+                } else {
+                    Money::ZERO
+                };
+                entity.release_usd(reserved_usd.try_sub(spent)?.try_sub(kept_usd)?)?;
+            }
+            OfferType::Sell => {
+                entity.release_material(self.item, offered - delivered - kept)?;
+            }
         }
 
-        if !match_found {
-            println!("🚫 No matching offers found.");
+        for maker in makers.values_mut() {
+            maker.save(self.conn)?;
         }
+        self.entity.as_mut().save(self.conn)?;
+        exchange.stage(self.conn, &changes)?;
+        println!("🎉 Offer execution complete.");
+        Ok((resting_id, changes))
+    }
 
-        if self.quantity > 0 {
-            println!("📬 Offer partially (or not) filled. Saving remainder to DB.");
-            self.save_to_db()?;
+    fn is_market_buy(&self) -> bool {
+        self.offer_type == OfferType::Buy && matches!(self.kind, OrderKind::Market { .. })
+    }
+
+    /// Cash a buy locks while it matches: its full value at the limit price,
+    /// or for a market buy its budget, capped by what the entity has.
+    fn cash_to_reserve(&self) -> EngineResult<Money> {
+        if self.offer_type == OfferType::Sell {
+            return Ok(Money::ZERO);
+        }
+        let available = self.entity.as_ref().available_usd();
+        match self.kind {
+            OrderKind::Market { budget } => {
+                Ok(budget.map_or(available, |budget| budget.min(available)))
+            }
+            _ => self.limit_price().try_mul(self.quantity),
+        }
+    }
+
+    /// How many units `candidates` could fill before self-trade prevention
+    /// or, for a market buy, the budget would stop the offer.
+    fn fillable(&self, candidates: &[RestingOrder]) -> EngineResult<u64> {
+        let mut budget = if self.is_market_buy() {
+            Some(self.cash_to_reserve()?)
         } else {
-            println!("✅ Offer fully executed and removed.");
+            None
+        };
+        let mut fillable: u64 = 0;
+        for candidate in candidates {
+            if self.is_self_trade(candidate) {
+                match self.self_trade {
+                    SelfTradePrevention::CancelOldest => continue,
+                    _ => break,
+                }
+            }
+            let mut quantity = candidate.quantity;
+            if let Some(budget) = budget.as_mut() {
+                quantity = quantity.min(budget.units_at(candidate.price));
+                *budget = budget.try_sub(candidate.price.try_mul(quantity)?)?;
+            }
+            fillable += quantity as u64;
         }
-        let _ = self.entity.as_mut().save(self.conn);
-        println!("🎉 Offer execution complete.");
-        Ok(())
+        Ok(fillable)
+    }
+
+    fn resting_order(&self, id: i64, cycle: u32) -> RestingOrder {
+        let entity = self.entity.as_ref();
+        RestingOrder {
+            id,
+            entity: entity
+                .id
+                .expect("Entity Id in resting extange offer is None!"),
+            owner: entity.owner,
+            offer_type: self.offer_type,
+            item: self.item,
+            quantity: self.quantity,
+            price: self.limit_price(),
+            seq: id,
+            expires_at: self.time_in_force.expires_at(cycle),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::init_memory_db, ledger::check_invariants, materials::Material, player::Player,
+        production::Prod,
+    };
+    use rusqlite::Connection;
+
+    fn farm(conn: &Connection, owner: &mut Player, grain: Material) -> ProdInstance {
+        let base = Prod::lookup("grain_farm").unwrap();
+        let mut farm = ProdInstance::new(conn, base, "Farm".to_string(), owner).unwrap();
+        farm.earn(Money::from_dollars(100)).unwrap();
+        farm.add_material(grain, 100).unwrap();
+        farm.save(conn).unwrap();
+        farm
+    }
+
+    fn trade_count(conn: &Connection) -> u32 {
+        conn.query_row("SELECT COUNT(*) FROM trades", [], |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn a_failed_fill_rolls_back_both_sides() {
+        let conn = init_memory_db().unwrap();
+        let mut exchange = Exchange::load(&conn).unwrap();
+        let grain = Material::lookup("Grain").unwrap();
+        let mut seller = Player::create(&conn, "seller").unwrap();
+        let mut buyer = Player::create(&conn, "buyer").unwrap();
+        seller.earn(Money::from_dollars(10_000)).unwrap();
+        buyer.earn(Money::from_dollars(10_000)).unwrap();
+
+        let mut first = farm(&conn, &mut seller, grain);
+        let mut second = farm(&conn, &mut seller, grain);
+        first
+            .quick_sell(&conn, &mut exchange, grain, Money::from_dollars(1), 20)
+            .unwrap();
+        second
+            .quick_sell(&conn, &mut exchange, grain, Money::from_dollars(1), 20)
+            .unwrap();
+        let mut bidder = farm(&conn, &mut buyer, grain);
+        seller.save(&conn).unwrap();
+        buyer.save(&conn).unwrap();
+
+        // The first fill goes through, then writing the second one fails.
+        conn.execute_batch(
+            "CREATE TEMP TRIGGER fail_second_fill BEFORE INSERT ON trades
+             WHEN (SELECT COUNT(*) FROM trades) > 0
+             BEGIN SELECT RAISE(ABORT, 'disk full'); END;",
+        )
+        .unwrap();
+        let before = bidder.clone();
+        let book: Vec<_> = exchange.orders().cloned().collect();
+        assert!(matches!(
+            bidder.quick_buy(&conn, &mut exchange, grain, Money::from_dollars(1), 30),
+            Err(EngineError::Persistence(_))
+        ));
+
+        assert_eq!(bidder, before);
+        assert_eq!(exchange.orders().cloned().collect::<Vec<_>>(), book);
+        assert_eq!(trade_count(&conn), 0);
+        for company in [&first, &second, &bidder] {
+            let stored = ProdInstance::load(&conn, company.id.unwrap())
+                .unwrap()
+                .unwrap();
+            assert_eq!(stored.usd, company.usd);
+            assert_eq!(stored.reserved(grain), company.reserved(grain));
+            assert_eq!(stored.available(grain), company.available(grain));
+        }
+        assert_eq!(check_invariants(&conn).unwrap(), vec![]);
+
+        // Nothing is left half-done, so the same offer can be tried again.
+        conn.execute_batch("DROP TRIGGER fail_second_fill").unwrap();
+        bidder
+            .quick_buy(&conn, &mut exchange, grain, Money::from_dollars(1), 30)
+            .unwrap();
+        assert_eq!(bidder.available(grain), 130);
+        assert_eq!(trade_count(&conn), 2);
+        assert_eq!(check_invariants(&conn).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn fills_go_best_price_first_then_oldest_and_are_logged() {
+        let conn = init_memory_db().unwrap();
+        let mut exchange = Exchange::load(&conn).unwrap();
+        let grain = Material::lookup("Grain").unwrap();
+        let mut seller = Player::create(&conn, "seller").unwrap();
+        let mut buyer = Player::create(&conn, "buyer").unwrap();
+        seller.earn(Money::from_dollars(10_000)).unwrap();
+        buyer.earn(Money::from_dollars(10_000)).unwrap();
+
+        let mut dear = farm(&conn, &mut seller, grain);
+        let mut older = farm(&conn, &mut seller, grain);
+        let mut newer = farm(&conn, &mut seller, grain);
+        let dear_ask = dear
+            .quick_sell(&conn, &mut exchange, grain, Money::from_dollars(2), 10)
+            .unwrap()
+            .unwrap();
+        older
+            .quick_sell(&conn, &mut exchange, grain, Money::from_dollars(1), 10)
+            .unwrap();
+        newer
+            .quick_sell(&conn, &mut exchange, grain, Money::from_dollars(1), 10)
+            .unwrap();
+        let mut bidder = farm(&conn, &mut buyer, grain);
+        bidder
+            .quick_buy(&conn, &mut exchange, grain, Money::from_dollars(2), 25)
+            .unwrap();
+
+        let mut stmt = conn
+            .prepare(
+                "SELECT buyer, seller, item, amount, unit_price, cycle FROM trades ORDER BY id",
+            )
+            .unwrap();
+        let trades: Vec<(u32, u32, String, u32, Money, u32)> = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                    row.get(5)?,
+                ))
+            })
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        let bidder = bidder.id.unwrap();
+        let fill = |seller: &ProdInstance, amount, dollars| {
+            (
+                bidder,
+                seller.id.unwrap(),
+                "Grain".to_string(),
+                amount,
+                Money::from_dollars(dollars),
+                0,
+            )
+        };
+        assert_eq!(
+            trades,
+            vec![fill(&older, 10, 1), fill(&newer, 10, 1), fill(&dear, 5, 2)]
+        );
+
+        let book: Vec<_> = exchange
+            .orders()
+            .map(|order| (order.id, order.quantity))
+            .collect();
+        assert_eq!(book, vec![(dear_ask, 5)]);
+        assert_eq!(check_invariants(&conn).unwrap(), vec![]);
     }
 }
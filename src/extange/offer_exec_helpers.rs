@@ -1,65 +1,87 @@
 use super::*;
-use rusqlite::params;
+use crate::{
+    error::{EngineError, EngineResult},
+    money::Money,
+    production::ProdInstance,
+};
+use rusqlite::Connection;
+use std::collections::{HashMap, hash_map::Entry};
 
-// Helper to build SQL query string
-pub fn build_sql_query(offer_type: OfferType, price_operator: &str) -> String {
-    format!(
-        "SELECT id, amount, unit_price, entity
-         FROM extchange
-         WHERE item = ?1
-         AND type = ?2
-         AND unit_price {} ?3
-         ORDER BY unit_price {}",
-        price_operator,
-        if offer_type == OfferType::Buy {
-            "ASC"
-        } else {
-            "DESC"
-        },
-    )
+// Helper to snapshot the resting orders an offer may trade against, in
+// price-time priority. Stops once there is enough quantity from other
+// owners to fill the offer, so a deep book isn't copied for a small order.
+pub fn collect_candidates(
+    exchange: &Exchange,
+    offer: &Offer<'_, '_>,
+    cycle: u32,
+) -> Vec<RestingOrder> {
+    let Some(book) = exchange.book(offer.item) else {
+        return Vec::new();
+    };
+
+    let mut candidates = Vec::new();
+    let mut tradeable: u64 = 0;
+    for order in book.crossing(offer.offer_type, offer.limit_price()) {
+        if tradeable >= offer.quantity as u64 {
+            break;
+        }
+        if !order.is_live(cycle) {
+            continue;
+        }
+        if !offer.is_self_trade(order) {
+            tradeable += order.quantity as u64;
+        }
+        candidates.push(order.clone());
+    }
+    candidates
 }
 
-// Helper to process a trade between offers
-pub fn process_trade<'a, 'b>(
-    offer: &mut Offer<'a, 'b>,
-    matched_offer: &mut Offer<'a, 'b>,
+// Helper to load each maker once per execution. They are saved together
+// when the offer has finished matching.
+pub fn maker_entity<'m>(
+    conn: &Connection,
+    makers: &'m mut HashMap<u32, ProdInstance>,
+    entity_id: u32,
+) -> EngineResult<&'m mut ProdInstance> {
+    match makers.entry(entity_id) {
+        Entry::Occupied(entry) => Ok(entry.into_mut()),
+        Entry::Vacant(entry) => {
+            let entity = ProdInstance::load(conn, entity_id)?
+                .ok_or(EngineError::CompanyNotFound(entity_id))?;
+            Ok(entry.insert(entity))
+        }
+    }
+}
+
+// Helper to process a trade between an offer and a resting order. Both
+// sides settle at the resting (maker) order's price, and the fill is written
+// to the trade log.
+pub fn process_trade(
+    offer: &mut Offer<'_, '_>,
+    maker: &mut ProdInstance,
     trade_qty: u32,
-    matched_price: f32,
-) -> rusqlite::Result<()> {
+    matched_price: Money,
+) -> EngineResult<()> {
+    let value = matched_price.try_mul(trade_qty)?;
+    let taker_id = offer.entity.as_ref().id.expect("Taker entity id is None!");
+    let maker_id = maker.id.expect("Maker entity id is None!");
     match offer.offer_type {
         OfferType::Buy => {
-            offer.entity.as_mut().add_material(offer.item, trade_qty);
-            matched_offer
-                .entity
-                .as_mut()
-                .earn(trade_qty as f32 * matched_price);
-            let _ = matched_offer.entity.as_mut().save(offer.conn);
+            let buyer = offer.entity.as_mut();
+            buyer.pay_reserved_usd(maker, value)?;
+            maker.deliver_reserved_material(buyer, offer.item, trade_qty)?;
+
+            Trade::new(taker_id, maker_id, offer.item, trade_qty, matched_price)
+                .record(offer.conn)?;
         }
         OfferType::Sell => {
-            matched_offer
-                .entity
-                .as_mut()
-                .add_material(offer.item, trade_qty);
-            let _ = matched_offer.entity.as_mut().save(offer.conn);
-            offer.entity.as_mut().earn(trade_qty as f32 * matched_price);
-        }
-    }
-    Ok(())
-}
+            let seller = offer.entity.as_mut();
+            seller.deliver_reserved_material(maker, offer.item, trade_qty)?;
+            maker.pay_reserved_usd(seller, value)?;
 
-// Helper to update database after trade
-pub fn update_db_after_trade(
-    conn: &rusqlite::Connection,
-    matched_id: i64,
-    matched_offer: &mut Offer<'_, '_>,
-) -> rusqlite::Result<()> {
-    if matched_offer.quantity == 0 {
-        conn.execute("DELETE FROM extchange WHERE id = ?1", params![matched_id])?;
-    } else {
-        conn.execute(
-            "UPDATE extchange SET amount = ?1 WHERE id = ?2",
-            params![matched_offer.quantity, matched_id],
-        )?;
+            Trade::new(maker_id, taker_id, offer.item, trade_qty, matched_price)
+                .record(offer.conn)?;
+        }
     }
     Ok(())
 }
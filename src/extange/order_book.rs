@@ -0,0 +1,202 @@
+use crate::{extange::OfferType, materials::Material, money::Money};
+use std::collections::{BTreeMap, VecDeque};
+
+/// An order waiting on the book for a counterparty.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RestingOrder {
+    pub id: i64,
+    pub entity: u32,
+    pub owner: u32,
+    pub offer_type: OfferType,
+    pub item: Material,
+    pub quantity: u32,
+    pub price: Money,
+    pub seq: i64,
+    pub expires_at: Option<u32>,
+}
+
+impl RestingOrder {
+    /// Whether the order can still trade during `cycle`.
+    pub fn is_live(&self, cycle: u32) -> bool {
+        self.expires_at.is_none_or(|expires_at| expires_at > cycle)
+    }
+}
+
+type Side = BTreeMap<Money, VecDeque<RestingOrder>>;
+
+/// Bids and asks for one material. Each price level is a FIFO queue kept in
+/// `seq` order, which gives strict price-time priority.
+#[derive(Debug, Default)]
+pub struct OrderBook {
+    bids: Side,
+    asks: Side,
+}
+
+impl OrderBook {
+    fn side(&self, offer_type: OfferType) -> &Side {
+        match offer_type {
+            OfferType::Buy => &self.bids,
+            OfferType::Sell => &self.asks,
+        }
+    }
+
+    fn side_mut(&mut self, offer_type: OfferType) -> &mut Side {
+        match offer_type {
+            OfferType::Buy => &mut self.bids,
+            OfferType::Sell => &mut self.asks,
+        }
+    }
+
+    pub fn insert(&mut self, order: RestingOrder) {
+        let queue = self
+            .side_mut(order.offer_type)
+            .entry(order.price)
+            .or_default();
+        // New orders always carry the highest seq, so this is a push to the
+        // back unless an order is being put back where it was.
+        let at = queue.partition_point(|resting| resting.seq < order.seq);
+        queue.insert(at, order);
+    }
+
+    pub fn get(&self, offer_type: OfferType, price: Money, id: i64) -> Option<&RestingOrder> {
+        self.side(offer_type)
+            .get(&price)?
+            .iter()
+            .find(|order| order.id == id)
+    }
+
+    /// Takes `quantity` off an order, dropping it once nothing is left.
+    pub fn fill(&mut self, offer_type: OfferType, price: Money, id: i64, quantity: u32) {
+        let side = self.side_mut(offer_type);
+        let Some(queue) = side.get_mut(&price) else {
+            return;
+        };
+        if let Some(at) = queue.iter().position(|order| order.id == id) {
+            let order = &mut queue[at];
+            order.quantity = order.quantity.saturating_sub(quantity);
+            if order.quantity == 0 {
+                queue.remove(at);
+            }
+        }
+        if queue.is_empty() {
+            side.remove(&price);
+        }
+    }
+
+    pub fn remove(&mut self, offer_type: OfferType, price: Money, id: i64) -> Option<RestingOrder> {
+        let side = self.side_mut(offer_type);
+        let queue = side.get_mut(&price)?;
+        let order = queue
+            .iter()
+            .position(|order| order.id == id)
+            .and_then(|at| queue.remove(at));
+        if queue.is_empty() {
+            side.remove(&price);
+        }
+        order
+    }
+
+    /// Resting orders an incoming offer at `limit` could trade against, best
+    /// price first and oldest first within a price.
+    pub fn crossing(
+        &self,
+        incoming: OfferType,
+        limit: Money,
+    ) -> Box<dyn Iterator<Item = &RestingOrder> + '_> {
+        match incoming {
+            OfferType::Buy => Box::new(
+                self.asks
+                    .range(..=limit)
+                    .flat_map(|(_, queue)| queue.iter()),
+            ),
+            OfferType::Sell => Box::new(
+                self.bids
+                    .range(limit..)
+                    .rev()
+                    .flat_map(|(_, queue)| queue.iter()),
+            ),
+        }
+    }
+
+    /// Price levels on one side, best price first.
+    pub fn levels(
+        &self,
+        offer_type: OfferType,
+    ) -> Box<dyn Iterator<Item = (Money, &VecDeque<RestingOrder>)> + '_> {
+        let levels = self
+            .side(offer_type)
+            .iter()
+            .map(|(price, queue)| (*price, queue));
+        match offer_type {
+            OfferType::Buy => Box::new(levels.rev()),
+            OfferType::Sell => Box::new(levels),
+        }
+    }
+
+    pub fn orders(&self) -> impl Iterator<Item = &RestingOrder> {
+        self.bids
+            .values()
+            .chain(self.asks.values())
+            .flat_map(|queue| queue.iter())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order(id: i64, offer_type: OfferType, dollars: u32) -> RestingOrder {
+        RestingOrder {
+            id,
+            entity: 1,
+            owner: 1,
+            offer_type,
+            item: Material::lookup("Grain").unwrap(),
+            quantity: 10,
+            price: Money::from_dollars(dollars),
+            seq: id,
+            expires_at: None,
+        }
+    }
+
+    fn ids<'a>(orders: impl Iterator<Item = &'a RestingOrder>) -> Vec<i64> {
+        orders.map(|order| order.id).collect()
+    }
+
+    #[test]
+    fn crossing_orders_come_best_price_first_then_oldest() {
+        let mut book = OrderBook::default();
+        for (id, dollars) in [(1, 3), (2, 1), (3, 2), (4, 1), (5, 5)] {
+            book.insert(order(id, OfferType::Sell, dollars));
+        }
+        for (id, dollars) in [(6, 1), (7, 2), (8, 2)] {
+            book.insert(order(id, OfferType::Buy, dollars));
+        }
+
+        let limit = Money::from_dollars(3);
+        assert_eq!(ids(book.crossing(OfferType::Buy, limit)), vec![2, 4, 3, 1]);
+        let limit = Money::from_dollars(1);
+        assert_eq!(ids(book.crossing(OfferType::Sell, limit)), vec![7, 8, 6]);
+        let limit = Money::from_dollars(2);
+        assert_eq!(ids(book.crossing(OfferType::Sell, limit)), vec![7, 8]);
+    }
+
+    #[test]
+    fn an_order_put_back_keeps_its_place() {
+        let mut book = OrderBook::default();
+        for id in 1..=3 {
+            book.insert(order(id, OfferType::Sell, 1));
+        }
+        let price = Money::from_dollars(1);
+        let second = book.remove(OfferType::Sell, price, 2).unwrap();
+        book.insert(second);
+        assert_eq!(ids(book.crossing(OfferType::Buy, price)), vec![1, 2, 3]);
+
+        // A partial fill doesn't move an order either.
+        book.fill(OfferType::Sell, price, 1, 4);
+        assert_eq!(ids(book.crossing(OfferType::Buy, price)), vec![1, 2, 3]);
+        assert_eq!(book.get(OfferType::Sell, price, 1).unwrap().quantity, 6);
+        book.fill(OfferType::Sell, price, 1, 6);
+        assert_eq!(ids(book.crossing(OfferType::Buy, price)), vec![2, 3]);
+    }
+}
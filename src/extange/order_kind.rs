@@ -0,0 +1,68 @@
+use crate::{extange::OfferType, money::Money};
+
+/// How an offer is priced and when it becomes active.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum OrderKind {
+    /// Trades at `price` or better and may rest on the book.
+    Limit(Money),
+    /// Sweeps the book at whatever prices are resting, up to the offer's
+    /// quantity and, for buys, an optional cash budget. Never rests.
+    Market { budget: Option<Money> },
+    /// Parked until the last trade price reaches `trigger`, then runs as a
+    /// market order. Buys need a budget to escrow while parked.
+    Stop {
+        trigger: Money,
+        budget: Option<Money>,
+    },
+    /// Parked until the last trade price reaches `trigger`, then runs as a
+    /// limit order at `limit`.
+    StopLimit { trigger: Money, limit: Money },
+}
+
+impl OrderKind {
+    /// Worst price the offer accepts when matching. Market orders take any
+    /// price, so they get the far end of the range.
+    pub fn limit_price(&self, offer_type: OfferType) -> Money {
+        match (self, offer_type) {
+            (OrderKind::Limit(price), _) | (OrderKind::StopLimit { limit: price, .. }, _) => *price,
+            (_, OfferType::Buy) => Money::MAX,
+            (_, OfferType::Sell) => Money::MIN,
+        }
+    }
+
+    /// Cash budget a buy must not exceed, if any.
+    pub fn budget(&self) -> Option<Money> {
+        match self {
+            OrderKind::Market { budget } | OrderKind::Stop { budget, .. } => *budget,
+            _ => None,
+        }
+    }
+
+    pub fn trigger(&self) -> Option<Money> {
+        match self {
+            OrderKind::Stop { trigger, .. } | OrderKind::StopLimit { trigger, .. } => {
+                Some(*trigger)
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether a stop on the `offer_type` side fires at `last_price`. Buy
+    /// stops fire as the price rises to the trigger, sell stops as it falls.
+    pub fn is_triggered(&self, offer_type: OfferType, last_price: Money) -> bool {
+        match (self.trigger(), offer_type) {
+            (Some(trigger), OfferType::Buy) => last_price >= trigger,
+            (Some(trigger), OfferType::Sell) => last_price <= trigger,
+            (None, _) => false,
+        }
+    }
+
+    /// The order a stop turns into once triggered.
+    pub fn triggered(&self) -> OrderKind {
+        match *self {
+            OrderKind::Stop { budget, .. } => OrderKind::Market { budget },
+            OrderKind::StopLimit { limit, .. } => OrderKind::Limit(limit),
+            kind => kind,
+        }
+    }
+}
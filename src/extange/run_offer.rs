@@ -1,63 +1,50 @@
 use super::*;
-use crate::{materials::*, production::ProdInstance};
+use crate::{error::EngineResult, materials::*, money::Money, production::ProdInstance};
 use rusqlite::Connection;
 impl ProdInstance {
-    pub fn quick_sell(&mut self, conn: &Connection, item: Material, price: f32, amount: u32) {
-        self.remove_material(item, amount);
-        let prod_id = self
-            .id
-            .expect("Id is None! Can't sell from a non-existent entity!");
-
+    /// Places a good-till-cancelled limit sell. Returns the id of the
+    /// resting remainder, if any.
+    pub fn quick_sell(
+        &mut self,
+        conn: &Connection,
+        exchange: &mut Exchange,
+        item: Material,
+        price: Money,
+        amount: u32,
+    ) -> EngineResult<Option<i64>> {
         let mut offer = Offer {
             entity: EntityRef::Borrowed(self),
-            conn: &conn,
+            conn,
             item,
             quantity: amount,
-            price,
+            kind: OrderKind::Limit(price),
             offer_type: OfferType::Sell,
+            time_in_force: TimeInForce::GoodTillCancelled,
+            self_trade: SelfTradePrevention::default(),
         };
-
-        if offer.valid() {
-            if let Err(e) = offer.execute() {
-                eprintln!(
-                    "Failed to execute sell offer for ProdInstance {}: {}",
-                    prod_id, e
-                );
-            } else {
-                println!("Created sell offer for ProdInstance {}!", prod_id);
-            }
-        } else {
-            println!("Sell offer not valid for ProdInstance {}!", prod_id);
-        }
-
-        let _ = self.save(&conn);
+        offer.execute(exchange)
     }
 
-    pub fn quick_buy(&mut self, conn: &Connection, item: Material, price: f32, amount: u32) {
-        self.spend(price * amount as f32);
-        let prod_id = self.id.expect("A non existant entity cant buy wares!");
+    /// Places a good-till-cancelled limit buy. Returns the id of the
+    /// resting remainder, if any.
+    pub fn quick_buy(
+        &mut self,
+        conn: &Connection,
+        exchange: &mut Exchange,
+        item: Material,
+        price: Money,
+        amount: u32,
+    ) -> EngineResult<Option<i64>> {
         let mut offer = Offer {
-            entity: EntityRef::Borrowed(self), // Use the original 'prod' here
-            conn: &conn,
-            item: item,
+            entity: EntityRef::Borrowed(self),
+            conn,
+            item,
             quantity: amount,
-            price: price,
+            kind: OrderKind::Limit(price),
             offer_type: OfferType::Buy,
+            time_in_force: TimeInForce::GoodTillCancelled,
+            self_trade: SelfTradePrevention::default(),
         };
-
-        if offer.valid() {
-            if let Err(e) = offer.execute() {
-                eprintln!(
-                    "Failed to save offer for TierOneProdInstance {}: {}",
-                    prod_id, e
-                );
-            } else {
-                println!("Created sell offer for TierOneProdInstance {}!", prod_id);
-            }
-        } else {
-            println!("Offer not valid for TierOneProdInstance {}!", prod_id);
-        }
-
-        let _ = self.save(&conn);
+        offer.execute(exchange)
     }
 }
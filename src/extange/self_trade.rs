@@ -0,0 +1,259 @@
+use super::*;
+use crate::{error::EngineResult, production::ProdInstance};
+use std::collections::HashMap;
+
+/// What to do when an incoming offer would trade against a resting order
+/// from the same company, or from another company with the same owner.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum SelfTradePrevention {
+    /// Drop the rest of the incoming offer and leave the resting order alone.
+    #[default]
+    CancelNewest,
+    /// Cancel the resting order and keep matching the incoming offer.
+    CancelOldest,
+    /// Shrink both orders by the overlapping quantity without trading.
+    DecrementBoth,
+}
+
+impl<'a, 'b> Offer<'a, 'b> {
+    pub(super) fn is_self_trade(&self, candidate: &RestingOrder) -> bool {
+        let entity = self.entity.as_ref();
+        entity.id == Some(candidate.entity) || entity.owner == candidate.owner
+    }
+
+    /// Applies this offer's self-trade prevention mode against `candidate`.
+    /// Returns `false` once the incoming offer should stop matching.
+    pub(super) fn prevent_self_trade(
+        &mut self,
+        candidate: &RestingOrder,
+        makers: &mut HashMap<u32, ProdInstance>,
+        changes: &mut Vec<BookChange>,
+    ) -> EngineResult<bool> {
+        match self.self_trade {
+            SelfTradePrevention::CancelNewest => {
+                println!("🚷 Self-trade prevented. Cancelling the incoming remainder.");
+                self.quantity = 0;
+                Ok(false)
+            }
+            SelfTradePrevention::CancelOldest => {
+                println!(
+                    "🚷 Self-trade prevented. Cancelling resting order {}.",
+                    candidate.id
+                );
+                self.release_resting(candidate, candidate.quantity, makers)?;
+                changes.push(BookChange::Remove(candidate.id));
+                Ok(true)
+            }
+            SelfTradePrevention::DecrementBoth => {
+                let qty = self.quantity.min(candidate.quantity);
+                println!(
+                    "🚷 Self-trade prevented. Decrementing both orders by {}.",
+                    qty
+                );
+                self.release_resting(candidate, qty, makers)?;
+                changes.push(BookChange::Fill {
+                    id: candidate.id,
+                    quantity: qty,
+                });
+                self.quantity -= qty;
+                Ok(true)
+            }
+        }
+    }
+
+    /// Refunds `quantity` units of a resting order's escrow. When the resting
+    /// order belongs to this offer's own entity the refund goes to the
+    /// in-memory entity, so no second copy of it is ever loaded and saved.
+    fn release_resting(
+        &mut self,
+        candidate: &RestingOrder,
+        quantity: u32,
+        makers: &mut HashMap<u32, ProdInstance>,
+    ) -> EngineResult<()> {
+        let entity = if self.entity.as_ref().id == Some(candidate.entity) {
+            self.entity.as_mut()
+        } else {
+            maker_entity(self.conn, makers, candidate.entity)?
+        };
+        release_order_escrow(
+            entity,
+            candidate.offer_type,
+            candidate.item,
+            candidate.price,
+            quantity,
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::init_memory_db, ledger::check_invariants, materials::Material, money::Money,
+        player::Player, production::Prod,
+    };
+    use rusqlite::Connection;
+
+    struct Outcome {
+        conn: Connection,
+        exchange: Exchange,
+        grain: Material,
+        /// Ids of the resting order that gets bid against, and of a
+        /// stranger's order at the same price behind it.
+        own_ask: i64,
+        other_ask: i64,
+        seller: u32,
+        buyer: u32,
+    }
+
+    impl Outcome {
+        // The resting orders as (id, quantity), after checking the stored
+        // book agrees with the one in memory.
+        fn book(&self) -> Vec<(i64, u32)> {
+            let sorted = |exchange: &Exchange| {
+                let mut book: Vec<_> = exchange
+                    .orders()
+                    .map(|order| (order.id, order.quantity))
+                    .collect();
+                book.sort();
+                book
+            };
+            let book = sorted(&self.exchange);
+            assert_eq!(sorted(&Exchange::load(&self.conn).unwrap()), book);
+            book
+        }
+
+        fn company(&self, id: u32) -> ProdInstance {
+            ProdInstance::load(&self.conn, id).unwrap().unwrap()
+        }
+    }
+
+    fn farm(conn: &Connection, owner: &mut Player, grain: Material) -> ProdInstance {
+        let base = Prod::lookup("grain_farm").unwrap();
+        let mut farm = ProdInstance::new(conn, base, "Farm".to_string(), owner).unwrap();
+        farm.earn(Money::from_dollars(100)).unwrap();
+        farm.add_material(grain, 100).unwrap();
+        farm.save(conn).unwrap();
+        farm
+    }
+
+    // A farm rests a sell of 40 grain at $1 and a stranger rests 20 more
+    // behind it. Then the farm itself, or another company with the same
+    // owner, bids for 30 at $1 using `mode`.
+    fn bid_against_own_ask(mode: SelfTradePrevention, same_company: bool) -> Outcome {
+        let conn = init_memory_db().unwrap();
+        let mut exchange = Exchange::load(&conn).unwrap();
+        let grain = Material::lookup("Grain").unwrap();
+        let price = Money::from_dollars(1);
+
+        let mut owner = Player::create(&conn, "owner").unwrap();
+        let mut stranger = Player::create(&conn, "stranger").unwrap();
+        owner.earn(Money::from_dollars(10_000)).unwrap();
+        stranger.earn(Money::from_dollars(10_000)).unwrap();
+
+        let mut seller = farm(&conn, &mut owner, grain);
+        let own_ask = seller
+            .quick_sell(&conn, &mut exchange, grain, price, 40)
+            .unwrap()
+            .unwrap();
+        let mut other = farm(&conn, &mut stranger, grain);
+        let other_ask = other
+            .quick_sell(&conn, &mut exchange, grain, price, 20)
+            .unwrap()
+            .unwrap();
+
+        let mut sibling = farm(&conn, &mut owner, grain);
+        let buyer = if same_company {
+            &mut seller
+        } else {
+            &mut sibling
+        };
+        let mut offer = Offer {
+            entity: EntityRef::Borrowed(buyer),
+            conn: &conn,
+            item: grain,
+            quantity: 30,
+            kind: OrderKind::Limit(price),
+            offer_type: OfferType::Buy,
+            time_in_force: TimeInForce::ImmediateOrCancel,
+            self_trade: mode,
+        };
+        assert_eq!(offer.execute(&mut exchange).unwrap(), None);
+        let buyer = buyer.id.unwrap();
+        let seller = seller.id.unwrap();
+
+        owner.save(&conn).unwrap();
+        stranger.save(&conn).unwrap();
+        assert_eq!(check_invariants(&conn).unwrap(), vec![]);
+        Outcome {
+            conn,
+            exchange,
+            grain,
+            own_ask,
+            other_ask,
+            seller,
+            buyer,
+        }
+    }
+
+    #[test]
+    fn cancel_newest_drops_the_incoming_offer() {
+        for same_company in [true, false] {
+            let outcome = bid_against_own_ask(SelfTradePrevention::CancelNewest, same_company);
+            assert_eq!(
+                outcome.book(),
+                vec![(outcome.own_ask, 40), (outcome.other_ask, 20)]
+            );
+
+            let seller = outcome.company(outcome.seller);
+            assert_eq!(seller.reserved(outcome.grain), 40);
+            assert_eq!(seller.available(outcome.grain), 60);
+            let buyer = outcome.company(outcome.buyer);
+            assert_eq!(buyer.reserved_usd(), Money::ZERO);
+            assert_eq!(buyer.available_usd(), Money::from_dollars(100));
+        }
+    }
+
+    #[test]
+    fn cancel_oldest_drops_the_resting_order_and_keeps_matching() {
+        for same_company in [true, false] {
+            let outcome = bid_against_own_ask(SelfTradePrevention::CancelOldest, same_company);
+            // The own ask is gone and the stranger's filled the bid.
+            assert_eq!(outcome.book(), vec![]);
+
+            let seller = outcome.company(outcome.seller);
+            let buyer = outcome.company(outcome.buyer);
+            assert_eq!(seller.reserved(outcome.grain), 0);
+            assert_eq!(buyer.reserved_usd(), Money::ZERO);
+            assert_eq!(buyer.available_usd(), Money::from_dollars(80));
+            if same_company {
+                assert_eq!(seller.available(outcome.grain), 120);
+            } else {
+                assert_eq!(seller.available(outcome.grain), 100);
+                assert_eq!(buyer.available(outcome.grain), 120);
+            }
+        }
+    }
+
+    #[test]
+    fn decrement_both_shrinks_both_orders_without_trading() {
+        for same_company in [true, false] {
+            let outcome = bid_against_own_ask(SelfTradePrevention::DecrementBoth, same_company);
+            assert_eq!(
+                outcome.book(),
+                vec![(outcome.own_ask, 10), (outcome.other_ask, 20)]
+            );
+
+            let seller = outcome.company(outcome.seller);
+            assert_eq!(seller.reserved(outcome.grain), 10);
+            assert_eq!(seller.available(outcome.grain), 90);
+            let buyer = outcome.company(outcome.buyer);
+            assert_eq!(buyer.reserved_usd(), Money::ZERO);
+            assert_eq!(buyer.available_usd(), Money::from_dollars(100));
+            if !same_company {
+                assert_eq!(buyer.available(outcome.grain), 100);
+            }
+        }
+    }
+}
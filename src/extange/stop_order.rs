@@ -0,0 +1,256 @@
+use super::*;
+use crate::{
+    db::atomic,
+    error::{EngineError, EngineResult},
+    materials::Material,
+    money::Money,
+    production::ProdInstance,
+};
+use rusqlite::Connection;
+use std::collections::HashSet;
+
+/// A stop or stop-limit order parked off the book until the last trade
+/// price in its material reaches the trigger. Its escrow stays locked while
+/// it waits.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StopOrder {
+    pub id: i64,
+    pub entity: u32,
+    pub offer_type: OfferType,
+    pub item: Material,
+    pub quantity: u32,
+    pub kind: OrderKind,
+}
+
+impl StopOrder {
+    /// Cash a parked buy keeps locked: its budget for a stop, its full value
+    /// at the limit for a stop-limit.
+    pub fn escrow_usd(&self) -> EngineResult<Money> {
+        match self.kind {
+            OrderKind::StopLimit { limit, .. } => limit.try_mul(self.quantity),
+            kind => Ok(kind.budget().unwrap_or(Money::ZERO)),
+        }
+    }
+
+    pub(super) fn reserve_escrow(&self, entity: &mut ProdInstance) -> EngineResult<()> {
+        match self.offer_type {
+            OfferType::Buy => entity.reserve_usd(self.escrow_usd()?),
+            OfferType::Sell => entity.reserve_material(self.item, self.quantity),
+        }
+    }
+
+    pub(super) fn release_escrow(&self, entity: &mut ProdInstance) -> EngineResult<()> {
+        match self.offer_type {
+            OfferType::Buy => entity.release_usd(self.escrow_usd()?)?,
+            OfferType::Sell => entity.release_material(self.item, self.quantity)?,
+        }
+        Ok(())
+    }
+}
+
+impl<'a, 'b> Offer<'a, 'b> {
+    /// Locks the stop's escrow and parks it until it triggers. Returns the
+    /// stop's id, which [`Exchange::cancel`] accepts like any order id.
+    pub(super) fn park_stop(&mut self, exchange: &mut Exchange) -> EngineResult<i64> {
+        let stop = StopOrder {
+            id: exchange.next_order_id(),
+            entity: self
+                .entity
+                .as_ref()
+                .id
+                .expect("Entity Id in stop order is None!"),
+            offer_type: self.offer_type,
+            item: self.item,
+            quantity: self.quantity,
+            kind: self.kind,
+        };
+
+        let conn = self.conn;
+        let entity_snapshot = self.entity.as_ref().clone();
+        let changes = vec![BookChange::InsertStop(stop.clone())];
+        let result = atomic(conn, || {
+            stop.reserve_escrow(self.entity.as_mut())?;
+            self.entity.as_mut().save(conn)?;
+            exchange.stage(conn, &changes)
+        });
+        if let Err(e) = result {
+            *self.entity.as_mut() = entity_snapshot;
+            return Err(e);
+        }
+
+        println!("⏸️ Stop order {} parked until {:?}.", stop.id, stop.kind);
+        exchange.commit(changes);
+        Ok(stop.id)
+    }
+}
+
+/// Outcome of an [`Exchange::fire_stops`] call.
+#[derive(Debug, Default)]
+pub struct StopReport {
+    /// Stops that triggered and ran, in the order they ran.
+    pub fired: Vec<i64>,
+    /// Stops that triggered but failed to run, and why. They stay parked.
+    pub failed: Vec<(i64, EngineError)>,
+}
+
+impl Exchange {
+    pub fn stop_order(&self, id: i64) -> Option<&StopOrder> {
+        self.stops.get(&id)
+    }
+
+    pub fn stop_orders(&self) -> impl Iterator<Item = &StopOrder> {
+        self.stops.values()
+    }
+
+    /// Runs every stop in `item` that the last trade price has reached,
+    /// oldest first. Fills from a triggered stop move the price again, so
+    /// this keeps going until no more stops trigger. A stop that fails to
+    /// run stays parked and is listed in the report with its error.
+    pub fn fire_stops(&mut self, conn: &Connection, item: Material) -> EngineResult<StopReport> {
+        let mut attempted = HashSet::new();
+        let mut report = StopReport::default();
+        loop {
+            let Some(last_price) = last_trade_price(conn, item)? else {
+                break;
+            };
+            let Some(stop) = self
+                .stops
+                .values()
+                .find(|stop| {
+                    stop.item == item
+                        && !attempted.contains(&stop.id)
+                        && stop.kind.is_triggered(stop.offer_type, last_price)
+                })
+                .cloned()
+            else {
+                break;
+            };
+
+            attempted.insert(stop.id);
+            println!("⚡ Stop order {} triggered at {}.", stop.id, last_price);
+            match self.trigger_stop(conn, &stop) {
+                Ok(()) => report.fired.push(stop.id),
+                Err(e) => report.failed.push((stop.id, e)),
+            }
+        }
+        Ok(report)
+    }
+
+    /// Takes `stop` off the stop list and submits the order it turns into.
+    /// Triggered orders are good till cancelled.
+    fn trigger_stop(&mut self, conn: &Connection, stop: &StopOrder) -> EngineResult<()> {
+        let mut entity = ProdInstance::load(conn, stop.entity)?
+            .ok_or(EngineError::CompanyNotFound(stop.entity))?;
+        stop.release_escrow(&mut entity)?;
+
+        let removal = BookChange::RemoveStop(stop.id);
+        self.apply_in_memory(&removal);
+        let result = atomic(conn, || {
+            self.stage(conn, std::slice::from_ref(&removal))?;
+            let mut offer = Offer {
+                entity: EntityRef::Owned(Box::new(entity)),
+                conn,
+                item: stop.item,
+                quantity: stop.quantity,
+                kind: stop.kind.triggered(),
+                offer_type: stop.offer_type,
+                time_in_force: TimeInForce::GoodTillCancelled,
+                self_trade: SelfTradePrevention::default(),
+            };
+            // Save first so the released escrow sticks even if the order
+            // turns out not to be valid any more.
+            offer.entity.as_mut().save(conn)?;
+            if offer.valid() {
+                offer.execute_now(self)?;
+            }
+            Ok(())
+        });
+        match result {
+            Ok(()) => {
+                self.defer(removal);
+                Ok(())
+            }
+            Err(e) => {
+                self.apply_in_memory(&BookChange::InsertStop(stop.clone()));
+                Err(e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db::init_memory_db, player::Player, production::Prod};
+
+    fn farm(conn: &Connection, owner: &str) -> ProdInstance {
+        let mut owner = Player::create(conn, owner).unwrap();
+        owner.earn(Money::from_dollars(10_000)).unwrap();
+        let base = Prod::lookup("grain_farm").unwrap();
+        let mut farm = ProdInstance::new(conn, base, "Farm".to_string(), &mut owner).unwrap();
+        farm.earn(Money::from_dollars(100)).unwrap();
+        farm.add_material(Material::lookup("Grain").unwrap(), 100)
+            .unwrap();
+        farm.save(conn).unwrap();
+        farm
+    }
+
+    fn offer<'a, 'b>(
+        conn: &'b Connection,
+        entity: &'a mut ProdInstance,
+        offer_type: OfferType,
+        kind: OrderKind,
+    ) -> Offer<'a, 'b> {
+        Offer {
+            entity: EntityRef::Borrowed(entity),
+            conn,
+            item: Material::lookup("Grain").unwrap(),
+            quantity: 5,
+            kind,
+            offer_type,
+            time_in_force: TimeInForce::GoodTillCancelled,
+            self_trade: SelfTradePrevention::default(),
+        }
+    }
+
+    #[test]
+    fn failing_stops_are_reported() {
+        let conn = init_memory_db().unwrap();
+        let mut exchange = Exchange::load(&conn).unwrap();
+        let price = Money::from_dollars(1);
+
+        let mut holder = farm(&conn, "holder");
+        let stop = OrderKind::Stop {
+            trigger: price,
+            budget: None,
+        };
+        let stop_id = offer(&conn, &mut holder, OfferType::Sell, stop)
+            .execute(&mut exchange)
+            .unwrap()
+            .unwrap();
+        // Lose the goods the stop has in escrow, so it can't run.
+        conn.execute("UPDATE company_inventory SET reserved = 0", [])
+            .unwrap();
+
+        let mut seller = farm(&conn, "seller");
+        offer(&conn, &mut seller, OfferType::Sell, OrderKind::Limit(price))
+            .execute(&mut exchange)
+            .unwrap();
+        let mut buyer = farm(&conn, "buyer");
+        let result = offer(&conn, &mut buyer, OfferType::Buy, OrderKind::Limit(price))
+            .execute(&mut exchange);
+
+        let Err(EngineError::StopsFailed { resting_id, failed }) = result else {
+            panic!("expected the failed stop to be reported, got {:?}", result);
+        };
+        assert_eq!(resting_id, None);
+        assert!(matches!(
+            failed.as_slice(),
+            [(id, EngineError::InsufficientMaterial { .. })] if *id == stop_id
+        ));
+        // The buy itself went through and the stop is still parked.
+        assert_eq!(buyer.available(Material::lookup("Grain").unwrap()), 105);
+        assert!(exchange.stop_order(stop_id).is_some());
+        assert!(Exchange::load(&conn).unwrap().stop_order(stop_id).is_some());
+    }
+}
@@ -0,0 +1,113 @@
+/// How long an offer may rest on the book.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum TimeInForce {
+    /// Rests until it is filled or cancelled.
+    #[default]
+    GoodTillCancelled,
+    /// Rests for this many cycles, then gets swept and refunded.
+    GoodForCycles(u32),
+    /// Fills what it can immediately and drops the remainder.
+    ImmediateOrCancel,
+    /// Fills completely right away, or not at all.
+    FillOrKill,
+}
+
+impl TimeInForce {
+    /// Whether an unfilled remainder should be written to the book.
+    pub fn rests(&self) -> bool {
+        matches!(
+            self,
+            TimeInForce::GoodTillCancelled | TimeInForce::GoodForCycles(_)
+        )
+    }
+
+    /// Cycle at which a resting order placed during `cycle` expires.
+    pub fn expires_at(&self, cycle: u32) -> Option<u32> {
+        match self {
+            TimeInForce::GoodForCycles(n) => Some(cycle.saturating_add(*n)),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::init_memory_db,
+        extange::{EntityRef, Exchange, Offer, OfferType, OrderKind, SelfTradePrevention},
+        ledger::check_invariants,
+        materials::Material,
+        money::Money,
+        player::Player,
+        production::{Prod, ProdInstance},
+    };
+    use rusqlite::Connection;
+
+    fn farm(conn: &Connection, owner: &mut Player, grain: Material) -> ProdInstance {
+        let base = Prod::lookup("grain_farm").unwrap();
+        let mut farm = ProdInstance::new(conn, base, "Farm".to_string(), owner).unwrap();
+        farm.earn(Money::from_dollars(100)).unwrap();
+        farm.add_material(grain, 100).unwrap();
+        farm.save(conn).unwrap();
+        farm
+    }
+
+    // A seller rests 20 grain at $1, then a buyer bids for `quantity` at $1
+    // with `time_in_force`. Returns what the bid left on the book, how much
+    // grain the buyer holds and how much of the ask is left.
+    fn bid(time_in_force: TimeInForce, quantity: u32) -> (Option<i64>, u32, u32) {
+        let conn = init_memory_db().unwrap();
+        let mut exchange = Exchange::load(&conn).unwrap();
+        let grain = Material::lookup("Grain").unwrap();
+        let price = Money::from_dollars(1);
+        let mut seller = Player::create(&conn, "seller").unwrap();
+        let mut buyer = Player::create(&conn, "buyer").unwrap();
+        seller.earn(Money::from_dollars(10_000)).unwrap();
+        buyer.earn(Money::from_dollars(10_000)).unwrap();
+
+        let ask = farm(&conn, &mut seller, grain)
+            .quick_sell(&conn, &mut exchange, grain, price, 20)
+            .unwrap()
+            .unwrap();
+        let mut company = farm(&conn, &mut buyer, grain);
+        let mut offer = Offer {
+            entity: EntityRef::Borrowed(&mut company),
+            conn: &conn,
+            item: grain,
+            quantity,
+            kind: OrderKind::Limit(price),
+            offer_type: OfferType::Buy,
+            time_in_force,
+            self_trade: SelfTradePrevention::default(),
+        };
+        let resting = offer.execute(&mut exchange).unwrap();
+
+        // Orders that don't rest never keep any cash back.
+        if !time_in_force.rests() {
+            assert_eq!(company.reserved_usd(), Money::ZERO);
+        }
+        assert_eq!(check_invariants(&conn).unwrap(), vec![]);
+        let left = exchange.order(ask).map_or(0, |order| order.quantity);
+        (resting, company.available(grain), left)
+    }
+
+    #[test]
+    fn immediate_or_cancel_drops_what_it_cannot_fill() {
+        assert_eq!(bid(TimeInForce::ImmediateOrCancel, 30), (None, 120, 0));
+        assert_eq!(bid(TimeInForce::ImmediateOrCancel, 5), (None, 105, 15));
+    }
+
+    #[test]
+    fn fill_or_kill_trades_everything_or_nothing() {
+        assert_eq!(bid(TimeInForce::FillOrKill, 30), (None, 100, 20));
+        assert_eq!(bid(TimeInForce::FillOrKill, 20), (None, 120, 0));
+    }
+
+    #[test]
+    fn resting_orders_keep_the_remainder() {
+        let (resting, bought, left) = bid(TimeInForce::GoodTillCancelled, 30);
+        assert!(resting.is_some());
+        assert_eq!((bought, left), (120, 0));
+    }
+}
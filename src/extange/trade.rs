@@ -0,0 +1,42 @@
+use crate::{db::current_cycle, error::EngineResult, materials::Material, money::Money};
+use rusqlite::{Connection, params};
+
+/// A single fill, as written to the `trades` table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trade {
+    pub buyer: u32,
+    pub seller: u32,
+    pub item: Material,
+    pub quantity: u32,
+    pub unit_price: Money,
+}
+
+impl Trade {
+    pub fn new(buyer: u32, seller: u32, item: Material, quantity: u32, unit_price: Money) -> Self {
+        Trade {
+            buyer,
+            seller,
+            item,
+            quantity,
+            unit_price,
+        }
+    }
+
+    /// Appends the trade to the log, stamped with the current cycle and time.
+    pub fn record(&self, conn: &Connection) -> EngineResult<i64> {
+        conn.execute(
+            "INSERT INTO trades (buyer, seller, item, amount, unit_price, cycle, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                self.buyer,
+                self.seller,
+                self.item.to_string_key(),
+                self.quantity,
+                self.unit_price,
+                current_cycle(conn)?,
+                chrono::Utc::now().to_rfc3339(),
+            ],
+        )?;
+        Ok(conn.last_insert_rowid())
+    }
+}
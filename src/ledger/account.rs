@@ -0,0 +1,161 @@
+use crate::{
+    error::{EngineError, EngineResult},
+    materials::Material,
+};
+use std::fmt;
+
+/// Who an account belongs to. The world is where money and goods come from
+/// when they enter the economy and go to when they leave it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Holder {
+    Player(u32),
+    Company(u32),
+    World,
+}
+
+/// What an account holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Asset {
+    /// Cash, counted in thousandths of a dollar.
+    Cash,
+    Goods(Material),
+}
+
+/// Whether the balance is free to use, locked behind an exchange order, or
+/// installed as a machine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Pocket {
+    Available,
+    Escrow,
+    Installed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Account {
+    pub holder: Holder,
+    pub pocket: Pocket,
+    pub asset: Asset,
+}
+
+impl Account {
+    pub const fn cash(holder: Holder) -> Self {
+        Account {
+            holder,
+            pocket: Pocket::Available,
+            asset: Asset::Cash,
+        }
+    }
+
+    pub const fn escrow_cash(holder: Holder) -> Self {
+        Account {
+            holder,
+            pocket: Pocket::Escrow,
+            asset: Asset::Cash,
+        }
+    }
+
+    pub const fn goods(holder: Holder, item: Material) -> Self {
+        Account {
+            holder,
+            pocket: Pocket::Available,
+            asset: Asset::Goods(item),
+        }
+    }
+
+    pub const fn escrow_goods(holder: Holder, item: Material) -> Self {
+        Account {
+            holder,
+            pocket: Pocket::Escrow,
+            asset: Asset::Goods(item),
+        }
+    }
+
+    /// Capital goods a company has installed as machines.
+    pub const fn installed(holder: Holder, item: Material) -> Self {
+        Account {
+            holder,
+            pocket: Pocket::Installed,
+            asset: Asset::Goods(item),
+        }
+    }
+
+    /// The world's account for `asset`.
+    pub const fn world(asset: Asset) -> Self {
+        Account {
+            holder: Holder::World,
+            pocket: Pocket::Available,
+            asset,
+        }
+    }
+
+    /// Parses a key written by [`Account`]'s `Display`, e.g.
+    /// `company:3:escrow:Grain`.
+    pub fn from_key(key: &str) -> EngineResult<Self> {
+        let corrupt = || EngineError::CorruptData(format!("Invalid ledger account: {}", key));
+        let mut parts = key.split(':');
+        let holder = match parts.next() {
+            Some("world") => Holder::World,
+            Some(kind @ ("player" | "company")) => {
+                let id = parts
+                    .next()
+                    .and_then(|id| id.parse().ok())
+                    .ok_or_else(corrupt)?;
+                if kind == "player" {
+                    Holder::Player(id)
+                } else {
+                    Holder::Company(id)
+                }
+            }
+            _ => return Err(corrupt()),
+        };
+        let (pocket, asset) = match (parts.next(), parts.next(), parts.next()) {
+            (Some("cash"), None, None) => (Pocket::Available, Asset::Cash),
+            (Some("escrow"), None, None) => (Pocket::Escrow, Asset::Cash),
+            (Some("goods"), Some(item), None) => {
+                (Pocket::Available, Asset::Goods(Material::lookup(item)?))
+            }
+            (Some("escrow"), Some(item), None) => {
+                (Pocket::Escrow, Asset::Goods(Material::lookup(item)?))
+            }
+            (Some("installed"), Some(item), None) => {
+                (Pocket::Installed, Asset::Goods(Material::lookup(item)?))
+            }
+            _ => return Err(corrupt()),
+        };
+        Ok(Account {
+            holder,
+            pocket,
+            asset,
+        })
+    }
+}
+
+impl fmt::Display for Holder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Holder::Player(id) => write!(f, "player:{}", id),
+            Holder::Company(id) => write!(f, "company:{}", id),
+            Holder::World => write!(f, "world"),
+        }
+    }
+}
+
+impl fmt::Display for Account {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.pocket, self.asset) {
+            (Pocket::Available, Asset::Cash) => write!(f, "{}:cash", self.holder),
+            (Pocket::Escrow, Asset::Cash) => write!(f, "{}:escrow", self.holder),
+            (Pocket::Available, Asset::Goods(item)) => {
+                write!(f, "{}:goods:{}", self.holder, item.to_string_key())
+            }
+            (Pocket::Escrow, Asset::Goods(item)) => {
+                write!(f, "{}:escrow:{}", self.holder, item.to_string_key())
+            }
+            // Only goods are ever installed.
+            (Pocket::Installed, Asset::Cash) => write!(f, "{}:installed", self.holder),
+            (Pocket::Installed, Asset::Goods(item)) => {
+                write!(f, "{}:installed:{}", self.holder, item.to_string_key())
+            }
+        }
+    }
+}
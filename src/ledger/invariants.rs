@@ -0,0 +1,73 @@
+use super::*;
+use crate::{error::EngineResult, materials::Material, money::Money, production::ProdInstance};
+use rusqlite::Connection;
+
+/// An account whose ledger balance disagrees with what its owner has stored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub account: Account,
+    pub ledger: i64,
+    pub stored: i64,
+}
+
+/// Replays the ledger against every saved company and player.
+///
+/// Each entry moves value between two accounts holding the same asset, so
+/// the ledger itself always sums to zero per asset. If every stored balance
+/// also matches its ledger balance, nothing was created or destroyed outside
+/// the world accounts. Returns the accounts that don't match; an empty list
+/// means money and goods are conserved.
+pub fn check_invariants(conn: &Connection) -> EngineResult<Vec<Violation>> {
+    let mut violations = Vec::new();
+    let mut check = |account: Account, stored: i64| -> EngineResult<()> {
+        let ledger = balance(conn, &account)?;
+        if ledger != stored {
+            violations.push(Violation {
+                account,
+                ledger,
+                stored,
+            });
+        }
+        Ok(())
+    };
+
+    let company_ids = conn
+        .prepare("SELECT id FROM company ORDER BY id")?
+        .query_map([], |row| row.get::<_, u32>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    for id in company_ids {
+        let Some(company) = ProdInstance::load(conn, id)? else {
+            continue;
+        };
+        let holder = Holder::Company(id);
+        check(Account::cash(holder), company.usd.milli())?;
+        check(Account::escrow_cash(holder), company.reserved_usd.milli())?;
+        let installed = company.installed()?;
+        for &item in Material::all() {
+            check(
+                Account::goods(holder, item),
+                company.owns.amount_of(item) as i64,
+            )?;
+            check(
+                Account::escrow_goods(holder, item),
+                company.reserved.amount_of(item) as i64,
+            )?;
+            check(
+                Account::installed(holder, item),
+                installed.amount_of(item) as i64,
+            )?;
+        }
+    }
+
+    let players = conn
+        .prepare("SELECT id, usd FROM user ORDER BY id")?
+        .query_map([], |row| {
+            Ok((row.get::<_, u32>(0)?, row.get::<_, Money>(1)?))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    for (id, usd) in players {
+        check(Account::cash(Holder::Player(id)), usd.milli())?;
+    }
+
+    Ok(violations)
+}
@@ -0,0 +1,155 @@
+use super::*;
+use crate::{
+    db::current_cycle,
+    error::{EngineError, EngineResult},
+};
+use rusqlite::{Connection, params};
+
+/// Why value moved between two accounts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Reason {
+    /// Value created out of the world, e.g. seed money.
+    Grant,
+    /// Value handed back to the world.
+    Spend,
+    /// A player buying a company.
+    Purchase,
+    /// A company's running costs for a cycle.
+    Upkeep,
+    /// Locked behind an exchange order.
+    Reserve,
+    /// Unlocked when an order fills short, expires or is cancelled.
+    Release,
+    /// Settlement of an exchange fill.
+    Trade,
+    /// Output of a production shift.
+    Produce,
+    /// Recipe inputs used up by a production shift.
+    Consume,
+    /// A capital good put to work as a machine.
+    Install,
+    /// A machine that reached the end of its life.
+    Scrap,
+    /// A company paying a worker for a shift.
+    Wage,
+}
+
+impl Reason {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Reason::Grant => "grant",
+            Reason::Spend => "spend",
+            Reason::Purchase => "purchase",
+            Reason::Upkeep => "upkeep",
+            Reason::Reserve => "reserve",
+            Reason::Release => "release",
+            Reason::Trade => "trade",
+            Reason::Produce => "produce",
+            Reason::Consume => "consume",
+            Reason::Install => "install",
+            Reason::Scrap => "scrap",
+            Reason::Wage => "wage",
+        }
+    }
+
+    pub fn from_str(reason: &str) -> EngineResult<Self> {
+        Ok(match reason {
+            "grant" => Reason::Grant,
+            "spend" => Reason::Spend,
+            "purchase" => Reason::Purchase,
+            "upkeep" => Reason::Upkeep,
+            "reserve" => Reason::Reserve,
+            "release" => Reason::Release,
+            "trade" => Reason::Trade,
+            "produce" => Reason::Produce,
+            "consume" => Reason::Consume,
+            "install" => Reason::Install,
+            "scrap" => Reason::Scrap,
+            "wage" => Reason::Wage,
+            _ => {
+                return Err(EngineError::CorruptData(format!(
+                    "Invalid ledger reason: {}",
+                    reason
+                )));
+            }
+        })
+    }
+}
+
+/// One movement of `amount` from `credit` into `debit`. Cash is counted in
+/// thousandths of a dollar and goods in units, so both sides of an entry
+/// always hold the same asset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JournalEntry {
+    pub debit: Account,
+    pub credit: Account,
+    pub amount: i64,
+    pub reason: Reason,
+}
+
+/// A journal entry as written to the ledger.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PostedEntry {
+    pub id: i64,
+    pub cycle: u32,
+    pub entry: JournalEntry,
+}
+
+/// Entries an entity has posted since it was last saved. They reach the
+/// ledger together with the entity's row, so a rolled back save drops both.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Journal(Vec<JournalEntry>);
+
+impl Journal {
+    pub fn post(&mut self, debit: Account, credit: Account, amount: i64, reason: Reason) {
+        debug_assert_eq!(debit.asset, credit.asset, "ledger entry mixes assets");
+        if amount != 0 {
+            self.0.push(JournalEntry {
+                debit,
+                credit,
+                amount,
+                reason,
+            });
+        }
+    }
+
+    pub fn entries(&self) -> &[JournalEntry] {
+        &self.0
+    }
+
+    /// Points entries posted by `from` at `to` instead. Used once a new
+    /// entity has been given its id.
+    pub fn rebind(&mut self, from: Holder, to: Holder) {
+        for entry in self.0.iter_mut() {
+            for account in [&mut entry.debit, &mut entry.credit] {
+                if account.holder == from {
+                    account.holder = to;
+                }
+            }
+        }
+    }
+
+    /// Writes the pending entries to the ledger at the current cycle.
+    pub fn commit(&mut self, conn: &Connection) -> EngineResult<()> {
+        if self.0.is_empty() {
+            return Ok(());
+        }
+        let cycle = current_cycle(conn)?;
+        let mut stmt = conn.prepare_cached(
+            "INSERT INTO ledger (cycle, reason, debit, credit, amount, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        )?;
+        for entry in self.0.iter() {
+            stmt.execute(params![
+                cycle,
+                entry.reason.as_str(),
+                entry.debit.to_string(),
+                entry.credit.to_string(),
+                entry.amount,
+                chrono::Utc::now().to_rfc3339(),
+            ])?;
+        }
+        self.0.clear();
+        Ok(())
+    }
+}
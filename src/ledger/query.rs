@@ -0,0 +1,55 @@
+use super::*;
+use crate::error::EngineResult;
+use rusqlite::{Connection, params};
+
+/// Every ledger entry touching one of `holder`'s accounts, oldest first.
+pub fn entries_for(conn: &Connection, holder: Holder) -> EngineResult<Vec<PostedEntry>> {
+    let pattern = format!("{}:%", holder);
+    let mut stmt = conn.prepare(
+        "SELECT id, cycle, reason, debit, credit, amount
+         FROM ledger
+         WHERE debit LIKE ?1 OR credit LIKE ?1
+         ORDER BY id ASC",
+    )?;
+    let rows = stmt.query_map(params![pattern], |row| {
+        Ok((
+            row.get::<_, i64>(0)?,
+            row.get::<_, u32>(1)?,
+            row.get::<_, String>(2)?,
+            row.get::<_, String>(3)?,
+            row.get::<_, String>(4)?,
+            row.get::<_, i64>(5)?,
+        ))
+    })?;
+
+    let mut entries = Vec::new();
+    for row in rows {
+        let (id, cycle, reason, debit, credit, amount) = row?;
+        entries.push(PostedEntry {
+            id,
+            cycle,
+            entry: JournalEntry {
+                debit: Account::from_key(&debit)?,
+                credit: Account::from_key(&credit)?,
+                amount,
+                reason: Reason::from_str(&reason)?,
+            },
+        });
+    }
+    Ok(entries)
+}
+
+/// What the ledger says `account` holds: everything debited to it minus
+/// everything credited from it. World accounts go negative by whatever has
+/// entered the economy.
+pub fn balance(conn: &Connection, account: &Account) -> EngineResult<i64> {
+    let balance = conn.query_row(
+        "SELECT COALESCE(SUM(CASE WHEN debit = ?1 THEN amount ELSE 0 END), 0)
+              - COALESCE(SUM(CASE WHEN credit = ?1 THEN amount ELSE 0 END), 0)
+         FROM ledger
+         WHERE debit = ?1 OR credit = ?1",
+        params![account.to_string()],
+        |row| row.get(0),
+    )?;
+    Ok(balance)
+}
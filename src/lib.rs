@@ -1,7 +1,11 @@
 #![allow(dead_code)]
+mod cycle;
 mod db;
+mod error;
 mod extange;
+mod ledger;
 mod macros;
 mod materials;
+mod money;
 mod player;
 mod production;
@@ -1,60 +1,98 @@
+#![allow(dead_code)]
 use rusqlite::{Connection, Result};
+use std::collections::HashMap;
 
 use crate::{
-    db::init_db,
+    cycle::next_cycle,
+    db::{current_cycle, init_db},
+    extange::Exchange,
+    money::Money,
     player::Player,
-    production::{ALL_PRODS, ProdInstance},
+    production::{Contract, Prod, ProdInstance, Wage},
 };
 
+mod cycle;
 mod db;
+mod error;
 mod extange;
+mod ledger;
 mod macros;
 mod materials;
+mod money;
 mod player;
 mod production;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("OurEconomy engine test runner starting...");
-    let conn: Connection = init_db().expect("Db didnt connect");
-    let mut player: Player = Player::blank();
-    player.earn(500_000);
-    for prod_base in ALL_PRODS[..3].iter() {
+    let path = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "main.db".to_string());
+    let conn: Connection = init_db(&path).expect("Db didnt connect");
+    let mut exchange: Exchange = Exchange::load(&conn)?;
+    let mut player: Player = match Player::load_by_username(&conn, "admin")? {
+        Some(player) => player,
+        None => Player::create(&conn, "admin")?,
+    };
+    player.earn(Money::from_dollars(500_000))?;
+    // The suppliers belong to someone else, so the admin's food plant isn't
+    // kept from buying from them as a self-trade.
+    let mut supplier: Player = match Player::load_by_username(&conn, "supplier")? {
+        Some(player) => player,
+        None => Player::create(&conn, "supplier")?,
+    };
+    supplier.earn(Money::from_dollars(500_000))?;
+    let cycle = current_cycle(&conn)?;
+    let contract = Contract {
+        wage: Wage::PerShift(Money::from_dollars(10)),
+        duration: None,
+        notice: 1,
+    };
+    for prod_id in ["water_company", "power_plant", "grain_farm"] {
         let mut prod: ProdInstance = ProdInstance::new(
             &conn,
-            prod_base,
-            "Admin Production Facility".to_string(),
-            &mut player,
-        )?
-        .expect("Prod Creation Failed");
+            Prod::lookup(prod_id)?,
+            "Supplier Production Facility".to_string(),
+            &mut supplier,
+        )?;
 
-        prod.earn(100_000.0);
+        prod.earn(Money::from_dollars(100_000))?;
 
-        let _ = prod.hire_worker(&player);
+        let _ = prod.hire_worker(&supplier, contract, cycle);
 
         prod.reset_workers();
 
-        let _ = prod.human_worked(&mut player);
+        let _ = prod.human_worked(&mut supplier, cycle);
+        prod.save_with_workers(&conn, std::slice::from_mut(&mut supplier))?;
 
-        prod.quick_sell(&conn, prod.creates, 0.1, 100);
-
-        let _ = prod.save(&conn);
+        if let Some(item) = prod.recipe.primary_output()
+            && let Err(e) = prod.quick_sell(&conn, &mut exchange, item, Money::from_milli(100), 100)
+        {
+            println!("Sell offer for {} failed: {}", prod.name, e);
+        }
     }
     let mut food_prod: ProdInstance = ProdInstance::new(
         &conn,
-        &ALL_PRODS[3],
+        Prod::lookup("food_processing_plant")?,
         "Admin Production Facility".to_string(),
         &mut player,
-    )?
-    .expect("Prod Creation Failed");
+    )?;
 
-    food_prod.earn(100_000.0);
+    food_prod.earn(Money::from_dollars(100_000))?;
 
-    let _ = food_prod.buy_needed(&conn, 5);
-    let _ = food_prod.hire_worker(&player);
+    let report = food_prod.buy_needed(&conn, &mut exchange, 5, &HashMap::new())?;
+    println!("Procurement: {:?}", report);
+    let _ = food_prod.hire_worker(&player, contract, cycle);
 
     food_prod.reset_workers();
 
-    let _ = food_prod.human_worked(&mut player);
-    let _ = food_prod.save(&conn);
+    let _ = food_prod.human_worked(&mut player, cycle);
+    food_prod.save_with_workers(&conn, std::slice::from_mut(&mut player))?;
+    supplier.save(&conn)?;
+
+    let report = next_cycle(&conn, &mut exchange)?;
+    println!("Cycle {}: {} orders expired", report.cycle, report.expired);
+    for (id, e) in report.failed.iter() {
+        println!("Company {} could not start the cycle: {}", id, e);
+    }
     Ok(())
 }
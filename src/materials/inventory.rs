@@ -0,0 +1,106 @@
+use super::Material;
+use crate::error::{EngineError, EngineResult};
+use std::collections::BTreeMap;
+
+/// Amounts of each material held. Materials with nothing held have no entry,
+/// so two inventories holding the same goods always compare equal.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Inventory(BTreeMap<Material, u32>);
+
+impl Inventory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `amount`. Fails with [`EngineError::MaterialOverflow`], leaving
+    /// the inventory as it was, if the total wouldn't fit.
+    pub fn add(&mut self, mat: Material, amount: u32) -> EngineResult<()> {
+        if amount > 0 {
+            let total = self
+                .amount_of(mat)
+                .checked_add(amount)
+                .ok_or(EngineError::MaterialOverflow(mat))?;
+            self.0.insert(mat, total);
+        }
+        Ok(())
+    }
+
+    /// Takes `amount` away. Fails with [`EngineError::InsufficientMaterial`],
+    /// leaving the inventory as it was, if less than that is held.
+    pub fn remove(&mut self, mat: Material, amount: u32) -> EngineResult<()> {
+        let held = self.amount_of(mat);
+        if amount > held {
+            return Err(EngineError::InsufficientMaterial {
+                item: mat,
+                needed: amount,
+                available: held,
+            });
+        }
+        if held == amount {
+            self.0.remove(&mat);
+        } else {
+            self.0.insert(mat, held - amount);
+        }
+        Ok(())
+    }
+
+    pub fn amount_of(&self, mat: Material) -> u32 {
+        self.0.get(&mat).copied().unwrap_or(0)
+    }
+
+    /// Every material held, with its amount.
+    pub fn iter(&self) -> impl Iterator<Item = (Material, u32)> + '_ {
+        self.0.iter().map(|(&mat, &amount)| (mat, amount))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adding_past_the_limit_fails_and_changes_nothing() {
+        let grain = Material::lookup("Grain").unwrap();
+        let mut inventory = Inventory::new();
+        inventory.add(grain, u32::MAX - 1).unwrap();
+        let before = inventory.clone();
+
+        assert!(matches!(
+            inventory.add(grain, 2),
+            Err(EngineError::MaterialOverflow(item)) if item == grain
+        ));
+        assert_eq!(inventory, before);
+        inventory.add(grain, 1).unwrap();
+        assert_eq!(inventory.amount_of(grain), u32::MAX);
+    }
+
+    #[test]
+    fn removing_more_than_is_held_fails_and_changes_nothing() {
+        let grain = Material::lookup("Grain").unwrap();
+        let water = Material::lookup("Water").unwrap();
+        let mut inventory = Inventory::new();
+        inventory.add(grain, 5).unwrap();
+        let before = inventory.clone();
+
+        assert!(matches!(
+            inventory.remove(grain, 6),
+            Err(EngineError::InsufficientMaterial { item, needed: 6, available: 5 })
+                if item == grain
+        ));
+        assert!(matches!(
+            inventory.remove(water, 1),
+            Err(EngineError::InsufficientMaterial { available: 0, .. })
+        ));
+        assert_eq!(inventory, before);
+
+        // Emptied materials leave no entry behind.
+        inventory.remove(grain, 5).unwrap();
+        inventory.add(water, 0).unwrap();
+        assert!(inventory.is_empty());
+        assert_eq!(inventory, Inventory::new());
+    }
+}
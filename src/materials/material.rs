@@ -1,8 +1,69 @@
-use crate::define_materials;
+use super::{MaterialDef, registry};
+use crate::{
+    error::{EngineError, EngineResult},
+    money::Money,
+};
+use std::fmt;
 
-define_materials! {
-    Grain => (grain, "kg"),
-    Electricity => (electricity, "kWh"),
-    Water => (water, "liters"),
-    Food => (food, "packages"),
+/// A good from the material registry. Cheap to copy and compare; the
+/// definition behind it is looked up in [`registry`].
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Material(pub(super) u16);
+
+impl Material {
+    pub fn def(self) -> &'static MaterialDef {
+        registry().def(self)
+    }
+
+    pub fn unit(&self) -> &'static str {
+        &self.def().unit
+    }
+
+    pub fn display_name(&self) -> &'static str {
+        &self.def().name
+    }
+
+    pub fn category(&self) -> &'static str {
+        &self.def().category
+    }
+
+    /// Cycles the good keeps before it spoils, or `None` if it never does.
+    pub fn perishable_after(&self) -> Option<u32> {
+        self.def().perishable_after
+    }
+
+    pub fn base_price(&self) -> Money {
+        self.def().base_price
+    }
+
+    pub fn from_str(name: &str) -> Option<Material> {
+        registry().get(name)
+    }
+
+    /// Like [`Material::from_str`], failing with
+    /// [`EngineError::UnknownMaterial`] for ids the registry doesn't define.
+    pub fn lookup(id: &str) -> EngineResult<Material> {
+        Material::from_str(id).ok_or_else(|| EngineError::UnknownMaterial(id.to_string()))
+    }
+
+    /// The id the material is stored under in the database and in data files.
+    pub fn to_string_key(self) -> &'static str {
+        &self.def().id
+    }
+
+    pub fn all() -> &'static [Material] {
+        registry().all()
+    }
+}
+
+impl fmt::Debug for Material {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.to_string_key())
+    }
+}
+
+impl fmt::Display for Material {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.display_name(), self.unit())
+    }
 }
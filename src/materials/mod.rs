@@ -1,3 +1,3 @@
 use crate::flatten_modules;
-mod material_macro;
-flatten_modules!(material, recipies);
+
+flatten_modules!(material, registry, inventory, recipies);
@@ -2,40 +2,56 @@ use super::Material;
 use std::borrow::Cow;
 use std::fmt;
 
-#[derive(Debug, Clone)]
+/// What one batch of production uses up and what it yields. A batch can
+/// yield several goods, e.g. grain with straw as a byproduct.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Recipe<'a> {
     pub inputs: Cow<'a, [(Material, u32)]>,
+    pub outputs: Cow<'a, [(Material, u32)]>,
 }
 
 impl<'a> Recipe<'a> {
-    pub const fn food() -> Recipe<'static> {
-        Recipe {
-            inputs: Cow::Borrowed(&[
-                (Material::Electricity, 10),
-                (Material::Water, 5),
-                (Material::Grain, 5),
-            ]),
-        }
-    }
-
     pub const fn empty() -> Recipe<'static> {
         Recipe {
             inputs: Cow::Borrowed(&[]),
+            outputs: Cow::Borrowed(&[]),
         }
     }
 
-    pub fn dynamic(inputs: Vec<(Material, u32)>) -> Recipe<'static> {
+    pub fn dynamic(inputs: Vec<(Material, u32)>, outputs: Vec<(Material, u32)>) -> Recipe<'static> {
         Recipe {
             inputs: Cow::Owned(inputs),
+            outputs: Cow::Owned(outputs),
         }
     }
+
+    /// The first output listed, which is what the building is for. Any
+    /// others are byproducts.
+    pub fn primary_output(&self) -> Option<Material> {
+        self.outputs.first().map(|(mat, _)| *mat)
+    }
+
+    /// How much of `item` one batch uses up.
+    pub fn needs(&self, item: Material) -> u32 {
+        self.inputs
+            .iter()
+            .find(|(mat, _)| *mat == item)
+            .map_or(0, |(_, amount)| *amount)
+    }
+
+    pub fn produces(&self, item: Material) -> bool {
+        self.outputs.iter().any(|(mat, _)| *mat == item)
+    }
 }
 
 impl fmt::Display for Recipe<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         writeln!(f, "Recipe:")?;
-        for (mat, amount) in self.inputs.iter() {
-            writeln!(f, "- {} {}", amount, mat.unit())?;
+        for (label, goods) in [("Inputs", &self.inputs), ("Outputs", &self.outputs)] {
+            writeln!(f, "  {}:", label)?;
+            for (mat, amount) in goods.iter() {
+                writeln!(f, "  - {} {} {}", amount, mat.unit(), mat.display_name())?;
+            }
         }
         Ok(())
     }
@@ -0,0 +1,262 @@
+use super::Material;
+use crate::{
+    error::{EngineError, EngineResult},
+    money::Money,
+};
+use json::JsonValue;
+use std::{collections::HashMap, path::Path, sync::OnceLock};
+
+/// The catalog shipped with the engine, used unless another one is
+/// installed before the first material lookup.
+const DEFAULT_MATERIALS: &str = include_str!("../../data/materials.json");
+
+static REGISTRY: OnceLock<MaterialRegistry> = OnceLock::new();
+
+/// One good as described in the material file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MaterialDef {
+    /// Stable key used in the database, in data files and in ledger
+    /// accounts. Letters, digits and underscores only.
+    pub id: String,
+    pub name: String,
+    pub unit: String,
+    pub category: String,
+    pub perishable_after: Option<u32>,
+    pub base_price: Money,
+}
+
+/// Every material the game knows about, in file order.
+#[derive(Debug)]
+pub struct MaterialRegistry {
+    defs: Vec<MaterialDef>,
+    materials: Vec<Material>,
+    by_id: HashMap<String, Material>,
+}
+
+impl MaterialRegistry {
+    /// Parses a material file:
+    ///
+    /// ```json
+    /// { "materials": [ { "id": "Grain", "name": "Grain", "unit": "kg",
+    ///   "category": "raw", "perishable_after": 30, "base_price": 0.5 } ] }
+    /// ```
+    ///
+    /// `perishable_after` may be `null` or left out for goods that keep, and
+    /// `base_price` is in dollars.
+    pub fn from_json(source: &str) -> EngineResult<Self> {
+        let root = json::parse(source)
+            .map_err(|e| invalid(format!("material file is not valid JSON: {}", e)))?;
+        if !root["materials"].is_array() {
+            return Err(invalid(
+                "material file has no \"materials\" list".to_string(),
+            ));
+        }
+
+        let mut registry = MaterialRegistry {
+            defs: Vec::new(),
+            materials: Vec::new(),
+            by_id: HashMap::new(),
+        };
+        for entry in root["materials"].members() {
+            let def = parse_def(entry)?;
+            if registry.by_id.contains_key(&def.id) {
+                return Err(invalid(format!("material {} is defined twice", def.id)));
+            }
+            let index = u16::try_from(registry.defs.len())
+                .map_err(|_| invalid("too many materials".to_string()))?;
+            let material = Material(index);
+            registry.by_id.insert(def.id.clone(), material);
+            registry.materials.push(material);
+            registry.defs.push(def);
+        }
+        Ok(registry)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> EngineResult<Self> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path)
+            .map_err(|e| invalid(format!("can't read {}: {}", path.display(), e)))?;
+        Self::from_json(&source)
+    }
+
+    pub fn get(&self, id: &str) -> Option<Material> {
+        self.by_id.get(id).copied()
+    }
+
+    pub fn def(&self, material: Material) -> &MaterialDef {
+        &self.defs[material.0 as usize]
+    }
+
+    pub fn all(&self) -> &[Material] {
+        &self.materials
+    }
+}
+
+/// Makes `registry` the one every [`Material`] resolves against. Has to
+/// happen before anything looks a material up; fails if a registry is
+/// already in use.
+pub fn install_registry(registry: MaterialRegistry) -> EngineResult<()> {
+    REGISTRY
+        .set(registry)
+        .map_err(|_| invalid("a material registry is already in use".to_string()))
+}
+
+/// The registry in use, loading the default catalog on first use.
+pub fn registry() -> &'static MaterialRegistry {
+    REGISTRY.get_or_init(|| {
+        MaterialRegistry::from_json(DEFAULT_MATERIALS).expect("default material file is invalid")
+    })
+}
+
+fn parse_def(entry: &JsonValue) -> EngineResult<MaterialDef> {
+    let text = |field: &str| -> EngineResult<String> {
+        entry[field]
+            .as_str()
+            .filter(|value| !value.is_empty())
+            .map(str::to_string)
+            .ok_or_else(|| invalid(format!("material {} has no {}", entry["id"], field)))
+    };
+
+    let id = text("id")?;
+    if !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(invalid(format!(
+            "material id {:?} may only use letters, digits and underscores",
+            id
+        )));
+    }
+
+    let perishable_after =
+        match &entry["perishable_after"] {
+            JsonValue::Null => None,
+            value => Some(value.as_u32().ok_or_else(|| {
+                invalid(format!("material {} has an invalid perishable_after", id))
+            })?),
+        };
+
+    let base_price = entry["base_price"]
+        .as_f64()
+        .and_then(Money::from_decimal)
+        .filter(|price| !price.is_negative())
+        .ok_or_else(|| invalid(format!("material {} has an invalid base_price", id)))?;
+
+    Ok(MaterialDef {
+        name: text("name")?,
+        unit: text("unit")?,
+        category: text("category")?,
+        perishable_after,
+        base_price,
+        id,
+    })
+}
+
+fn invalid(reason: String) -> EngineError {
+    EngineError::InvalidDefinition(reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A material file holding `entries`, each a JSON object body.
+    fn file(entries: &[&str]) -> String {
+        let entries: Vec<String> = entries.iter().map(|e| format!("{{ {} }}", e)).collect();
+        format!("{{ \"materials\": [{}] }}", entries.join(", "))
+    }
+
+    fn entry(id: &str, perishable_after: &str) -> String {
+        format!(
+            "\"id\": \"{}\", \"name\": \"{}\", \"unit\": \"kg\", \"category\": \"raw\", \
+             \"base_price\": 1.25{}",
+            id, id, perishable_after
+        )
+    }
+
+    fn rejects(source: &str, reason: &str) {
+        match MaterialRegistry::from_json(source) {
+            Err(EngineError::InvalidDefinition(message)) => {
+                assert!(message.contains(reason), "{:?} lacks {:?}", message, reason)
+            }
+            other => panic!("expected {:?}, got {:?}", reason, other),
+        }
+    }
+
+    #[test]
+    fn materials_are_numbered_in_file_order() {
+        let registry = MaterialRegistry::from_json(&file(&[
+            &entry("Grain", ", \"perishable_after\": 30"),
+            &entry("Iron_Ore2", ""),
+        ]))
+        .unwrap();
+
+        assert_eq!(registry.all(), &[Material(0), Material(1)]);
+        assert_eq!(registry.get("Iron_Ore2"), Some(Material(1)));
+        assert_eq!(registry.get("iron_ore2"), None);
+        let grain = registry.def(Material(0));
+        assert_eq!(grain.unit, "kg");
+        assert_eq!(grain.perishable_after, Some(30));
+        assert_eq!(grain.base_price, Money::from_milli(1_250));
+    }
+
+    #[test]
+    fn perishable_after_may_be_null_or_missing_but_not_anything_else() {
+        let registry = MaterialRegistry::from_json(&file(&[
+            &entry("Null", ", \"perishable_after\": null"),
+            &entry("Missing", ""),
+        ]))
+        .unwrap();
+        for material in registry.all() {
+            assert_eq!(registry.def(*material).perishable_after, None);
+        }
+
+        for bad in ["-1", "1.5", "\"30\"", "true"] {
+            rejects(
+                &file(&[&entry("Grain", &format!(", \"perishable_after\": {}", bad))]),
+                "invalid perishable_after",
+            );
+        }
+    }
+
+    #[test]
+    fn duplicate_and_malformed_ids_are_rejected() {
+        rejects(
+            &file(&[&entry("Grain", ""), &entry("Grain", "")]),
+            "Grain is defined twice",
+        );
+        for id in ["Iron Ore", "Grain-2", "Grän", ""] {
+            let reason = if id.is_empty() {
+                "has no id"
+            } else {
+                "may only use"
+            };
+            rejects(&file(&[&entry(id, "")]), reason);
+        }
+    }
+
+    #[test]
+    fn missing_fields_and_bad_prices_are_rejected() {
+        rejects("not json", "not valid JSON");
+        rejects("{ \"materials\": {} }", "no \"materials\" list");
+        rejects(
+            &file(&[
+                "\"id\": \"Grain\", \"name\": \"Grain\", \"category\": \"raw\", \"base_price\": 1",
+            ]),
+            "Grain has no unit",
+        );
+        rejects(
+            &file(&[&entry("Grain", "").replace("1.25", "-1")]),
+            "invalid base_price",
+        );
+        rejects(
+            &file(&[&entry("Grain", "").replace("1.25", "\"cheap\"")]),
+            "invalid base_price",
+        );
+    }
+
+    #[test]
+    fn the_shipped_file_loads() {
+        let registry = MaterialRegistry::from_json(DEFAULT_MATERIALS).unwrap();
+        for id in ["Grain", "Electricity", "Water", "Food"] {
+            assert!(registry.get(id).is_some(), "{} is missing", id);
+        }
+    }
+}
@@ -0,0 +1,198 @@
+use crate::error::{EngineError, EngineResult};
+use rusqlite::types::{FromSql, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use std::fmt;
+
+/// An amount of USD, stored as a whole number of thousandths of a dollar so
+/// sums over many trades stay exact.
+///
+/// There are no arithmetic operators on purpose: every operation is checked
+/// and overflow surfaces as [`EngineError::MoneyOverflow`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Money(i64);
+
+impl Money {
+    /// Thousandths of a dollar per dollar.
+    pub const SCALE: i64 = 1000;
+    pub const ZERO: Money = Money(0);
+    pub const MIN: Money = Money(i64::MIN);
+    pub const MAX: Money = Money(i64::MAX);
+
+    pub const fn from_milli(milli: i64) -> Self {
+        Money(milli)
+    }
+
+    pub const fn from_dollars(dollars: u32) -> Self {
+        Money(dollars as i64 * Self::SCALE)
+    }
+
+    /// Rounds a dollar amount from a data file to the nearest thousandth.
+    /// `None` if it isn't a finite amount that fits.
+    pub fn from_decimal(dollars: f64) -> Option<Self> {
+        let milli = (dollars * Self::SCALE as f64).round();
+        // i64::MAX rounds up to 2^63 as a float, which no longer fits.
+        (milli.is_finite() && milli >= i64::MIN as f64 && milli < i64::MAX as f64)
+            .then_some(Money(milli as i64))
+    }
+
+    pub const fn milli(self) -> i64 {
+        self.0
+    }
+
+    pub const fn is_zero(self) -> bool {
+        self.0 == 0
+    }
+
+    pub const fn is_negative(self) -> bool {
+        self.0 < 0
+    }
+
+    pub fn checked_add(self, other: Money) -> Option<Money> {
+        self.0.checked_add(other.0).map(Money)
+    }
+
+    pub fn checked_sub(self, other: Money) -> Option<Money> {
+        self.0.checked_sub(other.0).map(Money)
+    }
+
+    /// Value of `quantity` units at this price.
+    pub fn checked_mul(self, quantity: u32) -> Option<Money> {
+        self.0.checked_mul(quantity as i64).map(Money)
+    }
+
+    pub fn try_add(self, other: Money) -> EngineResult<Money> {
+        self.checked_add(other).ok_or(EngineError::MoneyOverflow)
+    }
+
+    pub fn try_sub(self, other: Money) -> EngineResult<Money> {
+        self.checked_sub(other).ok_or(EngineError::MoneyOverflow)
+    }
+
+    pub fn try_mul(self, quantity: u32) -> EngineResult<Money> {
+        self.checked_mul(quantity).ok_or(EngineError::MoneyOverflow)
+    }
+
+    /// Whole units this amount pays for at `price`. Anything is affordable
+    /// at a price of zero.
+    pub fn units_at(self, price: Money) -> u32 {
+        if price.0 <= 0 {
+            return u32::MAX;
+        }
+        (self.0.max(0) / price.0).try_into().unwrap_or(u32::MAX)
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let abs = self.0.unsigned_abs();
+        let scale = Self::SCALE as u64;
+        write!(f, "{}${}.{:03}", sign, abs / scale, abs % scale)
+    }
+}
+
+impl ToSql for Money {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        self.0.to_sql()
+    }
+}
+
+impl FromSql for Money {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        i64::column_result(value).map(Money)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overflow_is_an_error_not_a_wrap() {
+        let one = Money::from_milli(1);
+        assert_eq!(Money::MAX.checked_add(one), None);
+        assert!(matches!(
+            Money::MAX.try_add(one),
+            Err(EngineError::MoneyOverflow)
+        ));
+        assert_eq!(Money::MIN.checked_sub(one), None);
+        assert!(matches!(
+            Money::MIN.try_sub(one),
+            Err(EngineError::MoneyOverflow)
+        ));
+        assert_eq!(Money::MAX.checked_mul(2), None);
+        assert!(matches!(
+            Money::from_milli(i64::MAX / 2 + 1).try_mul(2),
+            Err(EngineError::MoneyOverflow)
+        ));
+
+        assert_eq!(
+            Money::MAX.try_sub(one).unwrap().try_add(one).unwrap(),
+            Money::MAX
+        );
+        assert_eq!(
+            Money::from_dollars(3)
+                .try_sub(Money::from_dollars(5))
+                .unwrap(),
+            Money::from_milli(-2_000)
+        );
+        assert_eq!(
+            Money::from_milli(1_250).try_mul(4).unwrap(),
+            Money::from_dollars(5)
+        );
+        assert_eq!(Money::MAX.try_mul(0).unwrap(), Money::ZERO);
+    }
+
+    #[test]
+    fn units_at_rounds_down_and_never_goes_negative() {
+        let ten = Money::from_dollars(10);
+        assert_eq!(ten.units_at(Money::from_dollars(3)), 3);
+        assert_eq!(ten.units_at(Money::from_milli(2_500)), 4);
+        assert_eq!(Money::ZERO.units_at(Money::from_dollars(1)), 0);
+        assert_eq!(
+            Money::from_milli(-5_000).units_at(Money::from_dollars(1)),
+            0
+        );
+
+        assert_eq!(ten.units_at(Money::ZERO), u32::MAX);
+        assert_eq!(ten.units_at(Money::from_milli(-1)), u32::MAX);
+        assert_eq!(Money::MAX.units_at(Money::from_milli(1)), u32::MAX);
+    }
+
+    #[test]
+    fn decimals_round_to_the_nearest_thousandth() {
+        assert_eq!(Money::from_decimal(12.5), Some(Money::from_milli(12_500)));
+        assert_eq!(Money::from_decimal(0.0004), Some(Money::ZERO));
+        assert_eq!(Money::from_decimal(0.0006), Some(Money::from_milli(1)));
+        assert_eq!(
+            Money::from_decimal(-1.2345),
+            Some(Money::from_milli(-1_235))
+        );
+        assert_eq!(Money::from_decimal(-0.0004), Some(Money::ZERO));
+
+        assert_eq!(Money::from_decimal(f64::NAN), None);
+        assert_eq!(Money::from_decimal(f64::INFINITY), None);
+        assert_eq!(Money::from_decimal(f64::NEG_INFINITY), None);
+        assert_eq!(Money::from_decimal(i64::MAX as f64 / 1000.0), None);
+        assert_eq!(Money::from_decimal(1e17), None);
+        assert_eq!(
+            Money::from_decimal(i64::MIN as f64 / 1000.0),
+            Some(Money::MIN)
+        );
+        assert_eq!(
+            Money::from_decimal(9e15),
+            Some(Money::from_milli(9_000_000_000_000_000_000))
+        );
+    }
+
+    #[test]
+    fn display_shows_sign_dollars_and_thousandths() {
+        assert_eq!(Money::ZERO.to_string(), "$0.000");
+        assert_eq!(Money::from_dollars(12).to_string(), "$12.000");
+        assert_eq!(Money::from_milli(1_005).to_string(), "$1.005");
+        assert_eq!(Money::from_milli(7).to_string(), "$0.007");
+        assert_eq!(Money::from_milli(-7).to_string(), "-$0.007");
+        assert_eq!(Money::from_milli(-12_340).to_string(), "-$12.340");
+        assert_eq!(Money::MIN.to_string(), "-$9223372036854775.808");
+        assert_eq!(Money::MAX.to_string(), "$9223372036854775.807");
+    }
+}
@@ -0,0 +1,142 @@
+use super::Player;
+use crate::{
+    db::atomic,
+    error::{EngineError, EngineResult},
+};
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier, password_hash::SaltString};
+use rand_core::OsRng;
+use rusqlite::{Connection, OptionalExtension, params};
+
+// A hash of nobody's password, made with the default parameters. Logins
+// with no real hash to check verify against it instead, so an unknown
+// username takes as long to turn down as a wrong password.
+const DUMMY_HASH: &str = "$argon2id$v=19$m=19456,t=2,p=1$30hLX5P2X3CDzqISmBm9Ng$4u5OOOl3yZ4MaesueuQF1m9D+BBSzBDZ/yS9/RXHwmE";
+
+/// Account registration and login.
+///
+/// Passwords are hashed with Argon2id and a random salt per user. The
+/// `password_hash` column holds the PHC string, which carries the salt and
+/// parameters, so they can be raised later without breaking old hashes.
+impl Player {
+    /// Creates a player with a password. Fails with
+    /// [`EngineError::UsernameTaken`] if the name is in use.
+    pub fn register(conn: &Connection, username: &str, password: &str) -> EngineResult<Self> {
+        let hash = hash_password(password)?;
+        atomic(conn, || {
+            let player = Player::create(conn, username)?;
+            set_password_hash(conn, player.id, &hash)?;
+            Ok(player)
+        })
+    }
+
+    /// Loads the player if the password matches. An unknown username and a
+    /// wrong password both fail with [`EngineError::InvalidCredentials`].
+    pub fn login(conn: &Connection, username: &str, password: &str) -> EngineResult<Self> {
+        let Some(player) = Player::load_by_username(conn, username)? else {
+            verify_dummy(password);
+            return Err(EngineError::InvalidCredentials);
+        };
+        if !player.check_password(conn, password)? {
+            return Err(EngineError::InvalidCredentials);
+        }
+        Ok(player)
+    }
+
+    pub fn check_password(&self, conn: &Connection, password: &str) -> EngineResult<bool> {
+        let stored: Option<String> = conn
+            .query_row(
+                "SELECT password_hash FROM user WHERE id = ?1",
+                params![self.id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let Some(stored) = stored else {
+            return Err(EngineError::PlayerNotFound(self.id));
+        };
+        // Players created without a password have an empty hash and can't
+        // log in until one is set.
+        if stored.is_empty() {
+            verify_dummy(password);
+            return Ok(false);
+        }
+        let parsed = PasswordHash::new(&stored).map_err(|e| {
+            EngineError::CorruptData(format!("Player {} password hash: {}", self.id, e))
+        })?;
+        Ok(Argon2::default()
+            .verify_password(password.as_bytes(), &parsed)
+            .is_ok())
+    }
+
+    /// Replaces the password after checking the current one, and ends every
+    /// session the player has open.
+    pub fn change_password(
+        &self,
+        conn: &Connection,
+        current: &str,
+        new_password: &str,
+    ) -> EngineResult<()> {
+        if !self.check_password(conn, current)? {
+            return Err(EngineError::InvalidCredentials);
+        }
+        let hash = hash_password(new_password)?;
+        atomic(conn, || {
+            set_password_hash(conn, self.id, &hash)?;
+            self.end_all_sessions(conn)
+        })
+    }
+}
+
+fn hash_password(password: &str) -> EngineResult<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| EngineError::PasswordHashing(e.to_string()))?;
+    Ok(hash.to_string())
+}
+
+fn verify_dummy(password: &str) {
+    let parsed = PasswordHash::new(DUMMY_HASH).expect("dummy hash is a valid PHC string");
+    let _ = Argon2::default().verify_password(password.as_bytes(), &parsed);
+}
+
+fn set_password_hash(conn: &Connection, player: u32, hash: &str) -> EngineResult<()> {
+    let updated = conn.execute(
+        "UPDATE user SET password_hash = ?1 WHERE id = ?2",
+        params![hash, player],
+    )?;
+    if updated == 0 {
+        return Err(EngineError::PlayerNotFound(player));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::init_memory_db;
+
+    #[test]
+    fn dummy_hash_costs_the_same_as_a_real_one() {
+        let real = hash_password("hunter2").unwrap();
+        let real = PasswordHash::new(&real).unwrap();
+        let dummy = PasswordHash::new(DUMMY_HASH).unwrap();
+        assert_eq!(dummy.algorithm, real.algorithm);
+        assert_eq!(dummy.version, real.version);
+        assert_eq!(dummy.params, real.params);
+    }
+
+    #[test]
+    fn unknown_players_and_wrong_passwords_look_the_same() {
+        let conn = init_memory_db().unwrap();
+        let ann = Player::register(&conn, "ann", "hunter2").unwrap();
+        Player::create(&conn, "bob").unwrap();
+
+        assert_eq!(Player::login(&conn, "ann", "hunter2").unwrap().id, ann.id);
+        for (username, password) in [("ann", "wrong"), ("bob", ""), ("carol", "hunter2")] {
+            assert!(matches!(
+                Player::login(&conn, username, password),
+                Err(EngineError::InvalidCredentials)
+            ));
+        }
+    }
+}
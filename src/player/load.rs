@@ -0,0 +1,65 @@
+use super::Player;
+use crate::{
+    error::{EngineError, EngineResult},
+    ledger::Journal,
+    money::Money,
+};
+use rusqlite::{Connection, OptionalExtension, Row, ToSql, params};
+
+impl Player {
+    pub fn load(conn: &Connection, id: u32) -> EngineResult<Option<Self>> {
+        Self::load_where(conn, "id = ?1", params![id])
+    }
+
+    pub fn load_by_username(conn: &Connection, username: &str) -> EngineResult<Option<Self>> {
+        Self::load_where(conn, "username = ?1", params![username])
+    }
+
+    fn load_where(
+        conn: &Connection,
+        filter: &str,
+        args: &[&dyn ToSql],
+    ) -> EngineResult<Option<Self>> {
+        let row = conn
+            .query_row(
+                &format!(
+                    "SELECT id, username, energy, usd, data FROM user WHERE {}",
+                    filter
+                ),
+                args,
+                read_row,
+            )
+            .optional()?;
+        let Some((id, name, energy, usd, data_str)) = row else {
+            return Ok(None);
+        };
+
+        let data = match data_str {
+            Some(data_str) => json::parse(&data_str).map_err(|e| {
+                EngineError::CorruptData(format!("Player {} data is not valid JSON: {}", id, e))
+            })?,
+            None => json::JsonValue::new_object(),
+        };
+
+        Ok(Some(Player {
+            id,
+            name,
+            usd,
+            energy,
+            data,
+            journal: Journal::default(),
+        }))
+    }
+}
+
+type PlayerRow = (u32, String, u8, Money, Option<String>);
+
+fn read_row(row: &Row) -> rusqlite::Result<PlayerRow> {
+    Ok((
+        row.get(0)?,
+        row.get(1)?,
+        row.get(2)?,
+        row.get(3)?,
+        row.get(4)?,
+    ))
+}
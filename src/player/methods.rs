@@ -1,26 +1,44 @@
 use super::Player;
+use crate::{
+    error::{EngineError, EngineResult},
+    ledger::{Account, Asset, Holder, Reason},
+    money::Money,
+};
 use json::{JsonValue, object};
 
 impl Player {
-    pub fn earn(&mut self, money: u32) {
-        self.usd += money;
+    pub fn earn(&mut self, money: Money) -> EngineResult<()> {
+        self.usd = self.usd.try_add(money)?;
+        self.journal.post(
+            Account::cash(Holder::Player(self.id)),
+            Account::world(Asset::Cash),
+            money.milli(),
+            Reason::Grant,
+        );
+        Ok(())
     }
-    pub fn spend(&mut self, amount: u32) {
+    pub fn spend(&mut self, amount: Money) -> EngineResult<()> {
+        self.spend_on(amount, Reason::Spend)
+    }
+    /// Spends `amount` out of the economy, recorded under `reason`.
+    pub fn spend_on(&mut self, amount: Money, reason: Reason) -> EngineResult<()> {
         if amount > self.usd {
-            eprintln!(
-                "Warning: Tried to spend {} but only have {}",
-                amount, self.usd
-            );
-            self.usd = 0;
-        } else {
-            self.usd -= amount;
+            return Err(EngineError::InsufficientFunds {
+                needed: amount,
+                available: self.usd,
+            });
         }
+        self.usd = self.usd.try_sub(amount)?;
+        self.journal.post(
+            Account::world(Asset::Cash),
+            Account::cash(Holder::Player(self.id)),
+            amount.milli(),
+            reason,
+        );
+        Ok(())
     }
-    pub fn edit_shares(&mut self, company_id_option: Option<u32>, amount: i16) {
-        let company_id = match company_id_option {
-            Some(id) => id,
-            None => panic!("Company ID is None — invalid input"),
-        };
+    pub fn edit_shares(&mut self, company_id_option: Option<u32>, amount: i16) -> EngineResult<()> {
+        let company_id = company_id_option.ok_or(EngineError::UnsavedCompany)?;
 
         // Ensure "owns" exists and is an object
         if self.data["owns"].is_null() {
@@ -48,23 +66,24 @@ impl Player {
             let current_amount = self.data["owns"]["shares"][idx]["amount"]
                 .as_i16()
                 .unwrap_or(0);
-            let new_amount = current_amount + amount;
-
-            if new_amount < 0 {
-                panic!(
-                    "Invalid operation: share amount would go negative ({} + {} = {})",
-                    current_amount, amount, new_amount
-                );
-            }
+            let new_amount = current_amount
+                .checked_add(amount)
+                .filter(|new_amount| *new_amount >= 0)
+                .ok_or(EngineError::InvalidShares {
+                    company: company_id,
+                    held: current_amount,
+                    change: amount,
+                })?;
 
             self.data["owns"]["shares"][idx]["amount"] = new_amount.into();
         } else {
             // Add new share entry
             if amount < 0 {
-                panic!(
-                    "Invalid operation: cannot create a share record with negative amount: {}",
-                    amount
-                );
+                return Err(EngineError::InvalidShares {
+                    company: company_id,
+                    held: 0,
+                    change: amount,
+                });
             }
 
             self.data["owns"]["shares"]
@@ -72,7 +91,8 @@ impl Player {
                     company_id: company_id,
                     amount: amount
                 })
-                .unwrap();
+                .map_err(|e| EngineError::CorruptData(format!("Failed to add shares: {}", e)))?;
         }
+        Ok(())
     }
 }
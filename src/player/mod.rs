@@ -1,3 +1,3 @@
 use crate::flatten_modules;
 
-flatten_modules!(methods, r#struct);
+flatten_modules!(methods, r#struct, save, load, auth, session);
@@ -0,0 +1,191 @@
+use super::Player;
+use crate::{
+    db::atomic,
+    error::{EngineError, EngineResult},
+    ledger::Holder,
+};
+use rusqlite::{Connection, ffi, params};
+
+impl Player {
+    /// Inserts a new player row and returns the saved player. Fails with
+    /// [`EngineError::UsernameTaken`] if the name is in use.
+    pub fn create(conn: &Connection, username: &str) -> EngineResult<Self> {
+        let mut player = Player::new(username.to_string());
+        player.insert(conn)?;
+        Ok(player)
+    }
+
+    /// Writes the player's row along with any ledger entries it has posted
+    /// since the last save, in one savepoint. A player that was never saved
+    /// is inserted.
+    pub fn save(&mut self, conn: &Connection) -> EngineResult<u32> {
+        if self.id == 0 {
+            return self.insert(conn);
+        }
+        atomic(conn, || {
+            let updated = conn
+                .execute(
+                    "UPDATE user SET username = ?1, energy = ?2, usd = ?3, data = ?4 WHERE id = ?5",
+                    params![self.name, self.energy, self.usd, self.data.dump(), self.id],
+                )
+                .map_err(|e| username_error(e, &self.name))?;
+            if updated == 0 {
+                return Err(EngineError::PlayerNotFound(self.id));
+            }
+            self.journal.commit(conn)
+        })?;
+        Ok(self.id)
+    }
+
+    // The id and the rebound journal are only kept once the savepoint has
+    // been released, so a failed insert leaves the player unsaved.
+    fn insert(&mut self, conn: &Connection) -> EngineResult<u32> {
+        let mut journal = self.journal.clone();
+        let new_id = atomic(conn, || -> EngineResult<u32> {
+            conn.execute(
+                "INSERT INTO user (username, password_hash, energy, usd, data)
+                 VALUES (?1, '', ?2, ?3, ?4)",
+                params![self.name, self.energy, self.usd, self.data.dump()],
+            )
+            .map_err(|e| username_error(e, &self.name))?;
+            let new_id = conn.last_insert_rowid() as u32;
+            journal.rebind(Holder::Player(0), Holder::Player(new_id));
+            journal.commit(conn)?;
+            Ok(new_id)
+        })?;
+        self.id = new_id;
+        self.journal = journal;
+        Ok(new_id)
+    }
+}
+
+// Only a clash on `user.username` means the name is taken. Any other
+// constraint failure is a real persistence error.
+fn username_error(e: rusqlite::Error, username: &str) -> EngineError {
+    match &e {
+        rusqlite::Error::SqliteFailure(err, Some(message))
+            if err.extended_code == ffi::SQLITE_CONSTRAINT_UNIQUE
+                && message.contains("user.username") =>
+        {
+            EngineError::UsernameTaken(username.to_string())
+        }
+        _ => EngineError::Persistence(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db::init_memory_db, ledger::check_invariants, money::Money};
+
+    #[test]
+    fn a_saved_player_loads_back_the_same() {
+        let conn = init_memory_db().unwrap();
+        let mut ann = Player::new("ann".to_string());
+        ann.earn(Money::from_milli(12_345)).unwrap();
+        ann.energy = 17;
+        ann.data["shares"] = 3.into();
+        let id = ann.save(&conn).unwrap();
+        assert_eq!(ann.id, id);
+        assert!(ann.journal.entries().is_empty());
+
+        ann.name = "annie".to_string();
+        ann.spend(Money::from_dollars(2)).unwrap();
+        assert_eq!(ann.save(&conn).unwrap(), id);
+
+        let loaded = Player::load(&conn, id).unwrap().unwrap();
+        assert_eq!(loaded.name, "annie");
+        assert_eq!(loaded.usd, Money::from_milli(10_345));
+        assert_eq!(loaded.energy, 17);
+        assert_eq!(loaded.data, ann.data);
+        assert_eq!(
+            Player::load_by_username(&conn, "annie")
+                .unwrap()
+                .unwrap()
+                .id,
+            id
+        );
+        assert!(Player::load_by_username(&conn, "ann").unwrap().is_none());
+        assert_eq!(check_invariants(&conn).unwrap(), vec![]);
+
+        let mut gone = loaded.clone();
+        gone.id = 999;
+        assert!(matches!(
+            gone.save(&conn),
+            Err(EngineError::PlayerNotFound(999))
+        ));
+    }
+
+    #[test]
+    fn usernames_are_unique() {
+        let conn = init_memory_db().unwrap();
+        Player::create(&conn, "ann").unwrap();
+        assert!(matches!(
+            Player::create(&conn, "ann"),
+            Err(EngineError::UsernameTaken(name)) if name == "ann"
+        ));
+
+        let mut bob = Player::create(&conn, "bob").unwrap();
+        bob.name = "ann".to_string();
+        assert!(matches!(
+            bob.save(&conn),
+            Err(EngineError::UsernameTaken(name)) if name == "ann"
+        ));
+        assert_eq!(Player::load(&conn, bob.id).unwrap().unwrap().name, "bob");
+
+        // Other constraint failures are not mistaken for a taken name.
+        conn.execute_batch(
+            "CREATE TEMP TRIGGER no_carol BEFORE INSERT ON user
+             WHEN NEW.username = 'carol'
+             BEGIN SELECT RAISE(ABORT, 'no carols'); END;",
+        )
+        .unwrap();
+        assert!(matches!(
+            Player::create(&conn, "carol"),
+            Err(EngineError::Persistence(_))
+        ));
+    }
+
+    #[test]
+    fn a_failed_save_writes_nothing() {
+        let conn = init_memory_db().unwrap();
+        conn.execute_batch(
+            "CREATE TEMP TRIGGER ledger_full BEFORE INSERT ON ledger
+             BEGIN SELECT RAISE(ABORT, 'disk full'); END;",
+        )
+        .unwrap();
+
+        // The row goes with the entries, and the player stays unsaved.
+        let mut ann = Player::new("ann".to_string());
+        ann.earn(Money::from_dollars(5)).unwrap();
+        assert!(matches!(ann.save(&conn), Err(EngineError::Persistence(_))));
+        assert_eq!(ann.id, 0);
+        assert!(Player::load_by_username(&conn, "ann").unwrap().is_none());
+
+        conn.execute_batch("DROP TRIGGER ledger_full").unwrap();
+        let id = ann.save(&conn).unwrap();
+        assert_eq!(check_invariants(&conn).unwrap(), vec![]);
+
+        // An update is rolled back with its entries too.
+        conn.execute_batch(
+            "CREATE TEMP TRIGGER ledger_full BEFORE INSERT ON ledger
+             BEGIN SELECT RAISE(ABORT, 'disk full'); END;",
+        )
+        .unwrap();
+        ann.earn(Money::from_dollars(1)).unwrap();
+        assert!(matches!(ann.save(&conn), Err(EngineError::Persistence(_))));
+        assert_eq!(
+            Player::load(&conn, id).unwrap().unwrap().usd,
+            Money::from_dollars(5)
+        );
+        assert_eq!(ann.journal.entries().len(), 1);
+
+        conn.execute_batch("DROP TRIGGER ledger_full").unwrap();
+        ann.save(&conn).unwrap();
+        assert_eq!(
+            Player::load(&conn, id).unwrap().unwrap().usd,
+            Money::from_dollars(6)
+        );
+        assert_eq!(check_invariants(&conn).unwrap(), vec![]);
+    }
+}
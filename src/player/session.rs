@@ -0,0 +1,213 @@
+use super::Player;
+use crate::error::EngineResult;
+use blake2::{Blake2s256, Digest};
+use chrono::{DateTime, Duration, Utc};
+use rand_core::{OsRng, RngCore};
+use rusqlite::{Connection, OptionalExtension, params};
+use std::fmt::Write;
+
+/// A login session. `token` is what the frontend keeps; the database only
+/// stores its hash, so a leaked database can't be used to log in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Session {
+    pub token: String,
+    pub player: u32,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl Player {
+    /// Opens a session for the player that lasts `ttl`.
+    pub fn start_session(&self, conn: &Connection, ttl: Duration) -> EngineResult<Session> {
+        let mut bytes = [0u8; 32];
+        OsRng.fill_bytes(&mut bytes);
+        let token = to_hex(&bytes);
+        let now = Utc::now();
+        let expires_at = now + ttl;
+        conn.execute(
+            "INSERT INTO session (token_hash, user_id, created_at, expires_at)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![
+                token_hash(&token),
+                self.id,
+                now.timestamp(),
+                expires_at.timestamp()
+            ],
+        )?;
+        Ok(Session {
+            token,
+            player: self.id,
+            expires_at,
+        })
+    }
+
+    /// Loads the player a session token belongs to. Unknown and expired
+    /// tokens give `None`; expired ones are deleted on the way.
+    pub fn from_session(conn: &Connection, token: &str) -> EngineResult<Option<Self>> {
+        let hash = token_hash(token);
+        let row: Option<(u32, i64)> = conn
+            .query_row(
+                "SELECT user_id, expires_at FROM session WHERE token_hash = ?1",
+                params![hash],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        let Some((player, expires_at)) = row else {
+            return Ok(None);
+        };
+        if expires_at <= Utc::now().timestamp() {
+            conn.execute("DELETE FROM session WHERE token_hash = ?1", params![hash])?;
+            return Ok(None);
+        }
+        Player::load(conn, player)
+    }
+
+    /// Logs a single session out. Returns `false` if the token wasn't open.
+    pub fn end_session(conn: &Connection, token: &str) -> EngineResult<bool> {
+        let deleted = conn.execute(
+            "DELETE FROM session WHERE token_hash = ?1",
+            params![token_hash(token)],
+        )?;
+        Ok(deleted > 0)
+    }
+
+    pub fn end_all_sessions(&self, conn: &Connection) -> EngineResult<()> {
+        conn.execute("DELETE FROM session WHERE user_id = ?1", params![self.id])?;
+        Ok(())
+    }
+}
+
+/// Drops every session that has run out. Returns how many were removed.
+pub fn purge_expired_sessions(conn: &Connection) -> EngineResult<usize> {
+    let deleted = conn.execute(
+        "DELETE FROM session WHERE expires_at <= ?1",
+        params![Utc::now().timestamp()],
+    )?;
+    Ok(deleted)
+}
+
+fn token_hash(token: &str) -> String {
+    to_hex(&Blake2s256::digest(token.as_bytes()))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{:02x}", byte);
+        hex
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::init_memory_db;
+
+    fn open_sessions(conn: &Connection) -> u32 {
+        conn.query_row("SELECT COUNT(*) FROM session", [], |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn tokens_log_in_until_they_expire() {
+        let conn = init_memory_db().unwrap();
+        let ann = Player::create(&conn, "ann").unwrap();
+        let live = ann.start_session(&conn, Duration::hours(1)).unwrap();
+        let stale = ann.start_session(&conn, Duration::seconds(-1)).unwrap();
+        assert_eq!(live.player, ann.id);
+        assert_ne!(live.token, stale.token);
+
+        // Only the hash is stored.
+        let stored: String = conn
+            .query_row("SELECT token_hash FROM session LIMIT 1", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_ne!(stored, live.token);
+
+        assert_eq!(
+            Player::from_session(&conn, &live.token)
+                .unwrap()
+                .unwrap()
+                .id,
+            ann.id
+        );
+        assert!(Player::from_session(&conn, "nope").unwrap().is_none());
+        assert_eq!(open_sessions(&conn), 2);
+        assert!(Player::from_session(&conn, &stale.token).unwrap().is_none());
+        assert_eq!(open_sessions(&conn), 1);
+    }
+
+    #[test]
+    fn sessions_end_one_at_a_time_or_all_together() {
+        let conn = init_memory_db().unwrap();
+        let ann = Player::create(&conn, "ann").unwrap();
+        let bob = Player::create(&conn, "bob").unwrap();
+        let first = ann.start_session(&conn, Duration::hours(1)).unwrap();
+        let second = ann.start_session(&conn, Duration::hours(1)).unwrap();
+        let other = bob.start_session(&conn, Duration::hours(1)).unwrap();
+
+        assert!(Player::end_session(&conn, &first.token).unwrap());
+        assert!(!Player::end_session(&conn, &first.token).unwrap());
+        assert!(Player::from_session(&conn, &first.token).unwrap().is_none());
+        assert!(
+            Player::from_session(&conn, &second.token)
+                .unwrap()
+                .is_some()
+        );
+
+        ann.start_session(&conn, Duration::hours(1)).unwrap();
+        ann.end_all_sessions(&conn).unwrap();
+        assert!(
+            Player::from_session(&conn, &second.token)
+                .unwrap()
+                .is_none()
+        );
+        assert!(Player::from_session(&conn, &other.token).unwrap().is_some());
+        assert_eq!(open_sessions(&conn), 1);
+    }
+
+    #[test]
+    fn purging_drops_only_expired_sessions() {
+        let conn = init_memory_db().unwrap();
+        let ann = Player::create(&conn, "ann").unwrap();
+        let live = ann.start_session(&conn, Duration::hours(1)).unwrap();
+        for _ in 0..2 {
+            ann.start_session(&conn, Duration::seconds(-1)).unwrap();
+        }
+
+        assert_eq!(purge_expired_sessions(&conn).unwrap(), 2);
+        assert_eq!(purge_expired_sessions(&conn).unwrap(), 0);
+        assert_eq!(open_sessions(&conn), 1);
+        assert!(Player::from_session(&conn, &live.token).unwrap().is_some());
+    }
+
+    #[test]
+    fn changing_the_password_ends_every_session() {
+        let conn = init_memory_db().unwrap();
+        let ann = Player::register(&conn, "ann", "hunter2").unwrap();
+        let bob = Player::register(&conn, "bob", "swordfish").unwrap();
+        let sessions: Vec<_> = (0..2)
+            .map(|_| ann.start_session(&conn, Duration::hours(1)).unwrap())
+            .collect();
+        let other = bob.start_session(&conn, Duration::hours(1)).unwrap();
+
+        // A wrong current password changes nothing.
+        assert!(ann.change_password(&conn, "wrong", "hunter3").is_err());
+        assert!(
+            Player::from_session(&conn, &sessions[0].token)
+                .unwrap()
+                .is_some()
+        );
+
+        ann.change_password(&conn, "hunter2", "hunter3").unwrap();
+        for session in sessions.iter() {
+            assert!(
+                Player::from_session(&conn, &session.token)
+                    .unwrap()
+                    .is_none()
+            );
+        }
+        assert!(Player::from_session(&conn, &other.token).unwrap().is_some());
+        assert!(Player::login(&conn, "ann", "hunter2").is_err());
+        assert_eq!(Player::login(&conn, "ann", "hunter3").unwrap().id, ann.id);
+    }
+}
@@ -1,10 +1,14 @@
+use crate::{ledger::Journal, money::Money};
+
 #[derive(Debug, Clone)]
 pub struct Player {
     pub id: u32,
     pub name: String,
-    pub usd: u32,
+    pub usd: Money,
     pub energy: u8,
     pub data: JsonValue,
+    /// Ledger entries waiting to be written.
+    pub journal: Journal,
 }
 
 impl Player {
@@ -12,18 +16,22 @@ impl Player {
         Player {
             id: 0,
             name: "0".to_string(),
-            usd: 0,
+            usd: Money::ZERO,
             data: JsonValue::new_object(),
             energy: 50,
+            journal: Journal::default(),
         }
     }
+    /// A player that hasn't been saved yet. [`Player::create`] inserts one
+    /// and gives it an id.
     pub fn new(username: String) -> Self {
         Player {
-            id: 1,
+            id: 0,
             name: username,
-            usd: 0,
+            usd: Money::ZERO,
             data: JsonValue::new_object(),
             energy: 50,
+            journal: Journal::default(),
         }
     }
 }
@@ -1,92 +1,117 @@
-use crate::materials::{Inventory, Material, Recipe};
+use crate::error::{EngineError, EngineResult};
+use crate::ledger::{Journal, Reason};
+use crate::materials::{Inventory, Recipe};
+use crate::money::Money;
 use crate::player::Player;
-use json::JsonValue;
+use crate::production::{HumanWorker, Machine, catalog};
 use rusqlite::Connection;
+use std::collections::BTreeMap;
 use std::fmt;
-#[derive(Debug, Clone)]
+/// A production type from the catalog (see [`catalog`]).
+#[derive(Debug, Clone, PartialEq)]
 pub struct Prod {
-    pub type_name: &'static str,
-    pub human_prod_rate: u32,
-    pub creates: Material,
-    pub max_human_workers: u32,
-    pub cost: u32,
+    /// Stable key the type is looked up and stored by.
+    pub id: String,
+    pub type_name: String,
+    /// Position in the supply chain. A type only consumes goods made by
+    /// types of a lower tier, and only of the categories its [`Tier`]
+    /// accepts.
+    ///
+    /// [`Tier`]: crate::production::Tier
+    pub tier: u32,
+    /// One batch, which is what one worker's shift turns out.
     pub recipe: Recipe<'static>,
+    pub cost: Money,
+    /// Paid every cycle to keep the building running.
+    pub upkeep: Money,
+    pub max_human_workers: u32,
+    /// Energy a worker spends on one shift.
+    pub shift_energy: u8,
 }
 
 impl Prod {
-    pub const fn new(
-        type_name: &'static str,
-        human_prod_rate: u32,
-        creates: Material,
-        recipe: Recipe<'static>,
-        cost: u32,
-    ) -> Self {
-        Self {
-            type_name,
-            human_prod_rate,
-            creates,
-            max_human_workers: 10,
-            cost,
-            recipe,
-        }
+    /// Finds a production type in the catalog by id.
+    pub fn lookup(id: &str) -> EngineResult<&'static Prod> {
+        catalog()
+            .get(id)
+            .ok_or_else(|| EngineError::UnknownProd(id.to_string()))
     }
 }
 
 impl fmt::Display for Prod {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         writeln!(f, "Production Facility: {}", self.type_name)?;
-        writeln!(f, "  Produces: {}", self.creates)?;
-        writeln!(f, "  Human Production Rate: {}", self.human_prod_rate)?;
+        writeln!(f, "  Tier: {}", self.tier)?;
         writeln!(f, "  Max Human Workers: {}", self.max_human_workers)?;
-        writeln!(f, "  Cost: ${}", self.cost)?;
-        writeln!(f, "  Recipe:\n{}", self.recipe)
+        writeln!(f, "  Energy Per Shift: {}", self.shift_energy)?;
+        writeln!(f, "  Cost: {}", self.cost)?;
+        writeln!(f, "  Upkeep: {}", self.upkeep)?;
+        write!(f, "  {}", self.recipe)
     }
 }
 
 /// Instance of a company
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ProdInstance {
     pub id: Option<u32>,
+    /// How many times the company has been saved. A copy loaded before
+    /// someone else saved the company can't be saved over their changes.
+    pub version: u32,
     pub name: String,
     pub owner: u32,
-    pub usd: f32,
+    pub usd: Money,
+    pub reserved_usd: Money,
     pub base_type: String,
-    pub creates: Material,
     pub recipe: Recipe<'static>,
-    pub human_prod_rate: u32,
     pub max_human_workers: u32,
-    pub human_workers: JsonValue,
+    pub shift_energy: u8,
+    pub upkeep: Money,
+    pub human_workers: Vec<HumanWorker>,
     pub owns: Inventory,
+    pub reserved: Inventory,
+    /// Installed machines, oldest first.
+    pub machines: Vec<Machine>,
+    /// Wages earned by players that the company couldn't pay yet.
+    pub wages_owed: BTreeMap<u32, Money>,
+    /// Ledger entries waiting for the next save.
+    pub journal: Journal,
 }
 
 impl ProdInstance {
+    /// Buys and saves a new company for `owner`, who must already be saved.
+    /// The owner's cash and shares only change in memory, so save the owner
+    /// afterwards.
     pub fn new(
         conn: &Connection,
         base: &Prod,
         name: String,
         owner: &mut Player,
-    ) -> Result<Option<Self>, String> {
-        if owner.usd < base.cost {
-            return Ok(None);
+    ) -> EngineResult<Self> {
+        if owner.id == 0 {
+            return Err(EngineError::PlayerNotFound(owner.id));
         }
-        owner.spend(base.cost);
+        owner.spend_on(base.cost, Reason::Purchase)?;
         let mut instance = ProdInstance {
             id: None,
+            version: 0,
             name,
             owner: owner.id,
-            usd: 0.0,
-            base_type: base.type_name.to_owned(),
-            creates: base.creates,
-            human_prod_rate: base.human_prod_rate,
-            human_workers: JsonValue::new_array(),
+            usd: Money::ZERO,
+            reserved_usd: Money::ZERO,
+            base_type: base.id.clone(),
+            human_workers: Vec::new(),
             owns: Inventory::new(),
+            reserved: Inventory::new(),
+            machines: Vec::new(),
+            wages_owed: BTreeMap::new(),
             recipe: base.recipe.clone(),
             max_human_workers: base.max_human_workers,
+            shift_energy: base.shift_energy,
+            upkeep: base.upkeep,
+            journal: Journal::default(),
         };
-        instance
-            .save(conn)
-            .map_err(|e| format!("Failed to save instance: {}", e))?;
-        owner.edit_shares(instance.id, 10000);
-        Ok(Some(instance))
+        instance.save(conn)?;
+        owner.edit_shares(instance.id, 10000)?;
+        Ok(instance)
     }
 }
@@ -0,0 +1,283 @@
+use crate::{
+    error::EngineResult,
+    materials::{Inventory, Material},
+    money::Money,
+    player::Player,
+    production::ProdInstance,
+};
+
+/// What stopped a production run from going further.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatchLimit {
+    /// The caller asked for no more batches.
+    Requested,
+    /// Not enough of this input for another batch.
+    Inputs(Material),
+    /// Another batch would hold more of this output than fits in the
+    /// inventory.
+    Storage(Material),
+    /// Every hired worker who showed up has already done their shift.
+    Workers,
+    /// The workers still free don't have the energy for a shift.
+    Energy,
+}
+
+/// How many batches a company can run right now, and who would work them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchPlan {
+    pub batches: u32,
+    /// Players who would work a shift, in hiring order.
+    pub workers: Vec<u32>,
+    pub limit: BatchLimit,
+}
+
+/// Outcome of a [`ProdInstance::run_batches`] call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchReport {
+    pub batches: u32,
+    /// Players who worked a shift.
+    pub workers: Vec<u32>,
+    pub consumed: Inventory,
+    pub produced: Inventory,
+    pub limit: BatchLimit,
+    /// Wages paid to the workers, back pay included.
+    pub wages_paid: Money,
+    /// Wages the company couldn't cover and still owes them.
+    pub wages_owed: Money,
+}
+
+impl ProdInstance {
+    /// Works out how many batches the company could run in `cycle` with the
+    /// goods it owns and `players` on shift, up to `wanted` if given. Each
+    /// batch is one worker's shift, so a batch needs a hired player who
+    /// hasn't worked this cycle and has the energy for it. Players who
+    /// aren't hired here, or whose employment has ended, are ignored.
+    pub fn plan_batches(&self, players: &[Player], wanted: Option<u32>, cycle: u32) -> BatchPlan {
+        let mut free = 0;
+        let mut ready = Vec::new();
+        for worker in self
+            .human_workers
+            .iter()
+            .filter(|worker| !worker.worked && worker.employed_in(cycle))
+        {
+            if let Some(player) = players.iter().find(|player| player.id == worker.player) {
+                free += 1;
+                if player.energy >= self.shift_energy {
+                    ready.push(player.id);
+                }
+            }
+        }
+
+        let by_inputs = self
+            .batches_by_inputs(|_| 0)
+            .map(|(batches, mat)| (batches, BatchLimit::Inputs(mat)));
+        let by_storage = self
+            .batches_by_storage()
+            .map(|(batches, mat)| (batches, BatchLimit::Storage(mat)));
+        let by_workers = if ready.len() < free {
+            (ready.len() as u32, BatchLimit::Energy)
+        } else {
+            (ready.len() as u32, BatchLimit::Workers)
+        };
+
+        // On a tie the limit listed first is the one reported.
+        let (batches, limit) = wanted
+            .map(|wanted| (wanted, BatchLimit::Requested))
+            .into_iter()
+            .chain(by_inputs)
+            .chain(by_storage)
+            .chain(std::iter::once(by_workers))
+            .min_by_key(|&(n, _)| n)
+            .unwrap_or(by_workers);
+
+        ready.truncate(batches as usize);
+        BatchPlan {
+            batches,
+            workers: ready,
+            limit,
+        }
+    }
+
+    /// Runs as many batches as [`ProdInstance::plan_batches`] allows in one
+    /// step: inputs for all of them are taken, all outputs are added, and
+    /// each worker used spends a shift's energy, is marked as having worked
+    /// and is paid as [`ProdInstance::human_worked`] would pay them.
+    pub fn run_batches(
+        &mut self,
+        players: &mut [Player],
+        wanted: Option<u32>,
+        cycle: u32,
+    ) -> EngineResult<BatchReport> {
+        self.check_recipe()?;
+        let plan = self.plan_batches(players, wanted, cycle);
+        let wages = plan
+            .workers
+            .iter()
+            .map(|&id| self.shift_wage(id))
+            .collect::<EngineResult<Vec<_>>>()?;
+        let (consumed, produced) = self.run_recipe(plan.batches)?;
+
+        let mut wages_paid = Money::ZERO;
+        let mut wages_owed = Money::ZERO;
+        for (&id, wage) in plan.workers.iter().zip(wages) {
+            if let Some(worker) = self.human_workers.iter_mut().find(|w| w.player == id) {
+                worker.worked = true;
+            }
+            if let Some(player) = players.iter_mut().find(|player| player.id == id) {
+                player.energy -= self.shift_energy;
+                let payslip = self.pay_wages(player, wage)?;
+                wages_paid = wages_paid.try_add(payslip.paid)?;
+                wages_owed = wages_owed.try_add(payslip.owed)?;
+            }
+        }
+
+        Ok(BatchReport {
+            batches: plan.batches,
+            workers: plan.workers,
+            consumed,
+            produced,
+            limit: plan.limit,
+            wages_paid,
+            wages_owed,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::init_memory_db,
+        production::{Contract, Prod, Wage},
+    };
+
+    fn m(key: &str) -> Material {
+        Material::lookup(key).unwrap()
+    }
+
+    // A food plant with three workers hired, holding the inputs for
+    // `batches` batches.
+    fn plant(batches: u32) -> (ProdInstance, Vec<Player>) {
+        let conn = init_memory_db().unwrap();
+        let mut owner = Player::create(&conn, "owner").unwrap();
+        owner.earn(Money::from_dollars(10_000)).unwrap();
+        let base = Prod::lookup("food_processing_plant").unwrap();
+        let mut plant = ProdInstance::new(&conn, base, "Plant".to_string(), &mut owner).unwrap();
+        for (item, amount) in [("Electricity", 10), ("Water", 5), ("Grain", 5)] {
+            plant.add_material(m(item), amount * batches).unwrap();
+        }
+
+        let contract = Contract {
+            wage: Wage::PerShift(Money::ZERO),
+            duration: None,
+            notice: 0,
+        };
+        let workers: Vec<Player> = (0..3)
+            .map(|i| Player::create(&conn, &format!("worker{}", i)).unwrap())
+            .collect();
+        for worker in workers.iter() {
+            plant.hire_worker(worker, contract, 0).unwrap();
+        }
+        (plant, workers)
+    }
+
+    fn ids(players: &[Player]) -> Vec<u32> {
+        players.iter().map(|player| player.id).collect()
+    }
+
+    #[test]
+    fn the_first_limit_listed_wins_a_tie() {
+        let (mut plant, workers) = plant(2);
+        plant.add_material(m("Food"), u32::MAX - 10).unwrap();
+        // Two batches by every input, by storage and as asked for.
+        let plan = plant.plan_batches(&workers, Some(2), 0);
+        assert_eq!((plan.batches, plan.limit), (2, BatchLimit::Requested));
+        let plan = plant.plan_batches(&workers, None, 0);
+        assert_eq!(
+            (plan.batches, plan.limit),
+            (2, BatchLimit::Inputs(m("Electricity")))
+        );
+        assert_eq!(plan.workers, ids(&workers[..2]));
+
+        plant.add_material(m("Electricity"), 10).unwrap();
+        let plan = plant.plan_batches(&workers, None, 0);
+        assert_eq!(
+            (plan.batches, plan.limit),
+            (2, BatchLimit::Inputs(m("Water")))
+        );
+    }
+
+    #[test]
+    fn storage_and_requests_cap_the_run() {
+        let (mut full, workers) = plant(3);
+        full.add_material(m("Food"), u32::MAX - 7).unwrap();
+        let plan = full.plan_batches(&workers, None, 0);
+        assert_eq!(
+            (plan.batches, plan.limit),
+            (1, BatchLimit::Storage(m("Food")))
+        );
+
+        let (plant, workers) = plant(3);
+        let plan = plant.plan_batches(&workers, Some(1), 0);
+        assert_eq!((plan.batches, plan.limit), (1, BatchLimit::Requested));
+        assert_eq!(plan.workers, ids(&workers[..1]));
+        let plan = plant.plan_batches(&workers, Some(0), 0);
+        assert_eq!((plan.batches, plan.limit), (0, BatchLimit::Requested));
+        assert!(plan.workers.is_empty());
+    }
+
+    #[test]
+    fn tired_workers_are_told_apart_from_missing_ones() {
+        let (plant, mut workers) = plant(5);
+        let plan = plant.plan_batches(&workers, None, 0);
+        assert_eq!((plan.batches, plan.limit), (3, BatchLimit::Workers));
+
+        // Someone who turned up too tired to work limits the run on energy.
+        workers[1].energy = plant.shift_energy - 1;
+        let plan = plant.plan_batches(&workers, None, 0);
+        assert_eq!((plan.batches, plan.limit), (2, BatchLimit::Energy));
+        assert_eq!(plan.workers, vec![workers[0].id, workers[2].id]);
+
+        // Someone who didn't turn up at all doesn't.
+        let plan = plant.plan_batches(&[workers[0].clone(), workers[2].clone()], None, 0);
+        assert_eq!((plan.batches, plan.limit), (2, BatchLimit::Workers));
+
+        // Players who aren't hired here don't count.
+        let mut stranger = Player::new("stranger".to_string());
+        stranger.id = 999;
+        let plan = plant.plan_batches(&[stranger], None, 0);
+        assert_eq!((plan.batches, plan.limit), (0, BatchLimit::Workers));
+    }
+
+    #[test]
+    fn a_run_reports_what_it_used_and_made() {
+        let (mut plant, mut workers) = plant(5);
+        plant.add_material(m("Water"), 3).unwrap();
+        workers[1].energy = plant.shift_energy - 1;
+        let energy = workers[0].energy;
+
+        let report = plant.run_batches(&mut workers, None, 0).unwrap();
+        assert_eq!((report.batches, report.limit), (2, BatchLimit::Energy));
+        assert_eq!(report.workers, vec![workers[0].id, workers[2].id]);
+        let mut consumed = Inventory::new();
+        for (item, amount) in [("Electricity", 20), ("Water", 10), ("Grain", 10)] {
+            consumed.add(m(item), amount).unwrap();
+        }
+        let mut produced = Inventory::new();
+        produced.add(m("Food"), 10).unwrap();
+        assert_eq!(report.consumed, consumed);
+        assert_eq!(report.produced, produced);
+        assert_eq!(plant.owns.amount_of(m("Water")), 18);
+        assert_eq!(plant.owns.amount_of(m("Food")), 10);
+        assert_eq!(workers[0].energy, energy - plant.shift_energy);
+        assert_eq!(workers[1].energy, plant.shift_energy - 1);
+        let worked: Vec<bool> = plant.human_workers.iter().map(|w| w.worked).collect();
+        assert_eq!(worked, vec![true, false, true]);
+
+        // Everyone able to has worked, so the next run does nothing.
+        let report = plant.run_batches(&mut workers, None, 0).unwrap();
+        assert_eq!((report.batches, report.limit), (0, BatchLimit::Energy));
+        assert!(report.consumed.is_empty() && report.produced.is_empty());
+        assert_eq!(plant.owns.amount_of(m("Food")), 10);
+    }
+}
@@ -0,0 +1,649 @@
+use crate::{
+    error::{EngineError, EngineResult},
+    materials::{Material, Recipe},
+    money::Money,
+    production::{MachineDef, Prod, Tier},
+};
+use json::JsonValue;
+use std::{collections::HashMap, path::Path, sync::OnceLock};
+
+/// The catalog shipped with the engine, used unless another one is
+/// installed before the first lookup.
+const DEFAULT_PRODS: &str = include_str!("../../data/prods.json");
+
+static CATALOG: OnceLock<ProdCatalog> = OnceLock::new();
+
+/// Every production type players can build, in file order.
+#[derive(Debug)]
+pub struct ProdCatalog {
+    tiers: Vec<Tier>,
+    prods: Vec<Prod>,
+    by_id: HashMap<String, usize>,
+    machines: Vec<MachineDef>,
+}
+
+impl ProdCatalog {
+    /// Parses a production file:
+    ///
+    /// ```json
+    /// { "tiers": [ { "tier": 2, "name": "Processing", "consumes": ["raw", "utility"] } ],
+    ///   "prods": [ { "id": "food_processing_plant", "name": "Food Processing Plant",
+    ///   "tier": 2, "outputs": [ { "material": "Food", "amount": 5 } ],
+    ///   "inputs": [ { "material": "Grain", "amount": 5 } ],
+    ///   "cost": 500, "upkeep": 10, "max_workers": 10, "shift_energy": 4 } ] }
+    /// ```
+    ///
+    /// `outputs` needs at least one good; the first is the type's main
+    /// product and the rest are byproducts. `cost` and `upkeep` are in
+    /// dollars. Materials must exist in the material registry, and every type
+    /// must sit on a higher tier than the types making its inputs, which also
+    /// rules out cycles.
+    ///
+    /// Every tier a type sits on has to be listed under `tiers`, and a type
+    /// may only consume materials whose category its tier accepts. The same
+    /// goes for the running goods of any machine that fits it.
+    ///
+    /// An optional `machines` list names the capital goods that can be
+    /// installed and what they do each cycle:
+    ///
+    /// ```json
+    /// { "material": "Tractor", "fits": ["grain_farm"], "batches": 2,
+    ///   "running": [ { "material": "Electricity", "amount": 20 } ],
+    ///   "maintenance": 2, "lifetime": 100 }
+    /// ```
+    pub fn from_json(source: &str) -> EngineResult<Self> {
+        let root = json::parse(source)
+            .map_err(|e| invalid(format!("production file is not valid JSON: {}", e)))?;
+        if !root["prods"].is_array() {
+            return Err(invalid("production file has no \"prods\" list".to_string()));
+        }
+
+        if !root["tiers"].is_array() {
+            return Err(invalid("production file has no \"tiers\" list".to_string()));
+        }
+
+        let mut catalog = ProdCatalog {
+            tiers: Vec::new(),
+            prods: Vec::new(),
+            by_id: HashMap::new(),
+            machines: Vec::new(),
+        };
+        for entry in root["tiers"].members() {
+            let tier = parse_tier(entry)?;
+            if catalog.tier(tier.level).is_some() {
+                return Err(invalid(format!("tier {} is defined twice", tier.level)));
+            }
+            catalog.tiers.push(tier);
+        }
+        catalog.tiers.sort_by_key(|tier| tier.level);
+
+        for entry in root["prods"].members() {
+            let prod = parse_prod(entry)?;
+            if catalog.by_id.contains_key(&prod.id) {
+                return Err(invalid(format!(
+                    "production type {} is defined twice",
+                    prod.id
+                )));
+            }
+            catalog.by_id.insert(prod.id.clone(), catalog.prods.len());
+            catalog.prods.push(prod);
+        }
+        catalog.check_tiers()?;
+
+        if !root["machines"].is_null() && !root["machines"].is_array() {
+            return Err(invalid(
+                "production file's \"machines\" is not a list".to_string(),
+            ));
+        }
+        for entry in root["machines"].members() {
+            let machine = parse_machine(entry)?;
+            if catalog.machine(machine.item).is_some() {
+                return Err(invalid(format!(
+                    "machine {:?} is defined twice",
+                    machine.item
+                )));
+            }
+            for id in machine.fits.iter() {
+                let Some(prod) = catalog.get(id) else {
+                    return Err(invalid(format!(
+                        "machine {:?} fits unknown production type {}",
+                        machine.item, id
+                    )));
+                };
+                if let Some((good, _)) = machine
+                    .running
+                    .iter()
+                    .find(|(good, _)| !catalog.may_consume(prod, *good))
+                {
+                    return Err(invalid(format!(
+                        "machine {:?} runs on {:?}, which {} (tier {}) may not consume",
+                        machine.item, good, prod.id, prod.tier
+                    )));
+                }
+            }
+            catalog.machines.push(machine);
+        }
+        Ok(catalog)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> EngineResult<Self> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path)
+            .map_err(|e| invalid(format!("can't read {}: {}", path.display(), e)))?;
+        Self::from_json(&source)
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Prod> {
+        self.by_id.get(id).map(|&index| &self.prods[index])
+    }
+
+    pub fn all(&self) -> &[Prod] {
+        &self.prods
+    }
+
+    /// Capital goods that can be installed as machines.
+    pub fn machines(&self) -> &[MachineDef] {
+        &self.machines
+    }
+
+    pub fn machine(&self, item: Material) -> Option<&MachineDef> {
+        self.machines.iter().find(|machine| machine.item == item)
+    }
+
+    /// The supply chain's tiers, lowest first.
+    pub fn tiers(&self) -> &[Tier] {
+        &self.tiers
+    }
+
+    pub fn tier(&self, level: u32) -> Option<&Tier> {
+        self.tiers.iter().find(|tier| tier.level == level)
+    }
+
+    /// Whether a building of type `prod` may use `item` as an input.
+    pub fn may_consume(&self, prod: &Prod, item: Material) -> bool {
+        self.tier(prod.tier)
+            .is_some_and(|tier| tier.may_consume(item))
+    }
+
+    /// Types that make `item`.
+    pub fn producers(&self, item: Material) -> impl Iterator<Item = &Prod> {
+        self.prods
+            .iter()
+            .filter(move |prod| prod.recipe.produces(item))
+    }
+
+    /// Rejects types on undeclared tiers or consuming goods their tier
+    /// doesn't accept, then dependency cycles, naming the types involved,
+    /// then any type that doesn't sit above its suppliers.
+    fn check_tiers(&self) -> EngineResult<()> {
+        for prod in self.prods.iter() {
+            let Some(tier) = self.tier(prod.tier) else {
+                return Err(invalid(format!(
+                    "{} is on tier {}, which isn't defined",
+                    prod.id, prod.tier
+                )));
+            };
+            for (input, _) in prod.recipe.inputs.iter() {
+                if !tier.may_consume(*input) {
+                    return Err(invalid(format!(
+                        "{} ({}) may not consume {:?}: tier {} doesn't accept {} goods",
+                        prod.id,
+                        tier.name,
+                        input,
+                        tier.level,
+                        input.category()
+                    )));
+                }
+            }
+        }
+
+        #[derive(Clone, Copy, PartialEq)]
+        enum Mark {
+            Unvisited,
+            InProgress,
+            Done,
+        }
+
+        fn visit(
+            catalog: &ProdCatalog,
+            index: usize,
+            marks: &mut [Mark],
+            path: &mut Vec<usize>,
+        ) -> EngineResult<()> {
+            match marks[index] {
+                Mark::Done => return Ok(()),
+                Mark::InProgress => {
+                    let start = path.iter().position(|&i| i == index).unwrap_or(0);
+                    let cycle: Vec<&str> = path[start..]
+                        .iter()
+                        .chain(std::iter::once(&index))
+                        .map(|&i| catalog.prods[i].id.as_str())
+                        .collect();
+                    return Err(invalid(format!(
+                        "production types depend on each other in a cycle: {}",
+                        cycle.join(" -> ")
+                    )));
+                }
+                Mark::Unvisited => {}
+            }
+            marks[index] = Mark::InProgress;
+            path.push(index);
+            for (input, _) in catalog.prods[index].recipe.inputs.iter() {
+                for (supplier, prod) in catalog.prods.iter().enumerate() {
+                    if prod.recipe.produces(*input) {
+                        visit(catalog, supplier, marks, path)?;
+                    }
+                }
+            }
+            path.pop();
+            marks[index] = Mark::Done;
+            Ok(())
+        }
+
+        let mut marks = vec![Mark::Unvisited; self.prods.len()];
+        for index in 0..self.prods.len() {
+            visit(self, index, &mut marks, &mut Vec::new())?;
+        }
+
+        for prod in self.prods.iter() {
+            for (input, _) in prod.recipe.inputs.iter() {
+                if let Some(supplier) = self.producers(*input).find(|s| s.tier >= prod.tier) {
+                    return Err(invalid(format!(
+                        "{} (tier {}) consumes {:?} from {} (tier {}), which is not a lower tier",
+                        prod.id, prod.tier, input, supplier.id, supplier.tier
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Makes `catalog` the one [`Prod::lookup`] searches. Has to happen before
+/// the first lookup; fails if a catalog is already in use.
+pub fn install_catalog(catalog: ProdCatalog) -> EngineResult<()> {
+    CATALOG
+        .set(catalog)
+        .map_err(|_| invalid("a production catalog is already in use".to_string()))
+}
+
+/// The catalog in use, loading the default one on first use.
+pub fn catalog() -> &'static ProdCatalog {
+    CATALOG.get_or_init(|| {
+        ProdCatalog::from_json(DEFAULT_PRODS).expect("default production file is invalid")
+    })
+}
+
+fn parse_tier(entry: &JsonValue) -> EngineResult<Tier> {
+    let level = entry["tier"]
+        .as_u32()
+        .ok_or_else(|| invalid("tier without a number".to_string()))?;
+    let field = |name: &str| invalid(format!("tier {} has an invalid {}", level, name));
+
+    let name = entry["name"]
+        .as_str()
+        .filter(|name| !name.is_empty())
+        .ok_or_else(|| field("name"))?
+        .to_string();
+    if !entry["consumes"].is_array() {
+        return Err(field("consumes"));
+    }
+    let mut consumes = Vec::new();
+    for category in entry["consumes"].members() {
+        let category = category.as_str().ok_or_else(|| field("consumes"))?;
+        if !Material::all()
+            .iter()
+            .any(|item| item.category() == category)
+        {
+            return Err(invalid(format!(
+                "tier {} consumes {} goods, but no material is in that category",
+                level, category
+            )));
+        }
+        consumes.push(category.to_string());
+    }
+    Ok(Tier {
+        level,
+        name,
+        consumes,
+    })
+}
+
+fn parse_machine(entry: &JsonValue) -> EngineResult<MachineDef> {
+    let key = entry["material"]
+        .as_str()
+        .ok_or_else(|| invalid("machine without a material".to_string()))?;
+    let item = Material::from_str(key)
+        .ok_or_else(|| invalid(format!("machine {} is not a known material", key)))?;
+    let field = |name: &str| invalid(format!("machine {} has an invalid {}", key, name));
+
+    if !entry["fits"].is_array() || entry["fits"].is_empty() {
+        return Err(field("fits"));
+    }
+    let mut fits = Vec::new();
+    for id in entry["fits"].members() {
+        fits.push(id.as_str().ok_or_else(|| field("fits"))?.to_string());
+    }
+    if !entry["running"].is_null() && !entry["running"].is_array() {
+        return Err(field("running"));
+    }
+
+    Ok(MachineDef {
+        item,
+        fits,
+        batches: entry["batches"]
+            .as_u32()
+            .filter(|batches| *batches > 0)
+            .ok_or_else(|| field("batches"))?,
+        running: goods_list(&format!("machine {}", key), &entry["running"])?,
+        maintenance: entry["maintenance"]
+            .as_f64()
+            .and_then(Money::from_decimal)
+            .filter(|amount| !amount.is_negative())
+            .ok_or_else(|| field("maintenance"))?,
+        lifetime: entry["lifetime"]
+            .as_u32()
+            .filter(|lifetime| *lifetime > 0)
+            .ok_or_else(|| field("lifetime"))?,
+    })
+}
+
+fn parse_prod(entry: &JsonValue) -> EngineResult<Prod> {
+    let id = entry["id"]
+        .as_str()
+        .filter(|id| !id.is_empty())
+        .ok_or_else(|| invalid("production type without an id".to_string()))?
+        .to_string();
+    let field = |name: &str| invalid(format!("production type {} has an invalid {}", id, name));
+
+    let type_name = entry["name"]
+        .as_str()
+        .filter(|name| !name.is_empty())
+        .ok_or_else(|| field("name"))?
+        .to_string();
+    let tier = entry["tier"].as_u32().ok_or_else(|| field("tier"))?;
+    if !entry["outputs"].is_array() {
+        return Err(field("outputs"));
+    }
+    let owner = format!("production type {}", id);
+    let outputs = goods_list(&owner, &entry["outputs"])?;
+    if outputs.is_empty() {
+        return Err(invalid(format!("production type {} makes nothing", id)));
+    }
+    if !entry["inputs"].is_null() && !entry["inputs"].is_array() {
+        return Err(field("inputs"));
+    }
+    let inputs = goods_list(&owner, &entry["inputs"])?;
+
+    let money = |name: &str| {
+        entry[name]
+            .as_f64()
+            .and_then(Money::from_decimal)
+            .filter(|amount| !amount.is_negative())
+            .ok_or_else(|| field(name))
+    };
+
+    Ok(Prod {
+        tier,
+        recipe: Recipe::dynamic(inputs, outputs),
+        cost: money("cost")?,
+        upkeep: money("upkeep")?,
+        max_human_workers: entry["max_workers"]
+            .as_u32()
+            .ok_or_else(|| field("max_workers"))?,
+        shift_energy: entry["shift_energy"]
+            .as_u8()
+            .ok_or_else(|| field("shift_energy"))?,
+        type_name,
+        id,
+    })
+}
+
+// Reads a list of goods, each naming a material at most once.
+fn goods_list(owner: &str, list: &JsonValue) -> EngineResult<Vec<(Material, u32)>> {
+    let mut goods: Vec<(Material, u32)> = Vec::new();
+    for value in list.members() {
+        let (item, amount) = material_amount(owner, value)?;
+        if goods.iter().any(|(seen, _)| *seen == item) {
+            return Err(invalid(format!("{} lists {:?} twice", owner, item)));
+        }
+        goods.push((item, amount));
+    }
+    Ok(goods)
+}
+
+// Reads `{ "material": "Grain", "amount": 5 }`.
+fn material_amount(owner: &str, value: &JsonValue) -> EngineResult<(Material, u32)> {
+    let key = value["material"]
+        .as_str()
+        .ok_or_else(|| invalid(format!("{} has a good without a material", owner)))?;
+    let item = Material::from_str(key)
+        .ok_or_else(|| invalid(format!("{} uses unknown material {}", owner, key)))?;
+    let amount = value["amount"]
+        .as_u32()
+        .filter(|amount| *amount > 0)
+        .ok_or_else(|| invalid(format!("{} has an invalid amount of {}", owner, key)))?;
+    Ok((item, amount))
+}
+
+fn invalid(reason: String) -> EngineError {
+    EngineError::InvalidDefinition(reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIERS: &str = r#"[
+        { "tier": 1, "name": "Extraction", "consumes": [] },
+        { "tier": 2, "name": "Processing", "consumes": ["raw", "utility", "processed"] }
+    ]"#;
+
+    fn goods(list: &[(&str, u32)]) -> String {
+        let goods: Vec<String> = list
+            .iter()
+            .map(|(item, amount)| format!(r#"{{ "material": "{}", "amount": {} }}"#, item, amount))
+            .collect();
+        format!("[{}]", goods.join(", "))
+    }
+
+    fn prod(id: &str, tier: u32, inputs: &[(&str, u32)], outputs: &[(&str, u32)]) -> String {
+        format!(
+            r#"{{ "id": "{}", "name": "{}", "tier": {}, "inputs": {}, "outputs": {},
+                "cost": 100, "upkeep": 1, "max_workers": 5, "shift_energy": 2 }}"#,
+            id,
+            id,
+            tier,
+            goods(inputs),
+            goods(outputs)
+        )
+    }
+
+    fn file(tiers: &str, prods: &[String], machines: &str) -> String {
+        format!(
+            r#"{{ "tiers": {}, "prods": [{}], "machines": {} }}"#,
+            tiers,
+            prods.join(", "),
+            machines
+        )
+    }
+
+    fn farm_and_mill() -> Vec<String> {
+        vec![
+            prod("farm", 1, &[], &[("Grain", 10)]),
+            prod("mill", 2, &[("Grain", 5)], &[("Food", 5)]),
+        ]
+    }
+
+    fn rejects(source: &str, reason: &str) {
+        match ProdCatalog::from_json(source) {
+            Err(EngineError::InvalidDefinition(message)) => {
+                assert!(message.contains(reason), "{:?} lacks {:?}", message, reason)
+            }
+            other => panic!("expected {:?}, got {:?}", reason, other),
+        }
+    }
+
+    #[test]
+    fn a_valid_file_loads() {
+        let tractor = r#"[{ "material": "Tractor", "fits": ["farm"], "batches": 2,
+            "maintenance": 1.5, "lifetime": 10 }]"#;
+        let catalog = ProdCatalog::from_json(&file(TIERS, &farm_and_mill(), tractor)).unwrap();
+
+        assert_eq!(catalog.all().len(), 2);
+        let mill = catalog.get("mill").unwrap();
+        assert_eq!(mill.cost, Money::from_dollars(100));
+        let grain = Material::lookup("Grain").unwrap();
+        assert!(catalog.may_consume(mill, grain));
+        assert!(!catalog.may_consume(catalog.get("farm").unwrap(), grain));
+        assert_eq!(
+            catalog
+                .producers(grain)
+                .map(|p| p.id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["farm"]
+        );
+        let tractor = catalog
+            .machine(Material::lookup("Tractor").unwrap())
+            .unwrap();
+        assert_eq!(tractor.maintenance, Money::from_milli(1_500));
+        assert!(tractor.running.is_empty());
+    }
+
+    #[test]
+    fn the_shipped_file_loads() {
+        let catalog = ProdCatalog::from_json(DEFAULT_PRODS).unwrap();
+        for id in ["grain_farm", "food_processing_plant", "restaurant"] {
+            assert!(catalog.get(id).is_some(), "{} is missing", id);
+        }
+        assert!(!catalog.machines().is_empty());
+    }
+
+    #[test]
+    fn unknown_materials_are_rejected() {
+        let mut prods = farm_and_mill();
+        prods.push(prod("mine", 1, &[], &[("Gold", 1)]));
+        rejects(
+            &file(TIERS, &prods, "[]"),
+            "production type mine uses unknown material Gold",
+        );
+
+        let machine = r#"[{ "material": "Robot", "fits": ["farm"], "batches": 1,
+            "maintenance": 1, "lifetime": 10 }]"#;
+        rejects(
+            &file(TIERS, &farm_and_mill(), machine),
+            "machine Robot is not a known material",
+        );
+    }
+
+    #[test]
+    fn types_that_feed_each_other_are_rejected() {
+        let prods = vec![
+            prod("mill", 2, &[("Grain", 5)], &[("Food", 5)]),
+            prod("silo", 2, &[("Food", 5)], &[("Grain", 5)]),
+        ];
+        rejects(
+            &file(TIERS, &prods, "[]"),
+            "depend on each other in a cycle: mill -> silo -> mill",
+        );
+    }
+
+    #[test]
+    fn suppliers_must_sit_on_a_lower_tier() {
+        let prods = vec![
+            prod("farm", 2, &[], &[("Grain", 10)]),
+            prod("mill", 2, &[("Grain", 5)], &[("Food", 5)]),
+        ];
+        rejects(
+            &file(TIERS, &prods, "[]"),
+            "mill (tier 2) consumes Grain from farm (tier 2), which is not a lower tier",
+        );
+    }
+
+    #[test]
+    fn duplicate_ids_are_rejected() {
+        let mut prods = farm_and_mill();
+        prods.push(prod("farm", 1, &[], &[("Water", 1)]));
+        rejects(
+            &file(TIERS, &prods, "[]"),
+            "production type farm is defined twice",
+        );
+
+        let tiers = r#"[{ "tier": 1, "name": "A", "consumes": [] },
+                        { "tier": 1, "name": "B", "consumes": [] }]"#;
+        rejects(&file(tiers, &[], "[]"), "tier 1 is defined twice");
+
+        let tractor = r#"{ "material": "Tractor", "fits": ["farm"], "batches": 1,
+            "maintenance": 1, "lifetime": 10 }"#;
+        rejects(
+            &file(
+                TIERS,
+                &farm_and_mill(),
+                &format!("[{}, {}]", tractor, tractor),
+            ),
+            "machine Tractor is defined twice",
+        );
+
+        rejects(
+            &file(
+                TIERS,
+                &[prod("farm", 1, &[], &[("Grain", 1), ("Grain", 2)])],
+                "[]",
+            ),
+            "production type farm lists Grain twice",
+        );
+    }
+
+    #[test]
+    fn types_need_a_declared_tier_that_accepts_their_inputs() {
+        let mut prods = farm_and_mill();
+        prods.push(prod("diner", 3, &[("Food", 1)], &[("Meal", 1)]));
+        rejects(
+            &file(TIERS, &prods, "[]"),
+            "diner is on tier 3, which isn't defined",
+        );
+
+        let prods = vec![
+            prod("farm", 1, &[], &[("Grain", 10)]),
+            prod("mill", 2, &[("Grain", 5), ("Straw", 1)], &[("Food", 5)]),
+        ];
+        rejects(
+            &file(TIERS, &prods, "[]"),
+            "mill (Processing) may not consume Straw: tier 2 doesn't accept byproduct goods",
+        );
+    }
+
+    #[test]
+    fn machines_must_fit_known_types() {
+        let machine = r#"[{ "material": "Tractor", "fits": ["farm", "orchard"], "batches": 1,
+            "maintenance": 1, "lifetime": 10 }]"#;
+        rejects(
+            &file(TIERS, &farm_and_mill(), machine),
+            "machine Tractor fits unknown production type orchard",
+        );
+
+        let machine = r#"[{ "material": "Tractor", "fits": [], "batches": 1,
+            "maintenance": 1, "lifetime": 10 }]"#;
+        rejects(
+            &file(TIERS, &farm_and_mill(), machine),
+            "machine Tractor has an invalid fits",
+        );
+    }
+
+    #[test]
+    fn machines_may_only_run_on_goods_every_type_they_fit_may_consume() {
+        let machine = |fits: &str| {
+            format!(
+                r#"[{{ "material": "Tractor", "fits": [{}], "batches": 1,
+                    "running": [{{ "material": "Electricity", "amount": 5 }}],
+                    "maintenance": 1, "lifetime": 10 }}]"#,
+                fits
+            )
+        };
+        ProdCatalog::from_json(&file(TIERS, &farm_and_mill(), &machine(r#""mill""#))).unwrap();
+        rejects(
+            &file(TIERS, &farm_and_mill(), &machine(r#""mill", "farm""#)),
+            "machine Tractor runs on Electricity, which farm (tier 1) may not consume",
+        );
+    }
+}
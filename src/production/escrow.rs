@@ -0,0 +1,217 @@
+use crate::{
+    error::{EngineError, EngineResult},
+    ledger::{Account, Reason},
+    materials::Material,
+    money::Money,
+    production::ProdInstance,
+};
+
+/// Escrow for resting exchange orders.
+///
+/// `usd` and `owns` always hold what the company can freely spend. Placing an
+/// order moves the locked part into `reserved_usd` / `reserved`, fills consume
+/// from there, and cancellation hands the remainder back. Taking more out of
+/// escrow than is in it fails and changes nothing.
+impl ProdInstance {
+    pub fn available_usd(&self) -> Money {
+        self.usd
+    }
+
+    pub fn available(&self, item: Material) -> u32 {
+        self.owns.amount_of(item)
+    }
+
+    pub fn reserved_usd(&self) -> Money {
+        self.reserved_usd
+    }
+
+    pub fn reserved(&self, item: Material) -> u32 {
+        self.reserved.amount_of(item)
+    }
+
+    pub fn reserve_usd(&mut self, amount: Money) -> EngineResult<()> {
+        if amount > self.usd {
+            return Err(EngineError::InsufficientFunds {
+                needed: amount,
+                available: self.usd,
+            });
+        }
+        self.usd = self.usd.try_sub(amount)?;
+        self.reserved_usd = self.reserved_usd.try_add(amount)?;
+        self.journal.post(
+            Account::escrow_cash(self.holder()),
+            Account::cash(self.holder()),
+            amount.milli(),
+            Reason::Reserve,
+        );
+        Ok(())
+    }
+
+    /// Returns reserved cash to the available balance.
+    pub fn release_usd(&mut self, amount: Money) -> EngineResult<()> {
+        self.check_reserved_usd(amount)?;
+        self.reserved_usd = self.reserved_usd.try_sub(amount)?;
+        self.usd = self.usd.try_add(amount)?;
+        self.journal.post(
+            Account::cash(self.holder()),
+            Account::escrow_cash(self.holder()),
+            amount.milli(),
+            Reason::Release,
+        );
+        Ok(())
+    }
+
+    /// Pays reserved cash out to a counterparty's available balance.
+    pub fn pay_reserved_usd(&mut self, to: &mut ProdInstance, amount: Money) -> EngineResult<()> {
+        self.check_reserved_usd(amount)?;
+        self.reserved_usd = self.reserved_usd.try_sub(amount)?;
+        to.usd = to.usd.try_add(amount)?;
+        self.journal.post(
+            Account::cash(to.holder()),
+            Account::escrow_cash(self.holder()),
+            amount.milli(),
+            Reason::Trade,
+        );
+        Ok(())
+    }
+
+    pub fn reserve_material(&mut self, item: Material, amount: u32) -> EngineResult<()> {
+        let available = self.owns.amount_of(item);
+        if amount > available {
+            return Err(EngineError::InsufficientMaterial {
+                item,
+                needed: amount,
+                available,
+            });
+        }
+        self.reserved.add(item, amount)?;
+        self.owns.remove(item, amount)?;
+        self.journal.post(
+            Account::escrow_goods(self.holder(), item),
+            Account::goods(self.holder(), item),
+            amount as i64,
+            Reason::Reserve,
+        );
+        Ok(())
+    }
+
+    /// Returns reserved goods to the available inventory.
+    pub fn release_material(&mut self, item: Material, amount: u32) -> EngineResult<()> {
+        self.check_reserved(item, amount)?;
+        self.owns.add(item, amount)?;
+        self.reserved.remove(item, amount)?;
+        self.journal.post(
+            Account::goods(self.holder(), item),
+            Account::escrow_goods(self.holder(), item),
+            amount as i64,
+            Reason::Release,
+        );
+        Ok(())
+    }
+
+    /// Delivers reserved goods into a counterparty's available inventory.
+    pub fn deliver_reserved_material(
+        &mut self,
+        to: &mut ProdInstance,
+        item: Material,
+        amount: u32,
+    ) -> EngineResult<()> {
+        self.check_reserved(item, amount)?;
+        to.owns.add(item, amount)?;
+        self.reserved.remove(item, amount)?;
+        self.journal.post(
+            Account::goods(to.holder(), item),
+            Account::escrow_goods(self.holder(), item),
+            amount as i64,
+            Reason::Trade,
+        );
+        Ok(())
+    }
+
+    fn check_reserved_usd(&self, amount: Money) -> EngineResult<()> {
+        if amount > self.reserved_usd {
+            return Err(EngineError::InsufficientFunds {
+                needed: amount,
+                available: self.reserved_usd,
+            });
+        }
+        Ok(())
+    }
+
+    fn check_reserved(&self, item: Material, amount: u32) -> EngineResult<()> {
+        let reserved = self.reserved.amount_of(item);
+        if amount > reserved {
+            return Err(EngineError::InsufficientMaterial {
+                item,
+                needed: amount,
+                available: reserved,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db::init_memory_db, player::Player, production::Prod};
+
+    fn companies() -> (ProdInstance, ProdInstance) {
+        let conn = init_memory_db().unwrap();
+        let mut owner = Player::create(&conn, "owner").unwrap();
+        owner.earn(Money::from_dollars(10_000)).unwrap();
+        let base = Prod::lookup("grain_farm").unwrap();
+        let mut open = |name: &str| {
+            let mut company = ProdInstance::new(&conn, base, name.to_string(), &mut owner).unwrap();
+            company.earn(Money::from_dollars(100)).unwrap();
+            company
+                .add_material(Material::lookup("Grain").unwrap(), 100)
+                .unwrap();
+            company
+        };
+        (open("Seller"), open("Buyer"))
+    }
+
+    #[test]
+    fn taking_more_than_is_reserved_fails() {
+        let grain = Material::lookup("Grain").unwrap();
+        let (mut seller, mut buyer) = companies();
+        seller.reserve_material(grain, 10).unwrap();
+        buyer.reserve_usd(Money::from_dollars(10)).unwrap();
+        let (seller_before, buyer_before) = (seller.clone(), buyer.clone());
+
+        assert!(matches!(
+            seller.release_material(grain, 11),
+            Err(EngineError::InsufficientMaterial {
+                needed: 11,
+                available: 10,
+                ..
+            })
+        ));
+        assert!(matches!(
+            seller.deliver_reserved_material(&mut buyer, grain, 11),
+            Err(EngineError::InsufficientMaterial { .. })
+        ));
+        let too_much = Money::from_dollars(11);
+        assert!(matches!(
+            buyer.release_usd(too_much),
+            Err(EngineError::InsufficientFunds { needed, .. }) if needed == too_much
+        ));
+        assert!(matches!(
+            buyer.pay_reserved_usd(&mut seller, too_much),
+            Err(EngineError::InsufficientFunds { .. })
+        ));
+        assert_eq!(seller, seller_before);
+        assert_eq!(buyer, buyer_before);
+
+        seller
+            .deliver_reserved_material(&mut buyer, grain, 10)
+            .unwrap();
+        buyer
+            .pay_reserved_usd(&mut seller, Money::from_dollars(10))
+            .unwrap();
+        assert_eq!(seller.reserved(grain), 0);
+        assert_eq!(buyer.available(grain), 110);
+        assert_eq!(seller.available_usd(), Money::from_dollars(110));
+    }
+}
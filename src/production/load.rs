@@ -1,73 +1,390 @@
 use crate::{
+    error::{EngineError, EngineResult},
+    ledger::Journal,
     materials::{Inventory, Material, Recipe},
-    production::ProdInstance,
+    money::Money,
+    production::{Contract, HumanWorker, Machine, ProdInstance, Wage},
 };
-use json::JsonValue;
-use rusqlite::{Connection, Result, params};
+use rusqlite::{Connection, OptionalExtension, params};
+use std::collections::BTreeMap;
+
+struct CompanyRow {
+    version: u32,
+    name: String,
+    owner: u32,
+    base_type: String,
+    usd: Money,
+    reserved_usd: Money,
+    max_human_workers: u32,
+    shift_energy: u8,
+    upkeep: Money,
+}
 
 impl ProdInstance {
-    pub fn load(conn: &Connection, id: u32) -> Result<Option<Self>> {
-        let mut stmt = conn.prepare("SELECT name, owner, type, data FROM company WHERE id = ?1")?;
-        let mut rows = stmt.query(params![id])?;
-
-        if let Some(row) = rows.next()? {
-            let name: String = row.get(0)?;
-            let owner_str: String = row.get(1)?;
-            let base_type: String = row.get(2)?;
-            let data_str: String = row.get(3)?;
-
-            let data_json = json::parse(&data_str).map_err(|e| {
-                rusqlite::Error::FromSqlConversionFailure(
-                    data_str.len(),
-                    rusqlite::types::Type::Text,
-                    Box::new(e),
-                )
-            })?;
-
-            let owner: u32 = owner_str.parse::<u32>().unwrap_or(0);
-            let usd: f32 = data_json["usd"].as_f32().unwrap_or(0.0);
-            let human_prod_rate: u32 = data_json["human_prod_rate"].as_u32().unwrap_or(0);
-            let human_workers: JsonValue = data_json["human_workers"].clone();
-            let max_human_workers: u32 = data_json["max_human_workers"].as_u32().unwrap_or(10);
-            let creates_str = data_json["creates"].as_str().unwrap_or("");
-            let creates = Material::from_str(creates_str).unwrap();
-
-            let mut inputs = Vec::new();
-            for (key, value) in data_json["consumes"]["inputs"].entries() {
-                // Assuming Material::from_str returns Option or Result
-                if let Some(mat) = Material::from_str(key) {
-                    let amt = value.as_u32().unwrap_or(0);
-                    inputs.push((mat, amt));
-                } else {
-                    // Handle unknown material key gracefully
-                    eprintln!("Unknown material key: {}", key);
+    /// Loads a saved company, or `None` if there is no row with this id.
+    ///
+    /// Everything `save` writes comes back unchanged. Rows that can't be
+    /// turned back into a company (an unknown material, a negative amount,
+    /// an owner that isn't a player id) fail with a typed error instead of
+    /// being patched up with defaults.
+    pub fn load(conn: &Connection, id: u32) -> EngineResult<Option<Self>> {
+        let row = conn
+            .query_row(
+                "SELECT name, owner, type, usd, reserved_usd, max_human_workers, shift_energy,
+                        upkeep, version
+                 FROM company WHERE id = ?1",
+                params![id],
+                |row| {
+                    Ok(CompanyRow {
+                        name: row.get(0)?,
+                        owner: row.get(1)?,
+                        base_type: row.get(2)?,
+                        usd: row.get(3)?,
+                        reserved_usd: row.get(4)?,
+                        max_human_workers: row.get(5)?,
+                        shift_energy: row.get(6)?,
+                        upkeep: row.get(7)?,
+                        version: row.get(8)?,
+                    })
+                },
+            )
+            .optional()
+            .map_err(corrupt(id))?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
+
+        let mut owns = Inventory::new();
+        let mut reserved = Inventory::new();
+        let mut stmt = conn.prepare_cached(
+            "SELECT item, owned, reserved FROM company_inventory WHERE company_id = ?1",
+        )?;
+        let rows = stmt.query_map(params![id], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, u32>(1)?,
+                row.get::<_, u32>(2)?,
+            ))
+        })?;
+        for row in rows {
+            let (item, owned, held) = row.map_err(corrupt(id))?;
+            let item = Material::lookup(&item)?;
+            owns.add(item, owned)?;
+            reserved.add(item, held)?;
+        }
+
+        let mut human_workers = Vec::new();
+        let mut stmt = conn.prepare_cached(
+            "SELECT player_id, worked, wage_kind, wage, duration, notice, hired_at, ends_at
+             FROM company_workers WHERE company_id = ?1 ORDER BY rowid",
+        )?;
+        let rows = stmt.query_map(params![id], |row| {
+            Ok((
+                row.get::<_, String>(2)?,
+                row.get::<_, Money>(3)?,
+                HumanWorker {
+                    player: row.get(0)?,
+                    worked: row.get(1)?,
+                    contract: Contract {
+                        wage: Wage::PerShift(Money::ZERO),
+                        duration: row.get(4)?,
+                        notice: row.get(5)?,
+                    },
+                    hired_at: row.get(6)?,
+                    ends_at: row.get(7)?,
+                },
+            ))
+        })?;
+        for row in rows {
+            let (wage_kind, wage, mut worker) = row.map_err(corrupt(id))?;
+            worker.contract.wage = match wage_kind.as_str() {
+                "shift" => Wage::PerShift(wage),
+                "unit" => Wage::PerUnit(wage),
+                _ => {
+                    return Err(EngineError::CorruptData(format!(
+                        "Company {} has a worker with wage kind {:?}",
+                        id, wage_kind
+                    )));
+                }
+            };
+            human_workers.push(worker);
+        }
+
+        let mut wages_owed = BTreeMap::new();
+        let mut stmt = conn.prepare_cached(
+            "SELECT player_id, owed FROM company_wages_owed WHERE company_id = ?1",
+        )?;
+        let rows = stmt.query_map(params![id], |row| {
+            Ok((row.get::<_, u32>(0)?, row.get::<_, Money>(1)?))
+        })?;
+        for row in rows {
+            let (player, owed) = row.map_err(corrupt(id))?;
+            wages_owed.insert(player, owed);
+        }
+
+        let inputs = load_goods(conn, "company_recipe_input", id)?;
+        let outputs = load_goods(conn, "company_recipe_output", id)?;
+
+        let mut machines = Vec::new();
+        let mut stmt = conn.prepare_cached(
+            "SELECT item, age FROM company_machines WHERE company_id = ?1 ORDER BY rowid",
+        )?;
+        let rows = stmt.query_map(params![id], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, u32>(1)?))
+        })?;
+        for row in rows {
+            let (item, age) = row.map_err(corrupt(id))?;
+            machines.push(Machine {
+                item: Material::lookup(&item)?,
+                age,
+            });
+        }
+
+        Ok(Some(ProdInstance {
+            id: Some(id),
+            version: row.version,
+            name: row.name,
+            owner: row.owner,
+            usd: row.usd,
+            reserved_usd: row.reserved_usd,
+            base_type: row.base_type,
+            human_workers,
+            max_human_workers: row.max_human_workers,
+            shift_energy: row.shift_energy,
+            upkeep: row.upkeep,
+            owns,
+            reserved,
+            machines,
+            wages_owed,
+            recipe: Recipe::dynamic(inputs, outputs),
+            journal: Journal::default(),
+        }))
+    }
+}
+
+// Reads one side of the company's recipe, in the order it was saved.
+fn load_goods(conn: &Connection, table: &str, id: u32) -> EngineResult<Vec<(Material, u32)>> {
+    let mut stmt = conn.prepare_cached(&format!(
+        "SELECT item, amount FROM {} WHERE company_id = ?1 ORDER BY rowid",
+        table
+    ))?;
+    let rows = stmt.query_map(params![id], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, u32>(1)?))
+    })?;
+    let mut goods = Vec::new();
+    for row in rows {
+        let (item, amount) = row.map_err(corrupt(id))?;
+        goods.push((Material::lookup(&item)?, amount));
+    }
+    Ok(goods)
+}
+
+// Values that are in the database but don't fit the field they belong to
+// are corrupt data, not a database failure.
+fn corrupt(id: u32) -> impl Fn(rusqlite::Error) -> EngineError {
+    move |e| match e {
+        rusqlite::Error::FromSqlConversionFailure(..)
+        | rusqlite::Error::IntegralValueOutOfRange(..)
+        | rusqlite::Error::InvalidColumnType(..) => {
+            EngineError::CorruptData(format!("Company {}: {}", id, e))
+        }
+        e => EngineError::Persistence(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db::init_memory_db, player::Player};
+
+    // xorshift64, so failures reproduce from the seed alone.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: u32) -> u32 {
+            (self.next() % n as u64) as u32
+        }
+
+        fn chance(&mut self) -> bool {
+            self.next() & 1 == 1
+        }
+
+        fn maybe(&mut self, n: u32) -> Option<u32> {
+            self.chance().then(|| self.below(n))
+        }
+
+        fn money(&mut self) -> Money {
+            Money::from_milli(self.below(10_000_000) as i64)
+        }
+
+        fn material(&mut self) -> Material {
+            let all = Material::all();
+            all[self.below(all.len() as u32) as usize]
+        }
+
+        fn inventory(&mut self) -> Inventory {
+            let mut inventory = Inventory::new();
+            for _ in 0..self.below(6) {
+                inventory.add(self.material(), self.below(1_000)).unwrap();
+            }
+            inventory
+        }
+
+        // Distinct materials in a random order, as a recipe lists them.
+        fn goods(&mut self) -> Vec<(Material, u32)> {
+            let mut goods: Vec<(Material, u32)> = Vec::new();
+            for _ in 0..self.below(4) {
+                let item = self.material();
+                if goods.iter().all(|(mat, _)| *mat != item) {
+                    goods.push((item, self.below(50)));
                 }
             }
+            goods
+        }
+    }
 
-            let owns = Inventory {
-                grain: data_json["owns"]["grain"].as_u32().unwrap_or(0),
-                electricity: data_json["owns"]["electricity"].as_u32().unwrap_or(0),
-                water: data_json["owns"]["water"].as_u32().unwrap_or(0),
-                food: data_json["owns"]["food"].as_u32().unwrap_or(0),
-            };
+    fn arbitrary(rng: &mut Rng, players: &[u32]) -> ProdInstance {
+        let mut human_workers: Vec<HumanWorker> = Vec::new();
+        for &player in players {
+            if rng.chance() {
+                let amount = rng.money();
+                human_workers.push(HumanWorker {
+                    player,
+                    worked: rng.chance(),
+                    contract: Contract {
+                        wage: if rng.chance() {
+                            Wage::PerShift(amount)
+                        } else {
+                            Wage::PerUnit(amount)
+                        },
+                        duration: rng.maybe(100),
+                        notice: rng.below(10),
+                    },
+                    hired_at: rng.below(1_000),
+                    ends_at: rng.maybe(2_000),
+                });
+            }
+        }
+        // Shuffle so hiring order isn't player order.
+        for i in (1..human_workers.len()).rev() {
+            human_workers.swap(i, rng.below(i as u32 + 1) as usize);
+        }
+        let mut wages_owed = BTreeMap::new();
+        for &player in players {
+            if rng.chance() {
+                wages_owed.insert(player, rng.money());
+            }
+        }
+        let machines = (0..rng.below(4))
+            .map(|_| Machine {
+                item: rng.material(),
+                age: rng.below(50),
+            })
+            .collect();
 
-            Ok(Some(ProdInstance {
-                id: Some(id),
-                name,
-                owner,
-                usd,
-                base_type,
-                creates,
-                human_prod_rate,
-                human_workers,
-                max_human_workers,
-                owns,
-                recipe: Recipe {
-                    inputs: std::borrow::Cow::Owned(inputs),
-                },
-            }))
-        } else {
-            Ok(None)
+        ProdInstance {
+            id: None,
+            version: 0,
+            name: format!("Company {}", rng.below(1_000)),
+            owner: players[rng.below(players.len() as u32) as usize],
+            usd: rng.money(),
+            reserved_usd: rng.money(),
+            base_type: ["grain_farm", "restaurant", "gone_from_the_catalog"][rng.below(3) as usize]
+                .to_string(),
+            recipe: Recipe::dynamic(rng.goods(), rng.goods()),
+            max_human_workers: rng.below(20),
+            shift_energy: rng.below(10) as u8,
+            upkeep: rng.money(),
+            human_workers,
+            owns: rng.inventory(),
+            reserved: rng.inventory(),
+            machines,
+            wages_owed,
+            journal: Journal::default(),
         }
     }
+
+    #[test]
+    fn load_returns_what_save_wrote() {
+        let conn = init_memory_db().unwrap();
+        let players: Vec<u32> = (0..5)
+            .map(|i| Player::create(&conn, &format!("player{}", i)).unwrap().id)
+            .collect();
+        let mut rng = Rng(0x5eed_cafe_f00d_beef);
+
+        for _ in 0..200 {
+            let mut company = arbitrary(&mut rng, &players);
+            let id = company.save(&conn).unwrap();
+            assert_eq!(
+                ProdInstance::load(&conn, id).unwrap(),
+                Some(company.clone())
+            );
+
+            // Saving over an existing row must round-trip too.
+            let mut changed = arbitrary(&mut rng, &players);
+            changed.id = company.id;
+            changed.version = company.version;
+            changed.save(&conn).unwrap();
+            assert_eq!(ProdInstance::load(&conn, id).unwrap(), Some(changed));
+        }
+    }
+
+    #[test]
+    fn corrupt_rows_fail_to_load() {
+        let conn = init_memory_db().unwrap();
+        let owner = Player::create(&conn, "owner").unwrap();
+        let mut company = arbitrary(&mut Rng(7), &[owner.id]);
+        company.human_workers.clear();
+        company.wages_owed.clear();
+        let id = company.save(&conn).unwrap();
+        conn.execute_batch(
+            "PRAGMA foreign_keys = OFF;
+             INSERT INTO company_workers (company_id, player_id) VALUES (1, 1);
+             INSERT INTO company_wages_owed (company_id, player_id, owed) VALUES (1, 1, 0);",
+        )
+        .unwrap();
+
+        for corruption in [
+            "UPDATE company SET owner = 'bob'",
+            "UPDATE company SET owner = -1",
+            "UPDATE company SET usd = 'lots'",
+            "UPDATE company SET max_human_workers = -3",
+            "UPDATE company SET shift_energy = 300",
+            "DELETE FROM company_inventory WHERE item = 'Grain';
+             INSERT INTO company_inventory (company_id, item, owned) VALUES (1, 'Grain', -5)",
+            "UPDATE company_workers SET wage_kind = 'hourly'",
+            "UPDATE company_workers SET worked = 'maybe'",
+            "UPDATE company_wages_owed SET owed = 1.5",
+            "INSERT INTO company_machines (company_id, item, age) VALUES (1, 'Tractor', -1)",
+        ] {
+            conn.execute_batch("SAVEPOINT corrupt;").unwrap();
+            conn.execute_batch(corruption).unwrap();
+            let result = ProdInstance::load(&conn, id);
+            assert!(
+                matches!(result, Err(EngineError::CorruptData(_))),
+                "{}: {:?}",
+                corruption,
+                result
+            );
+            conn.execute_batch("ROLLBACK TO corrupt; RELEASE corrupt;")
+                .unwrap();
+        }
+
+        conn.execute(
+            "INSERT INTO company_recipe_output (company_id, item, amount) VALUES (1, 'Gold', 1)",
+            [],
+        )
+        .unwrap();
+        assert!(matches!(
+            ProdInstance::load(&conn, id),
+            Err(EngineError::UnknownMaterial(key)) if key == "Gold"
+        ));
+    }
 }
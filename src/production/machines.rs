@@ -0,0 +1,420 @@
+use crate::{
+    error::{EngineError, EngineResult},
+    ledger::{Account, Asset, Reason},
+    materials::{Inventory, Material},
+    money::Money,
+    production::{ProdInstance, catalog},
+};
+
+/// A capital good that works on its own once installed. Machines are
+/// ordinary materials, bought and sold like any other; the catalog says
+/// which of them can be installed and what they do.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MachineDef {
+    pub item: Material,
+    /// Production type ids the machine can be installed in.
+    pub fits: Vec<String>,
+    /// Recipe batches it runs per cycle, input stock permitting.
+    pub batches: u32,
+    /// Goods it uses up per cycle while running, e.g. electricity.
+    pub running: Vec<(Material, u32)>,
+    /// Paid per cycle while running.
+    pub maintenance: Money,
+    /// Cycles until it wears out and is scrapped.
+    pub lifetime: u32,
+}
+
+impl MachineDef {
+    pub fn fits(&self, prod: &str) -> bool {
+        self.fits.iter().any(|id| id == prod)
+    }
+}
+
+/// A machine installed in a company.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Machine {
+    pub item: Material,
+    /// Cycles it has been installed for.
+    pub age: u32,
+}
+
+impl Machine {
+    pub fn def(&self) -> Option<&'static MachineDef> {
+        catalog().machine(self.item)
+    }
+
+    /// What is left of the machine's value, written down in a straight
+    /// line from the material's base price to nothing over its lifetime.
+    pub fn book_value(&self) -> Money {
+        let Some(def) = self.def() else {
+            return Money::ZERO;
+        };
+        let remaining = def.lifetime.saturating_sub(self.age) as i128;
+        let value = self.item.base_price().milli() as i128 * remaining / def.lifetime as i128;
+        Money::from_milli(value as i64)
+    }
+}
+
+/// Why a machine sat out a cycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MachineIdle {
+    /// The company couldn't pay the maintenance.
+    Funds,
+    /// Not enough of this good to run, or to run a single batch.
+    Inputs(Material),
+    /// Its output has no room left in the inventory.
+    Storage(Material),
+    /// The catalog no longer describes this machine.
+    Unknown,
+}
+
+/// Outcome of a [`ProdInstance::run_machines`] call.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct MachineReport {
+    pub batches: u32,
+    /// Recipe inputs and running goods used up.
+    pub consumed: Inventory,
+    pub produced: Inventory,
+    pub maintenance: Money,
+    pub idle: Vec<(Material, MachineIdle)>,
+    /// Machines that wore out this cycle.
+    pub scrapped: Vec<Material>,
+}
+
+impl ProdInstance {
+    /// Installs one unit of `item` from the company's goods as a machine.
+    pub fn install_machine(&mut self, item: Material) -> EngineResult<()> {
+        if !catalog()
+            .machine(item)
+            .is_some_and(|def| def.fits(&self.base_type))
+        {
+            return Err(EngineError::UnsuitableMachine {
+                item,
+                prod: self.base_type.clone(),
+            });
+        }
+        let available = self.owns.amount_of(item);
+        if available == 0 {
+            return Err(EngineError::InsufficientMaterial {
+                item,
+                needed: 1,
+                available,
+            });
+        }
+        self.owns.remove(item, 1)?;
+        self.journal.post(
+            Account::installed(self.holder(), item),
+            Account::goods(self.holder(), item),
+            1,
+            Reason::Install,
+        );
+        self.machines.push(Machine { item, age: 0 });
+        Ok(())
+    }
+
+    /// Installed machines counted by material.
+    pub fn installed(&self) -> EngineResult<Inventory> {
+        let mut installed = Inventory::new();
+        for machine in self.machines.iter() {
+            installed.add(machine.item, 1)?;
+        }
+        Ok(installed)
+    }
+
+    /// Book value of every installed machine.
+    pub fn machine_value(&self) -> EngineResult<Money> {
+        self.machines
+            .iter()
+            .try_fold(Money::ZERO, |total, machine| {
+                total.try_add(machine.book_value())
+            })
+    }
+
+    /// Runs every machine for one cycle, in installation order. A machine
+    /// that can pay its maintenance and running goods runs as many batches
+    /// of the recipe as it can, up to its rate; otherwise it sits idle and
+    /// costs nothing. Every machine ages a cycle either way, and those at
+    /// the end of their life are scrapped.
+    pub fn run_machines(&mut self) -> EngineResult<MachineReport> {
+        self.check_recipe()?;
+        self.check_running_goods()?;
+        let mut report = MachineReport::default();
+        for index in 0..self.machines.len() {
+            let item = self.machines[index].item;
+            if let Some(idle) = self.run_machine(item, &mut report)? {
+                report.idle.push((item, idle));
+            }
+            self.machines[index].age += 1;
+        }
+
+        let holder = self.holder();
+        let journal = &mut self.journal;
+        self.machines.retain(|machine| {
+            let worn_out = machine.def().is_some_and(|def| machine.age >= def.lifetime);
+            if worn_out {
+                journal.post(
+                    Account::world(Asset::Goods(machine.item)),
+                    Account::installed(holder, machine.item),
+                    1,
+                    Reason::Scrap,
+                );
+                report.scrapped.push(machine.item);
+            }
+            !worn_out
+        });
+        Ok(report)
+    }
+
+    /// Fails if the company's tier may not use a good one of its machines
+    /// runs on. Like [`ProdInstance::check_recipe`], this guards against a
+    /// catalog that changed since the machine was installed.
+    fn check_running_goods(&self) -> EngineResult<()> {
+        let catalog = catalog();
+        let prod = catalog
+            .get(&self.base_type)
+            .ok_or_else(|| EngineError::UnknownProd(self.base_type.clone()))?;
+        for def in self.machines.iter().filter_map(|machine| machine.def()) {
+            for &(item, _) in def.running.iter() {
+                if !catalog.may_consume(prod, item) {
+                    return Err(EngineError::ForbiddenInput {
+                        item,
+                        prod: prod.id.clone(),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn run_machine(
+        &mut self,
+        item: Material,
+        report: &mut MachineReport,
+    ) -> EngineResult<Option<MachineIdle>> {
+        let Some(def) = catalog().machine(item) else {
+            return Ok(Some(MachineIdle::Unknown));
+        };
+        if def.maintenance > self.usd {
+            return Ok(Some(MachineIdle::Funds));
+        }
+        let running = |mat: Material| {
+            def.running
+                .iter()
+                .find(|(good, _)| *good == mat)
+                .map_or(0, |(_, amount)| *amount)
+        };
+        for &(mat, amount) in def.running.iter() {
+            if self.owns.amount_of(mat) < amount {
+                return Ok(Some(MachineIdle::Inputs(mat)));
+            }
+        }
+
+        let mut batches = def.batches;
+        let mut limit = None;
+        if let Some((by_inputs, mat)) = self.batches_by_inputs(running)
+            && by_inputs < batches
+        {
+            batches = by_inputs;
+            limit = Some(MachineIdle::Inputs(mat));
+        }
+        if let Some((by_storage, mat)) = self.batches_by_storage()
+            && by_storage < batches
+        {
+            batches = by_storage;
+            limit = Some(MachineIdle::Storage(mat));
+        }
+        if batches == 0 {
+            return Ok(limit);
+        }
+
+        self.spend_on(def.maintenance, Reason::Upkeep)?;
+        report.maintenance = report.maintenance.try_add(def.maintenance)?;
+        let holder = self.holder();
+        for &(mat, amount) in def.running.iter() {
+            self.owns.remove(mat, amount)?;
+            report.consumed.add(mat, amount)?;
+            self.journal.post(
+                Account::world(Asset::Goods(mat)),
+                Account::goods(holder, mat),
+                amount as i64,
+                Reason::Consume,
+            );
+        }
+        let (consumed, produced) = self.run_recipe(batches)?;
+        for (mat, amount) in consumed.iter() {
+            report.consumed.add(mat, amount)?;
+        }
+        for (mat, amount) in produced.iter() {
+            report.produced.add(mat, amount)?;
+        }
+        report.batches += batches;
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cycle::next_cycle, db::init_memory_db, extange::Exchange, ledger::check_invariants,
+        player::Player, production::Prod,
+    };
+    use rusqlite::Connection;
+
+    fn m(key: &str) -> Material {
+        Material::lookup(key).unwrap()
+    }
+
+    // A grain farm with $100 and `tractors` tractors in stock.
+    fn farm(conn: &Connection, tractors: u32) -> ProdInstance {
+        let mut owner = Player::create(conn, "farmer").unwrap();
+        owner.earn(Money::from_dollars(10_000)).unwrap();
+        let base = Prod::lookup("grain_farm").unwrap();
+        let mut farm = ProdInstance::new(conn, base, "Farm".to_string(), &mut owner).unwrap();
+        owner.save(conn).unwrap();
+        farm.earn(Money::from_dollars(100)).unwrap();
+        farm.add_material(m("Tractor"), tractors).unwrap();
+        farm
+    }
+
+    #[test]
+    fn only_held_machines_that_fit_can_be_installed() {
+        let conn = init_memory_db().unwrap();
+        let mut farm = farm(&conn, 1);
+
+        assert!(matches!(
+            farm.install_machine(m("Pump")),
+            Err(EngineError::UnsuitableMachine { item, ref prod })
+                if item == m("Pump") && prod == "grain_farm"
+        ));
+        assert!(matches!(
+            farm.install_machine(m("Grain")),
+            Err(EngineError::UnsuitableMachine { .. })
+        ));
+        farm.install_machine(m("Tractor")).unwrap();
+        assert!(matches!(
+            farm.install_machine(m("Tractor")),
+            Err(EngineError::InsufficientMaterial {
+                needed: 1,
+                available: 0,
+                ..
+            })
+        ));
+
+        assert_eq!(farm.owns.amount_of(m("Tractor")), 0);
+        assert_eq!(farm.installed().unwrap().amount_of(m("Tractor")), 1);
+        assert_eq!(
+            farm.machines,
+            vec![Machine {
+                item: m("Tractor"),
+                age: 0
+            }]
+        );
+        farm.save(&conn).unwrap();
+        assert_eq!(check_invariants(&conn).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn machines_run_in_order_until_something_runs_out() {
+        let conn = init_memory_db().unwrap();
+        let mut farm = farm(&conn, 2);
+        farm.install_machine(m("Tractor")).unwrap();
+        farm.install_machine(m("Tractor")).unwrap();
+        // Enough electricity for one tractor.
+        farm.add_material(m("Electricity"), 30).unwrap();
+
+        let report = farm.run_machines().unwrap();
+        assert_eq!(report.batches, 2);
+        assert_eq!(report.consumed.amount_of(m("Electricity")), 20);
+        assert_eq!(report.produced.amount_of(m("Grain")), 200);
+        assert_eq!(report.produced.amount_of(m("Straw")), 40);
+        assert_eq!(report.maintenance, Money::from_dollars(2));
+        assert_eq!(
+            report.idle,
+            vec![(m("Tractor"), MachineIdle::Inputs(m("Electricity")))]
+        );
+        assert_eq!(farm.usd, Money::from_dollars(98));
+        assert_eq!(farm.owns.amount_of(m("Electricity")), 10);
+        // Idle or not, both machines aged.
+        assert!(farm.machines.iter().all(|machine| machine.age == 1));
+        farm.save(&conn).unwrap();
+        assert_eq!(check_invariants(&conn).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn machines_idle_without_funds_or_storage() {
+        let conn = init_memory_db().unwrap();
+        let mut farm = farm(&conn, 1);
+        farm.install_machine(m("Tractor")).unwrap();
+        farm.add_material(m("Electricity"), 100).unwrap();
+
+        farm.spend(Money::from_milli(98_500)).unwrap();
+        let report = farm.run_machines().unwrap();
+        assert_eq!(report.idle, vec![(m("Tractor"), MachineIdle::Funds)]);
+        assert_eq!(report.maintenance, Money::ZERO);
+        assert!(report.consumed.is_empty());
+
+        farm.earn(Money::from_dollars(10)).unwrap();
+        farm.add_material(m("Grain"), u32::MAX - 50).unwrap();
+        let report = farm.run_machines().unwrap();
+        assert_eq!(
+            report.idle,
+            vec![(m("Tractor"), MachineIdle::Storage(m("Grain")))]
+        );
+        assert_eq!(farm.owns.amount_of(m("Electricity")), 100);
+
+        // Room for one batch of the two is still worth running.
+        farm.remove_material(m("Grain"), 100).unwrap();
+        let report = farm.run_machines().unwrap();
+        assert_eq!((report.batches, report.idle.len()), (1, 0));
+        assert_eq!(farm.machines[0].age, 3);
+    }
+
+    #[test]
+    fn machines_depreciate_and_are_scrapped_when_worn_out() {
+        let conn = init_memory_db().unwrap();
+        let mut farm = farm(&conn, 1);
+        farm.install_machine(m("Tractor")).unwrap();
+        let lifetime = farm.machines[0].def().unwrap().lifetime;
+        assert_eq!(farm.machine_value().unwrap(), Money::from_dollars(400));
+
+        farm.machines[0].age = lifetime / 4;
+        assert_eq!(farm.machine_value().unwrap(), Money::from_dollars(300));
+        farm.machines[0].age = lifetime - 1;
+        assert_eq!(farm.machine_value().unwrap(), Money::from_dollars(4));
+
+        // Its last cycle it sits idle without electricity and is scrapped.
+        let report = farm.run_machines().unwrap();
+        assert_eq!(report.scrapped, vec![m("Tractor")]);
+        assert!(farm.machines.is_empty());
+        assert_eq!(farm.machine_value().unwrap(), Money::ZERO);
+        assert!(farm.installed().unwrap().is_empty());
+        farm.save(&conn).unwrap();
+        assert_eq!(check_invariants(&conn).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn machines_run_when_the_cycle_advances() {
+        let conn = init_memory_db().unwrap();
+        let mut exchange = Exchange::load(&conn).unwrap();
+        let mut farm = farm(&conn, 1);
+        farm.install_machine(m("Tractor")).unwrap();
+        farm.add_material(m("Electricity"), 50).unwrap();
+        farm.save(&conn).unwrap();
+        let id = farm.id.unwrap();
+
+        let report = next_cycle(&conn, &mut exchange).unwrap();
+        assert!(report.failed.is_empty());
+        assert_eq!(report.machines.len(), 1);
+        assert_eq!(
+            (report.machines[0].0, report.machines[0].1.batches),
+            (id, 2)
+        );
+
+        let farm = ProdInstance::load(&conn, id).unwrap().unwrap();
+        assert_eq!(farm.owns.amount_of(m("Grain")), 200);
+        assert_eq!(farm.owns.amount_of(m("Electricity")), 30);
+        assert_eq!(farm.machines[0].age, 1);
+        assert_eq!(check_invariants(&conn).unwrap(), vec![]);
+    }
+}
@@ -1,33 +1,82 @@
-use crate::{materials::Material, production::ProdInstance};
+use crate::{
+    error::{EngineError, EngineResult},
+    ledger::{Account, Asset, Holder, Reason},
+    materials::Material,
+    money::Money,
+    production::ProdInstance,
+};
 
 impl ProdInstance {
-    pub fn earn(&mut self, money: f32) {
-        self.usd += money;
+    /// The company's side of its ledger accounts. Entries posted before the
+    /// first save are moved onto the real id when it is assigned.
+    pub fn holder(&self) -> Holder {
+        Holder::Company(self.id.unwrap_or(0))
     }
-    pub fn spend(&mut self, amount: f32) {
+
+    pub fn earn(&mut self, money: Money) -> EngineResult<()> {
+        self.usd = self.usd.try_add(money)?;
+        self.journal.post(
+            Account::cash(self.holder()),
+            Account::world(Asset::Cash),
+            money.milli(),
+            Reason::Grant,
+        );
+        Ok(())
+    }
+    pub fn spend(&mut self, amount: Money) -> EngineResult<()> {
+        self.spend_on(amount, Reason::Spend)
+    }
+
+    /// Pays this cycle's upkeep. Fails without paying anything if the
+    /// company can't cover it.
+    pub fn pay_upkeep(&mut self) -> EngineResult<()> {
+        self.spend_on(self.upkeep, Reason::Upkeep)
+    }
+
+    pub(crate) fn spend_on(&mut self, amount: Money, reason: Reason) -> EngineResult<()> {
         if amount > self.usd {
-            eprintln!(
-                "Warning: Tried to spend {} but only have {}",
-                amount, self.usd
-            );
-        } else {
-            self.usd -= amount;
+            return Err(EngineError::InsufficientFunds {
+                needed: amount,
+                available: self.usd,
+            });
         }
+        self.usd = self.usd.try_sub(amount)?;
+        self.journal.post(
+            Account::world(Asset::Cash),
+            Account::cash(self.holder()),
+            amount.milli(),
+            reason,
+        );
+        Ok(())
     }
 
-    pub fn add_material(&mut self, item: Material, amount: u32) {
-        self.owns.add(item, amount);
+    pub fn add_material(&mut self, item: Material, amount: u32) -> EngineResult<()> {
+        self.owns.add(item, amount)?;
+        self.journal.post(
+            Account::goods(self.holder(), item),
+            Account::world(Asset::Goods(item)),
+            amount as i64,
+            Reason::Grant,
+        );
+        Ok(())
     }
 
-    pub fn remove_material(&mut self, item: Material, amount: u32) {
-        if amount > self.owns.amount_of(item) {
-            eprintln!(
-                "Warning: Tried to remove {} but only have {}",
-                amount,
-                self.owns.amount_of(item)
-            );
-        } else {
-            self.owns.remove(item, amount);
+    pub fn remove_material(&mut self, item: Material, amount: u32) -> EngineResult<()> {
+        let available = self.owns.amount_of(item);
+        if amount > available {
+            return Err(EngineError::InsufficientMaterial {
+                item,
+                needed: amount,
+                available,
+            });
         }
+        self.owns.remove(item, amount)?;
+        self.journal.post(
+            Account::world(Asset::Goods(item)),
+            Account::goods(self.holder(), item),
+            amount as i64,
+            Reason::Spend,
+        );
+        Ok(())
     }
 }
@@ -1,3 +1,6 @@
 use crate::flatten_modules;
 
-flatten_modules!(base_prod, save, load, workers, misc, work, prod_list);
+flatten_modules!(
+    base_prod, save, load, query, workers, misc, escrow, work, batch, machines, payroll, catalog,
+    tiers
+);
@@ -0,0 +1,298 @@
+use crate::{
+    db::atomic,
+    error::{EngineError, EngineResult},
+    ledger::{Account, Holder, Reason},
+    money::Money,
+    player::Player,
+    production::ProdInstance,
+};
+use rusqlite::Connection;
+
+/// What a worker got paid, and what the company still owes them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Payslip {
+    pub paid: Money,
+    pub owed: Money,
+}
+
+impl ProdInstance {
+    /// Wages earned by `player` that the company couldn't pay yet.
+    pub fn wages_owed_to(&self, player: u32) -> Money {
+        self.wages_owed.get(&player).copied().unwrap_or(Money::ZERO)
+    }
+
+    /// Pays `player` as much of their back pay as the company's cash
+    /// covers. Save both with [`ProdInstance::save_with_workers`].
+    pub fn settle_wages(&mut self, player: &mut Player) -> EngineResult<Payslip> {
+        self.pay_wages(player, Money::ZERO)
+    }
+
+    /// What `player` earns for a shift that turned out one batch.
+    pub(crate) fn shift_wage(&self, player: u32) -> EngineResult<Money> {
+        let worker = self
+            .human_workers
+            .iter()
+            .find(|worker| worker.player == player)
+            .ok_or(EngineError::NotHired { player })?;
+        let units = self.recipe.outputs.first().map_or(0, |(_, amount)| *amount);
+        worker.contract.wage.for_shift(units)
+    }
+
+    /// Saves the company together with `workers` in one savepoint. Wages
+    /// move cash between the company and its workers, so save them this way
+    /// after paying anyone: either every row is written or none is, and on
+    /// failure all of them are left as they were in memory.
+    pub fn save_with_workers(
+        &mut self,
+        conn: &Connection,
+        workers: &mut [Player],
+    ) -> EngineResult<u32> {
+        let (company, players) = (self.clone(), workers.to_vec());
+        let saved = atomic(conn, || {
+            for player in workers.iter_mut() {
+                player.save(conn)?;
+            }
+            self.save(conn)
+        });
+        if saved.is_err() {
+            *self = company;
+            workers.clone_from_slice(&players);
+        }
+        saved
+    }
+
+    /// Pays `earned` plus anything already owed to `player` out of the
+    /// company's cash. Whatever the cash doesn't cover is kept as owed and
+    /// paid first next time. The player's cash only changes in memory, so
+    /// save them with [`ProdInstance::save_with_workers`].
+    pub(crate) fn pay_wages(
+        &mut self,
+        player: &mut Player,
+        earned: Money,
+    ) -> EngineResult<Payslip> {
+        let due = self.wages_owed_to(player.id).try_add(earned)?;
+        let paid = due.min(self.usd);
+        let owed = due.try_sub(paid)?;
+
+        player.usd = player.usd.try_add(paid)?;
+        self.usd = self.usd.try_sub(paid)?;
+        self.journal.post(
+            Account::cash(Holder::Player(player.id)),
+            Account::cash(self.holder()),
+            paid.milli(),
+            Reason::Wage,
+        );
+        if owed.is_zero() {
+            self.wages_owed.remove(&player.id);
+        } else {
+            self.wages_owed.insert(player.id, owed);
+        }
+        Ok(Payslip { paid, owed })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cycle::next_cycle,
+        db::init_memory_db,
+        extange::Exchange,
+        ledger::check_invariants,
+        production::{Contract, Prod, Wage},
+    };
+
+    const PER_SHIFT: Contract = Contract {
+        wage: Wage::PerShift(Money::from_dollars(10)),
+        duration: None,
+        notice: 2,
+    };
+
+    // A grain farm with `cash`, employing a new player on each contract.
+    fn farm(conn: &Connection, cash: Money, contracts: &[Contract]) -> (ProdInstance, Vec<Player>) {
+        let mut owner = Player::create(conn, "farmer").unwrap();
+        owner.earn(Money::from_dollars(10_000)).unwrap();
+        let base = Prod::lookup("grain_farm").unwrap();
+        let mut farm = ProdInstance::new(conn, base, "Farm".to_string(), &mut owner).unwrap();
+        owner.save(conn).unwrap();
+        farm.earn(cash).unwrap();
+
+        let mut workers = Vec::new();
+        for (i, contract) in contracts.iter().enumerate() {
+            let worker = Player::create(conn, &format!("worker{}", i)).unwrap();
+            farm.hire_worker(&worker, *contract, 0).unwrap();
+            workers.push(worker);
+        }
+        farm.save_with_workers(conn, &mut workers).unwrap();
+        (farm, workers)
+    }
+
+    fn payslip(paid: Money, owed: Money) -> Payslip {
+        Payslip { paid, owed }
+    }
+
+    #[test]
+    fn shift_and_unit_wages_are_paid_and_saved_together() {
+        let conn = init_memory_db().unwrap();
+        // Grain farms turn out 100 grain a shift, so 5 cents a unit is $5.
+        let per_unit = Contract {
+            wage: Wage::PerUnit(Money::from_milli(50)),
+            ..PER_SHIFT
+        };
+        let (mut farm, mut workers) = farm(&conn, Money::from_dollars(100), &[PER_SHIFT, per_unit]);
+
+        assert_eq!(
+            farm.human_worked(&mut workers[0], 0).unwrap(),
+            payslip(Money::from_dollars(10), Money::ZERO)
+        );
+        assert_eq!(
+            farm.human_worked(&mut workers[1], 0).unwrap(),
+            payslip(Money::from_dollars(5), Money::ZERO)
+        );
+        assert_eq!(farm.usd, Money::from_dollars(85));
+        farm.save_with_workers(&conn, &mut workers).unwrap();
+
+        for (worker, wage) in workers.iter().zip([10, 5]) {
+            let stored = Player::load(&conn, worker.id).unwrap().unwrap();
+            assert_eq!(stored.usd, Money::from_dollars(wage));
+        }
+        assert_eq!(check_invariants(&conn).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn a_broke_company_owes_wages_until_it_settles() {
+        let conn = init_memory_db().unwrap();
+        let (mut farm, mut workers) = farm(&conn, Money::from_dollars(15), &[PER_SHIFT]);
+        let worker = &mut workers[0];
+
+        let shift = |farm: &mut ProdInstance, worker: &mut Player| {
+            farm.reset_workers();
+            farm.human_worked(worker, 0).unwrap()
+        };
+        let dollars = Money::from_dollars;
+        assert_eq!(shift(&mut farm, worker), payslip(dollars(10), Money::ZERO));
+        assert_eq!(shift(&mut farm, worker), payslip(dollars(5), dollars(5)));
+        assert_eq!(shift(&mut farm, worker), payslip(Money::ZERO, dollars(15)));
+        assert_eq!(farm.wages_owed_to(worker.id), dollars(15));
+        farm.save_with_workers(&conn, &mut workers).unwrap();
+
+        // What is owed is kept with the company and paid before new wages.
+        let mut farm = ProdInstance::load(&conn, farm.id.unwrap())
+            .unwrap()
+            .unwrap();
+        assert_eq!(farm.wages_owed_to(workers[0].id), dollars(15));
+        farm.earn(dollars(12)).unwrap();
+        assert_eq!(
+            farm.settle_wages(&mut workers[0]).unwrap(),
+            payslip(dollars(12), dollars(3))
+        );
+        farm.earn(dollars(20)).unwrap();
+        assert_eq!(
+            farm.settle_wages(&mut workers[0]).unwrap(),
+            payslip(dollars(3), Money::ZERO)
+        );
+        assert_eq!(farm.wages_owed_to(workers[0].id), Money::ZERO);
+        assert_eq!(workers[0].usd, dollars(30));
+        assert_eq!(farm.usd, dollars(17));
+        farm.save_with_workers(&conn, &mut workers).unwrap();
+        assert_eq!(check_invariants(&conn).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn a_failed_save_leaves_the_company_and_workers_unsaved() {
+        let conn = init_memory_db().unwrap();
+        let (mut farm, mut workers) = farm(&conn, Money::from_dollars(100), &[PER_SHIFT]);
+        farm.human_worked(&mut workers[0], 0).unwrap();
+
+        conn.execute_batch(
+            "CREATE TEMP TRIGGER company_locked BEFORE UPDATE ON company
+             BEGIN SELECT RAISE(ABORT, 'locked'); END;",
+        )
+        .unwrap();
+        let (company, players) = (farm.clone(), workers.clone());
+        assert!(matches!(
+            farm.save_with_workers(&conn, &mut workers),
+            Err(EngineError::Persistence(_))
+        ));
+        assert_eq!(farm, company);
+        assert_eq!(workers[0].usd, players[0].usd);
+        assert_eq!(workers[0].journal, players[0].journal);
+        let stored = Player::load(&conn, workers[0].id).unwrap().unwrap();
+        assert_eq!(stored.usd, Money::ZERO);
+
+        conn.execute_batch("DROP TRIGGER company_locked").unwrap();
+        farm.save_with_workers(&conn, &mut workers).unwrap();
+        let stored = Player::load(&conn, workers[0].id).unwrap().unwrap();
+        assert_eq!(stored.usd, Money::from_dollars(10));
+        assert_eq!(check_invariants(&conn).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn notice_runs_out_before_a_fixed_term_or_after_it() {
+        let conn = init_memory_db().unwrap();
+        let fixed_term = Contract {
+            duration: Some(3),
+            ..PER_SHIFT
+        };
+        let (mut farm, mut workers) = farm(
+            &conn,
+            Money::from_dollars(100),
+            &[PER_SHIFT, fixed_term, PER_SHIFT],
+        );
+
+        // Two cycles' notice either way, unless the contract ends sooner.
+        assert_eq!(farm.fire_worker(workers[0].id, 5).unwrap(), 7);
+        assert_eq!(farm.quit(&workers[1], 2).unwrap(), 3);
+        assert_eq!(farm.quit(&workers[2], 0).unwrap(), 2);
+        assert!(matches!(
+            farm.fire_worker(999, 0),
+            Err(EngineError::NotHired { player: 999 })
+        ));
+
+        // They work out their notice, then can't take another shift.
+        farm.human_worked(&mut workers[0], 6).unwrap();
+        farm.reset_workers();
+        assert!(matches!(
+            farm.human_worked(&mut workers[0], 7),
+            Err(EngineError::NotHired { .. })
+        ));
+        assert!(farm.plan_batches(&workers, None, 7).workers.is_empty());
+        let plan = farm.plan_batches(&workers, None, 2);
+        assert_eq!(plan.workers, vec![workers[0].id, workers[1].id]);
+
+        assert_eq!(farm.end_contracts(1), Vec::<u32>::new());
+        assert_eq!(farm.end_contracts(3), vec![workers[1].id, workers[2].id]);
+        assert_eq!(farm.end_contracts(7), vec![workers[0].id]);
+        assert!(farm.human_workers.is_empty());
+    }
+
+    #[test]
+    fn contracts_end_when_the_cycle_advances() {
+        let conn = init_memory_db().unwrap();
+        let mut exchange = Exchange::load(&conn).unwrap();
+        let short_notice = Contract {
+            notice: 1,
+            ..PER_SHIFT
+        };
+        let (mut farm, mut workers) = farm(
+            &conn,
+            Money::from_dollars(100),
+            &[short_notice, short_notice],
+        );
+        farm.fire_worker(workers[0].id, 0).unwrap();
+        farm.human_worked(&mut workers[0], 0).unwrap();
+        farm.save_with_workers(&conn, &mut workers).unwrap();
+        let id = farm.id.unwrap();
+
+        let report = next_cycle(&conn, &mut exchange).unwrap();
+        assert!(report.failed.is_empty());
+        assert_eq!(report.left, vec![(id, vec![workers[0].id])]);
+        let farm = ProdInstance::load(&conn, id).unwrap().unwrap();
+        let hired: Vec<u32> = farm.human_workers.iter().map(|w| w.player).collect();
+        assert_eq!(hired, vec![workers[1].id]);
+
+        let report = next_cycle(&conn, &mut exchange).unwrap();
+        assert!(report.left.is_empty());
+        assert_eq!(check_invariants(&conn).unwrap(), vec![]);
+    }
+}
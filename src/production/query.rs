@@ -0,0 +1,39 @@
+use crate::{error::EngineResult, materials::Material};
+use rusqlite::{Connection, params};
+
+/// Ids of every company, oldest first.
+pub fn company_ids(conn: &Connection) -> EngineResult<Vec<u32>> {
+    let mut stmt = conn.prepare("SELECT id FROM company ORDER BY id")?;
+    let ids = stmt
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<Vec<u32>, _>>()?;
+    Ok(ids)
+}
+
+/// Ids of companies with at least `at_least` of `item` available.
+pub fn companies_holding(
+    conn: &Connection,
+    item: Material,
+    at_least: u32,
+) -> EngineResult<Vec<u32>> {
+    let mut stmt = conn.prepare(
+        "SELECT company_id FROM company_inventory
+         WHERE item = ?1 AND owned >= ?2
+         ORDER BY company_id",
+    )?;
+    let ids = stmt
+        .query_map(params![item.to_string_key(), at_least], |row| row.get(0))?
+        .collect::<Result<Vec<u32>, _>>()?;
+    Ok(ids)
+}
+
+/// Ids of companies that have hired `player`.
+pub fn companies_employing(conn: &Connection, player: u32) -> EngineResult<Vec<u32>> {
+    let mut stmt = conn.prepare(
+        "SELECT company_id FROM company_workers WHERE player_id = ?1 ORDER BY company_id",
+    )?;
+    let ids = stmt
+        .query_map(params![player], |row| row.get(0))?
+        .collect::<Result<Vec<u32>, _>>()?;
+    Ok(ids)
+}
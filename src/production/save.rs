@@ -1,61 +1,258 @@
-use crate::production::ProdInstance;
-use json::{JsonValue, object};
-use rusqlite::{Connection, Result, params};
+use crate::{
+    db::atomic,
+    error::{EngineError, EngineResult},
+    ledger::Holder,
+    materials::Material,
+    production::{ProdInstance, Wage},
+};
+use rusqlite::{Connection, params};
 
 impl ProdInstance {
-    pub fn save(&mut self, conn: &Connection) -> Result<u32> {
-        // Build the JSON object for `consumes.inputs`
-        let inputs_obj =
-            self.recipe
-                .inputs
-                .iter()
-                .fold(object::Object::new(), |mut obj, (mat, amt)| {
-                    obj.insert(mat.to_string_key(), JsonValue::from(*amt));
-                    obj
-                });
-
-        // Full data payload
-        let data = object! {
-            usd: self.usd,
-            human_prod_rate: self.human_prod_rate,
-            max_human_workers: self.max_human_workers,
-            human_workers: self.human_workers.clone(),
-            owns: {
-                grain: self.owns.grain,
-                electricity: self.owns.electricity,
-                water: self.owns.water,
-                food: self.owns.food,
-            },
-            creates: self.creates.to_string_key(),
-            recipe: {
-                inputs: inputs_obj,
-            },
-        };
-
-        let data_str = data.dump();
+    /// Writes the company row, its inventory, workers and their contracts,
+    /// unpaid wages, recipe and machines, along with any ledger entries it
+    /// has posted since the last save.
+    ///
+    /// Fails with [`EngineError::StaleCompany`] if the company was saved
+    /// from another copy since this one was loaded, e.g. because one of its
+    /// resting orders was filled. Reload it and apply the change again.
+    pub fn save(&mut self, conn: &Connection) -> EngineResult<u32> {
+        // A new company only takes its id, and its entries their holder,
+        // once the savepoint is released, so a failed first save can be
+        // retried as an insert.
+        let mut journal = self.journal.clone();
+        let id = atomic(conn, || -> EngineResult<u32> {
+            let id = self.save_row(conn)?;
+            if self.id.is_none() {
+                journal.rebind(Holder::Company(0), Holder::Company(id));
+            }
 
+            conn.execute(
+                "DELETE FROM company_inventory WHERE company_id = ?1",
+                params![id],
+            )?;
+            let mut stmt = conn.prepare_cached(
+                "INSERT INTO company_inventory (company_id, item, owned, reserved)
+                 VALUES (?1, ?2, ?3, ?4)",
+            )?;
+            for &item in Material::all() {
+                let (owned, reserved) = (self.owns.amount_of(item), self.reserved.amount_of(item));
+                if owned > 0 || reserved > 0 {
+                    stmt.execute(params![id, item.to_string_key(), owned, reserved])?;
+                }
+            }
+
+            conn.execute(
+                "DELETE FROM company_workers WHERE company_id = ?1",
+                params![id],
+            )?;
+            let mut stmt = conn.prepare_cached(
+                "INSERT INTO company_workers
+                 (company_id, player_id, worked, wage_kind, wage, duration, notice, hired_at, ends_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            )?;
+            for worker in self.human_workers.iter() {
+                let (wage_kind, wage) = match worker.contract.wage {
+                    Wage::PerShift(wage) => ("shift", wage),
+                    Wage::PerUnit(wage) => ("unit", wage),
+                };
+                stmt.execute(params![
+                    id,
+                    worker.player,
+                    worker.worked,
+                    wage_kind,
+                    wage,
+                    worker.contract.duration,
+                    worker.contract.notice,
+                    worker.hired_at,
+                    worker.ends_at
+                ])?;
+            }
+
+            conn.execute(
+                "DELETE FROM company_wages_owed WHERE company_id = ?1",
+                params![id],
+            )?;
+            let mut stmt = conn.prepare_cached(
+                "INSERT INTO company_wages_owed (company_id, player_id, owed) VALUES (?1, ?2, ?3)",
+            )?;
+            for (player, owed) in self.wages_owed.iter() {
+                stmt.execute(params![id, player, owed])?;
+            }
+
+            save_goods(conn, "company_recipe_input", id, &self.recipe.inputs)?;
+            save_goods(conn, "company_recipe_output", id, &self.recipe.outputs)?;
+
+            conn.execute(
+                "DELETE FROM company_machines WHERE company_id = ?1",
+                params![id],
+            )?;
+            let mut stmt = conn.prepare_cached(
+                "INSERT INTO company_machines (company_id, item, age) VALUES (?1, ?2, ?3)",
+            )?;
+            for machine in self.machines.iter() {
+                stmt.execute(params![id, machine.item.to_string_key(), machine.age])?;
+            }
+
+            journal.commit(conn)?;
+            Ok(id)
+        })?;
+        self.id = Some(id);
+        self.journal = journal;
+        self.version += 1;
+        Ok(id)
+    }
+
+    fn save_row(&self, conn: &Connection) -> EngineResult<u32> {
         if let Some(id) = self.id {
             // Update existing row
-            conn.execute(
-                "UPDATE company SET name = ?1, owner = ?2, type = ?3, data = ?4 WHERE id = ?5",
+            let updated = conn.execute(
+                "UPDATE company
+                 SET name = ?1, owner = ?2, type = ?3, usd = ?4, reserved_usd = ?5,
+                     max_human_workers = ?6, shift_energy = ?7, upkeep = ?8,
+                     version = version + 1
+                 WHERE id = ?9 AND version = ?10",
                 params![
                     self.name,
-                    self.owner.to_string(),
+                    self.owner,
                     self.base_type,
-                    data_str,
-                    id
+                    self.usd,
+                    self.reserved_usd,
+                    self.max_human_workers,
+                    self.shift_energy,
+                    self.upkeep,
+                    id,
+                    self.version
                 ],
             )?;
+            if updated == 0 {
+                return Err(EngineError::StaleCompany(id));
+            }
             Ok(id)
         } else {
             // Insert new row
             conn.execute(
-                "INSERT INTO company (name, owner, type, data) VALUES (?1, ?2, ?3, ?4)",
-                params![self.name, self.owner.to_string(), self.base_type, data_str],
+                "INSERT INTO company
+                 (name, owner, type, usd, reserved_usd, max_human_workers, shift_energy, upkeep,
+                  version)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                params![
+                    self.name,
+                    self.owner,
+                    self.base_type,
+                    self.usd,
+                    self.reserved_usd,
+                    self.max_human_workers,
+                    self.shift_energy,
+                    self.upkeep,
+                    self.version + 1
+                ],
             )?;
-            let new_id = conn.last_insert_rowid() as u32;
-            self.id = Some(new_id);
-            Ok(new_id)
+            Ok(conn.last_insert_rowid() as u32)
         }
     }
 }
+
+// Replaces one side of the company's recipe, keeping the recipe's order.
+fn save_goods(
+    conn: &Connection,
+    table: &str,
+    id: u32,
+    goods: &[(Material, u32)],
+) -> EngineResult<()> {
+    conn.execute(
+        &format!("DELETE FROM {} WHERE company_id = ?1", table),
+        params![id],
+    )?;
+    let mut stmt = conn.prepare_cached(&format!(
+        "INSERT INTO {} (company_id, item, amount) VALUES (?1, ?2, ?3)",
+        table
+    ))?;
+    for (item, amount) in goods {
+        stmt.execute(params![id, item.to_string_key(), amount])?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::init_memory_db, extange::Exchange, ledger::check_invariants, money::Money,
+        player::Player, production::Prod,
+    };
+    use std::collections::HashMap;
+
+    fn company(conn: &Connection, owner: &str, prod: &str) -> ProdInstance {
+        let mut owner = Player::create(conn, owner).unwrap();
+        owner.earn(Money::from_dollars(10_000)).unwrap();
+        let base = Prod::lookup(prod).unwrap();
+        let company = ProdInstance::new(conn, base, prod.to_string(), &mut owner).unwrap();
+        owner.save(conn).unwrap();
+        company
+    }
+
+    #[test]
+    fn a_failed_first_save_leaves_the_company_unsaved() {
+        let conn = init_memory_db().unwrap();
+        let mut farm = company(&conn, "farmer", "grain_farm");
+        let mut copy = farm.clone();
+        copy.id = None;
+        copy.version = 0;
+        copy.earn(Money::from_dollars(5)).unwrap();
+
+        conn.execute_batch(
+            "CREATE TEMP TRIGGER ledger_full BEFORE INSERT ON ledger
+             BEGIN SELECT RAISE(ABORT, 'disk full'); END;",
+        )
+        .unwrap();
+        let before = copy.clone();
+        assert!(matches!(copy.save(&conn), Err(EngineError::Persistence(_))));
+        assert_eq!(copy, before);
+
+        conn.execute_batch("DROP TRIGGER ledger_full").unwrap();
+        let id = copy.save(&conn).unwrap();
+        assert_ne!(Some(id), farm.id);
+        assert_eq!(copy.id, Some(id));
+        assert!(copy.journal.entries().is_empty());
+        farm.save(&conn).unwrap();
+        assert_eq!(check_invariants(&conn).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn saving_a_stale_copy_fails() {
+        let conn = init_memory_db().unwrap();
+        let mut exchange = Exchange::load(&conn).unwrap();
+        let grain = Material::lookup("Grain").unwrap();
+
+        let mut farm = company(&conn, "farmer", "grain_farm");
+        farm.add_material(grain, 50).unwrap();
+        farm.save(&conn).unwrap();
+        farm.quick_sell(&conn, &mut exchange, grain, Money::from_milli(250), 50)
+            .unwrap();
+
+        let mut plant = company(&conn, "cook", "food_processing_plant");
+        plant.earn(Money::from_dollars(100)).unwrap();
+        let report = plant
+            .buy_needed(&conn, &mut exchange, 5, &HashMap::new())
+            .unwrap();
+        assert_eq!(report.materials[2].bought, 25);
+
+        // The fill paid the farm through a copy loaded from the database, so
+        // this copy no longer knows about the sale.
+        farm.earn(Money::from_dollars(1)).unwrap();
+        assert!(matches!(
+            farm.save(&conn),
+            Err(EngineError::StaleCompany(id)) if Some(id) == farm.id
+        ));
+        assert_eq!(check_invariants(&conn).unwrap(), vec![]);
+
+        let mut farm = ProdInstance::load(&conn, farm.id.unwrap())
+            .unwrap()
+            .unwrap();
+        assert_eq!(farm.usd, Money::from_milli(6_250));
+        assert_eq!(farm.reserved(grain), 25);
+        farm.earn(Money::from_dollars(1)).unwrap();
+        farm.save(&conn).unwrap();
+        assert_eq!(check_invariants(&conn).unwrap(), vec![]);
+    }
+}
@@ -0,0 +1,21 @@
+use crate::materials::Material;
+
+/// One level of the supply chain, as declared in the production file.
+/// Which goods a building may use as inputs is decided by the material
+/// categories its tier accepts, so adding a new link to a chain (say
+/// restaurants on top of food processing) is a data change.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tier {
+    pub level: u32,
+    pub name: String,
+    /// Material categories buildings on this tier may consume.
+    pub consumes: Vec<String>,
+}
+
+impl Tier {
+    pub fn may_consume(&self, item: Material) -> bool {
+        self.consumes
+            .iter()
+            .any(|category| category == item.category())
+    }
+}
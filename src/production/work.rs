@@ -1,52 +1,232 @@
-use crate::{materials::Material, player::Player, production::ProdInstance};
+use crate::{
+    error::{EngineError, EngineResult},
+    ledger::{Account, Asset, Reason},
+    materials::{Inventory, Material},
+    player::Player,
+    production::{Payslip, ProdInstance, catalog},
+};
 
 impl ProdInstance {
-    pub fn human_worked(&mut self, player: &mut Player) -> Result<(), String> {
-        for entry in self.human_workers.members_mut() {
-            if let Some(pid) = entry[0].as_u32() {
-                if pid == player.id {
-                    if entry[1].as_bool().unwrap_or(false) {
-                        return Err(format!("Player {} has already worked this cycle.", pid));
-                    }
-                    if player.energy < 4 {
-                        return Err(format!("Player {} doesn't have enough energy.", pid));
-                    }
-
-                    for (mat, amount) in self.recipe.inputs.iter() {
-                        let owned = match mat {
-                            Material::Grain => self.owns.grain,
-                            Material::Electricity => self.owns.electricity,
-                            Material::Water => self.owns.water,
-                            Material::Food => {
-                                return Err("Tier 2 companies shouldn’t consume Food!".to_string());
-                            }
-                        };
-                        if owned < *amount {
-                            return Err(format!(
-                                "Not enough {:?} to produce {:?}",
-                                mat, self.creates
-                            ));
-                        }
-                    }
-
-                    for (mat, amount) in self.recipe.inputs.iter() {
-                        match mat {
-                            Material::Grain => self.owns.grain -= *amount,
-                            Material::Electricity => self.owns.electricity -= *amount,
-                            Material::Water => self.owns.water -= *amount,
-                            Material::Food => {
-                                return Err("Tier 2 companies shouldn’t consume Food!".to_string());
-                            }
-                        }
-                    }
-
-                    self.owns.add(self.creates, self.human_prod_rate);
-                    player.energy -= 4;
-                    entry[1] = true.into();
-                    return Ok(());
-                }
+    /// Runs one batch of the recipe for `player`'s shift in `cycle`: every
+    /// input is checked before anything is taken, so the batch either
+    /// consumes all inputs and yields all outputs or changes nothing. The
+    /// player is then paid their wage, or as much of it as the company can
+    /// afford. A worker whose employment has ended counts as not hired.
+    pub fn human_worked(&mut self, player: &mut Player, cycle: u32) -> EngineResult<Payslip> {
+        self.check_recipe()?;
+        let Some(index) = self
+            .human_workers
+            .iter()
+            .position(|worker| worker.player == player.id && worker.employed_in(cycle))
+        else {
+            return Err(EngineError::NotHired { player: player.id });
+        };
+        if self.human_workers[index].worked {
+            return Err(EngineError::AlreadyWorked { player: player.id });
+        }
+        if player.energy < self.shift_energy {
+            return Err(EngineError::NoEnergy {
+                player: player.id,
+                needed: self.shift_energy,
+                available: player.energy,
+            });
+        }
+
+        if let Some((0, item)) = self.batches_by_inputs(|_| 0) {
+            let needed = self.recipe.needs(item);
+            return Err(EngineError::InsufficientMaterial {
+                item,
+                needed,
+                available: self.owns.amount_of(item),
+            });
+        }
+
+        if let Some((0, item)) = self.batches_by_storage() {
+            return Err(EngineError::MaterialOverflow(item));
+        }
+
+        let wage = self.shift_wage(player.id)?;
+        self.run_recipe(1)?;
+        player.energy -= self.shift_energy;
+        self.human_workers[index].worked = true;
+        self.pay_wages(player, wage)
+    }
+
+    /// Fails if the company's tier may not use one of its recipe's inputs.
+    /// The catalog is checked when it loads, but a company's recipe is its
+    /// own copy and can drift from it.
+    pub(crate) fn check_recipe(&self) -> EngineResult<()> {
+        let catalog = catalog();
+        let prod = catalog
+            .get(&self.base_type)
+            .ok_or_else(|| EngineError::UnknownProd(self.base_type.clone()))?;
+        for &(item, _) in self.recipe.inputs.iter() {
+            if !catalog.may_consume(prod, item) {
+                return Err(EngineError::ForbiddenInput {
+                    item,
+                    prod: prod.id.clone(),
+                });
             }
         }
-        Err(format!("Player {} is not hired here.", player.id))
+        Ok(())
+    }
+
+    /// How many batches the company's goods cover once `set_aside` of each
+    /// input is kept back, and which input runs out first. `None` if the
+    /// recipe takes no inputs.
+    pub(crate) fn batches_by_inputs(
+        &self,
+        set_aside: impl Fn(Material) -> u32,
+    ) -> Option<(u32, Material)> {
+        self.recipe
+            .inputs
+            .iter()
+            .filter(|(_, amount)| *amount > 0)
+            .map(|&(mat, amount)| {
+                let spare = self.owns.amount_of(mat).saturating_sub(set_aside(mat));
+                (spare / amount, mat)
+            })
+            .min_by_key(|&(batches, _)| batches)
+    }
+
+    /// How many batches' outputs still fit in the inventory, and which
+    /// output fills up first. `None` if the recipe yields nothing.
+    pub(crate) fn batches_by_storage(&self) -> Option<(u32, Material)> {
+        self.recipe
+            .outputs
+            .iter()
+            .filter(|(_, amount)| *amount > 0)
+            .map(|&(mat, amount)| ((u32::MAX - self.owns.amount_of(mat)) / amount, mat))
+            .min_by_key(|&(batches, _)| batches)
+    }
+
+    /// Takes the inputs for `batches` batches and adds their outputs,
+    /// returning what was consumed and what was produced. Fails without
+    /// changing anything if the inputs aren't there or the outputs don't fit.
+    pub(crate) fn run_recipe(&mut self, batches: u32) -> EngineResult<(Inventory, Inventory)> {
+        let mut owns = self.owns.clone();
+        let mut consumed = Inventory::new();
+        let mut produced = Inventory::new();
+        for &(mat, amount) in self.recipe.inputs.iter() {
+            let total = amount
+                .checked_mul(batches)
+                .ok_or(EngineError::MaterialOverflow(mat))?;
+            owns.remove(mat, total)?;
+            consumed.add(mat, total)?;
+        }
+        for &(mat, amount) in self.recipe.outputs.iter() {
+            let total = amount
+                .checked_mul(batches)
+                .ok_or(EngineError::MaterialOverflow(mat))?;
+            owns.add(mat, total)?;
+            produced.add(mat, total)?;
+        }
+
+        self.owns = owns;
+        let holder = self.holder();
+        for (mat, total) in consumed.iter() {
+            self.journal.post(
+                Account::world(Asset::Goods(mat)),
+                Account::goods(holder, mat),
+                total as i64,
+                Reason::Consume,
+            );
+        }
+        for (mat, total) in produced.iter() {
+            self.journal.post(
+                Account::goods(holder, mat),
+                Account::world(Asset::Goods(mat)),
+                total as i64,
+                Reason::Produce,
+            );
+        }
+        Ok((consumed, produced))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::init_memory_db,
+        materials::Recipe,
+        money::Money,
+        production::{Contract, Prod, Wage},
+    };
+
+    fn m(key: &str) -> Material {
+        Material::lookup(key).unwrap()
+    }
+
+    // A company of type `prod` with `worker` hired and able to work.
+    fn company(prod: &str, worker: &mut Player) -> ProdInstance {
+        let conn = init_memory_db().unwrap();
+        let mut owner = Player::create(&conn, "owner").unwrap();
+        owner.earn(Money::from_dollars(10_000)).unwrap();
+        let base = Prod::lookup(prod).unwrap();
+        let mut company = ProdInstance::new(&conn, base, prod.to_string(), &mut owner).unwrap();
+        worker.id = 99;
+        worker.energy = 50;
+        let contract = Contract {
+            wage: Wage::PerShift(Money::ZERO),
+            duration: None,
+            notice: 0,
+        };
+        company.hire_worker(worker, contract, 0).unwrap();
+        company
+    }
+
+    #[test]
+    fn the_first_short_input_is_reported() {
+        let mut worker = Player::new("worker".to_string());
+        let mut plant = company("food_processing_plant", &mut worker);
+        plant.add_material(m("Electricity"), 25).unwrap();
+        plant.add_material(m("Water"), 5).unwrap();
+        plant.add_material(m("Grain"), 12).unwrap();
+
+        assert_eq!(plant.batches_by_inputs(|_| 0), Some((1, m("Water"))));
+        assert_eq!(
+            plant.batches_by_inputs(|mat| if mat == m("Electricity") { 20 } else { 0 }),
+            Some((0, m("Electricity")))
+        );
+        plant.human_worked(&mut worker, 0).unwrap();
+        assert_eq!(plant.owns.amount_of(m("Food")), 5);
+
+        plant.reset_workers();
+        assert!(matches!(
+            plant.human_worked(&mut worker, 0),
+            Err(EngineError::InsufficientMaterial { item, needed: 5, available: 0 })
+                if item == m("Water")
+        ));
+    }
+
+    #[test]
+    fn inputs_outside_the_tier_are_refused() {
+        let mut worker = Player::new("worker".to_string());
+        let mut farm = company("grain_farm", &mut worker);
+        farm.recipe = Recipe::dynamic(vec![(m("Food"), 1)], vec![(m("Grain"), 10)]);
+        farm.add_material(m("Food"), 10).unwrap();
+        let before = farm.clone();
+
+        assert!(matches!(
+            farm.human_worked(&mut worker, 0),
+            Err(EngineError::ForbiddenInput { item, ref prod })
+                if item == m("Food") && prod == "grain_farm"
+        ));
+        assert!(matches!(
+            farm.run_batches(std::slice::from_mut(&mut worker), None, 0),
+            Err(EngineError::ForbiddenInput { .. })
+        ));
+        assert!(matches!(
+            farm.run_machines(),
+            Err(EngineError::ForbiddenInput { .. })
+        ));
+        assert_eq!(farm, before);
+
+        farm.base_type = "gold_mine".to_string();
+        assert!(matches!(
+            farm.human_worked(&mut worker, 0),
+            Err(EngineError::UnknownProd(_))
+        ));
     }
 }
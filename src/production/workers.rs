@@ -1,25 +1,191 @@
-use crate::{player::Player, production::ProdInstance};
-use json::JsonValue;
+use crate::{
+    error::{EngineError, EngineResult},
+    money::Money,
+    player::Player,
+    production::ProdInstance,
+};
+
+/// What a worker earns for a shift.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Wage {
+    /// A flat amount per shift worked.
+    PerShift(Money),
+    /// An amount per unit of the recipe's main output.
+    PerUnit(Money),
+}
+
+impl Wage {
+    /// Pay for one shift that turned out `units` of the main output.
+    pub fn for_shift(self, units: u32) -> EngineResult<Money> {
+        match self {
+            Wage::PerShift(amount) => Ok(amount),
+            Wage::PerUnit(amount) => amount.try_mul(units),
+        }
+    }
+}
+
+/// Terms a player is hired on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Contract {
+    pub wage: Wage,
+    /// Cycles the contract runs for, or `None` until notice is given.
+    pub duration: Option<u32>,
+    /// Cycles between giving notice and the employment ending.
+    pub notice: u32,
+}
+
+/// A player hired by a company.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HumanWorker {
+    pub player: u32,
+    /// Whether they have done their shift this cycle.
+    pub worked: bool,
+    pub contract: Contract,
+    /// Cycle the contract started.
+    pub hired_at: u32,
+    /// Cycle the employment ends, once it is known.
+    pub ends_at: Option<u32>,
+}
+
+impl HumanWorker {
+    /// Whether they are still employed, and so may work, in `cycle`.
+    pub fn employed_in(&self, cycle: u32) -> bool {
+        self.ends_at.is_none_or(|ends_at| ends_at > cycle)
+    }
+}
 
 impl ProdInstance {
-    pub fn hire_worker(&mut self, player: &Player) -> Result<(), String> {
-        for entry in self.human_workers.members() {
-            if let Some(pid) = entry[0].as_u32() {
-                if pid == player.id {
-                    return Err(format!("Player {} is already hired here!", pid));
-                }
-            }
+    /// Hires `player` on `contract`, starting in `cycle`, if there is room
+    /// for another worker.
+    pub fn hire_worker(
+        &mut self,
+        player: &Player,
+        contract: Contract,
+        cycle: u32,
+    ) -> EngineResult<()> {
+        if player.id == 0 {
+            return Err(EngineError::PlayerNotFound(player.id));
         }
-        let new_entry = JsonValue::Array(vec![player.id.into(), false.into()]);
-        self.human_workers
-            .push(new_entry)
-            .map_err(|e| format!("Failed to add worker: {}", e))?;
+        if self
+            .human_workers
+            .iter()
+            .any(|worker| worker.player == player.id)
+        {
+            return Err(EngineError::AlreadyHired { player: player.id });
+        }
+        if self.human_workers.len() >= self.max_human_workers as usize {
+            return Err(EngineError::NoVacancy {
+                max: self.max_human_workers,
+            });
+        }
+        self.human_workers.push(HumanWorker {
+            player: player.id,
+            worked: false,
+            contract,
+            hired_at: cycle,
+            ends_at: contract
+                .duration
+                .map(|duration| cycle.saturating_add(duration)),
+        });
         Ok(())
     }
 
+    /// Gives `player` notice in `cycle`. They keep working until the notice
+    /// period runs out, or the contract ends if that comes first. Returns
+    /// the cycle their employment ends.
+    pub fn fire_worker(&mut self, player: u32, cycle: u32) -> EngineResult<u32> {
+        let worker = self
+            .human_workers
+            .iter_mut()
+            .find(|worker| worker.player == player)
+            .ok_or(EngineError::NotHired { player })?;
+        let notice_ends = cycle.saturating_add(worker.contract.notice);
+        let ends_at = worker
+            .ends_at
+            .map_or(notice_ends, |ends| ends.min(notice_ends));
+        worker.ends_at = Some(ends_at);
+        Ok(ends_at)
+    }
+
+    /// `player` hands in their notice. Works like [`ProdInstance::fire_worker`];
+    /// the notice period is the same from either side.
+    pub fn quit(&mut self, player: &Player, cycle: u32) -> EngineResult<u32> {
+        self.fire_worker(player.id, cycle)
+    }
+
+    /// Lets go of every worker whose employment has ended by `cycle` and
+    /// returns who left. Wages still owed to them stay owed.
+    pub fn end_contracts(&mut self, cycle: u32) -> Vec<u32> {
+        let mut left = Vec::new();
+        self.human_workers.retain(|worker| {
+            let ended = !worker.employed_in(cycle);
+            if ended {
+                left.push(worker.player);
+            }
+            !ended
+        });
+        left
+    }
+
     pub fn reset_workers(&mut self) {
-        for entry in self.human_workers.members_mut() {
-            entry[1] = false.into();
+        for worker in self.human_workers.iter_mut() {
+            worker.worked = false;
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db::init_memory_db, production::Prod};
+
+    const CONTRACT: Contract = Contract {
+        wage: Wage::PerShift(Money::ZERO),
+        duration: None,
+        notice: 0,
+    };
+
+    fn player(id: u32) -> Player {
+        let mut player = Player::new(format!("worker{}", id));
+        player.id = id;
+        player
+    }
+
+    #[test]
+    fn hiring_stops_at_max_workers() {
+        let conn = init_memory_db().unwrap();
+        let mut owner = Player::create(&conn, "owner").unwrap();
+        owner.earn(Money::from_dollars(100_000)).unwrap();
+        let base = Prod::lookup("grain_farm").unwrap();
+        let mut farm = ProdInstance::new(&conn, base, "Farm".to_string(), &mut owner).unwrap();
+        farm.max_human_workers = 2;
+
+        farm.hire_worker(&player(10), CONTRACT, 0).unwrap();
+        farm.hire_worker(&player(11), CONTRACT, 0).unwrap();
+        assert!(matches!(
+            farm.hire_worker(&player(12), CONTRACT, 0),
+            Err(EngineError::NoVacancy { max: 2 })
+        ));
+
+        // A place frees up once someone's employment has ended.
+        farm.fire_worker(10, 0).unwrap();
+        assert_eq!(farm.end_contracts(0), vec![10]);
+        farm.hire_worker(&player(12), CONTRACT, 0).unwrap();
+        assert_eq!(farm.human_workers.len(), 2);
+    }
+
+    #[test]
+    fn unsaved_players_cannot_be_hired() {
+        let conn = init_memory_db().unwrap();
+        let mut owner = Player::create(&conn, "owner").unwrap();
+        owner.earn(Money::from_dollars(100_000)).unwrap();
+        let base = Prod::lookup("grain_farm").unwrap();
+        let mut farm = ProdInstance::new(&conn, base, "Farm".to_string(), &mut owner).unwrap();
+
+        assert!(matches!(
+            farm.hire_worker(&Player::new("drifter".to_string()), CONTRACT, 0),
+            Err(EngineError::PlayerNotFound(0))
+        ));
+        assert!(farm.human_workers.is_empty());
+    }
+}